@@ -0,0 +1,403 @@
+//! Lowering of an ungrammar into the shape of a typed AST.
+//!
+//! A node whose rule only chooses between other nodes becomes an enum, every
+//! other node a struct with one field per label or unlabeled node reference:
+//!
+//! ```text
+//! Path        = AbsolutePath | RelativePath      // enum Path
+//! QueryParams = '?' KvParam ('&' KvParam)*       // kv_params: AstChildren<KvParam>
+//! ResourceUrl = (scheme:Scheme '://')? path:Path // scheme: Option<Scheme>, path: Path
+//! ```
+//!
+//! Nodes with a `%precedence` declaration keep its levels, for the code that
+//! parses their operators.
+mod render;
+
+use ungrammar_fork::{Grammar, Node, Rule};
+
+pub use ungrammar_fork::Assoc;
+
+pub use render::render;
+
+/// The AST of a whole grammar.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AstSrc {
+    pub nodes: Vec<AstNodeSrc>,
+    pub enums: Vec<AstEnumSrc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AstNodeSrc {
+    pub name: String,
+    pub fields: Vec<Field>,
+    /// For each labeled rule and unlabeled node reference of the node's
+    /// rule, in order, the index of the field it contributes to.
+    pub occurrences: Vec<usize>,
+    /// The precedence levels of the node's operators, from loosest to
+    /// tightest binding. Empty without a declaration.
+    pub precedence: Vec<PrecedenceLevelSrc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrecedenceLevelSrc {
+    pub assoc: Assoc,
+    /// The operators of the level, like `"+"`.
+    pub tokens: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AstEnumSrc {
+    pub name: String,
+    pub variants: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Cardinality {
+    One,
+    Optional,
+    Many,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    /// A labeled token, like `op:('+' | '-')`.
+    Token {
+        name: String,
+        cardinality: Cardinality,
+    },
+    /// `ty` is `SyntaxNode` for labels over several kinds of nodes.
+    Node {
+        name: String,
+        ty: String,
+        cardinality: Cardinality,
+    },
+}
+
+impl Field {
+    pub fn name(&self) -> &str {
+        match self {
+            Field::Token { name, .. } | Field::Node { name, .. } => name,
+        }
+    }
+
+    pub fn cardinality(&self) -> Cardinality {
+        match self {
+            Field::Token { cardinality, .. } | Field::Node { cardinality, .. } => *cardinality,
+        }
+    }
+
+    /// The Rust type of the field, like `Option<Path>`.
+    pub fn ty(&self) -> String {
+        match self {
+            Field::Token { cardinality, .. } => match cardinality {
+                Cardinality::One => "SyntaxToken".to_string(),
+                Cardinality::Optional => "Option<SyntaxToken>".to_string(),
+                Cardinality::Many => "Vec<SyntaxToken>".to_string(),
+            },
+            Field::Node {
+                ty, cardinality, ..
+            } => match cardinality {
+                Cardinality::One => ty.clone(),
+                Cardinality::Optional => format!("Option<{ty}>"),
+                Cardinality::Many => format!("AstChildren<{ty}>"),
+            },
+        }
+    }
+}
+
+pub fn lower(grammar: &Grammar) -> AstSrc {
+    let mut res = AstSrc::default();
+    for node in grammar.iter() {
+        match lower_node(grammar, node) {
+            Lowered::Enum(it) => res.enums.push(it),
+            Lowered::Node(it) => res.nodes.push(it),
+        }
+    }
+    res
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lowered {
+    Enum(AstEnumSrc),
+    Node(AstNodeSrc),
+}
+
+pub fn lower_node(grammar: &Grammar, node: Node) -> Lowered {
+    let data = &grammar[node];
+    if let Some(variants) = enum_variants(grammar, &data.rule) {
+        return Lowered::Enum(AstEnumSrc {
+            name: data.name.clone(),
+            variants,
+        });
+    }
+    let mut acc = Acc {
+        grammar,
+        fields: Vec::new(),
+        occurrences: Vec::new(),
+    };
+    acc.rule(&data.rule, Cardinality::One);
+    // Unlabeled lists are named after what they contain, in plural.
+    let labeled: Vec<_> = labels(&data.rule);
+    for field in &mut acc.fields {
+        if let Field::Node {
+            name, cardinality, ..
+        } = field
+        {
+            if *cardinality == Cardinality::Many && !labeled.contains(name) {
+                name.push('s');
+            }
+        }
+    }
+    let precedence = grammar
+        .precedence(node)
+        .map(|it| &it.levels[..])
+        .unwrap_or_default()
+        .iter()
+        .map(|level| PrecedenceLevelSrc {
+            assoc: level.assoc,
+            tokens: level
+                .tokens
+                .iter()
+                .map(|&it| grammar[it].name.clone())
+                .collect(),
+        })
+        .collect();
+    Lowered::Node(AstNodeSrc {
+        name: data.name.clone(),
+        fields: acc.fields,
+        occurrences: acc.occurrences,
+        precedence,
+    })
+}
+
+fn enum_variants(grammar: &Grammar, rule: &Rule) -> Option<Vec<String>> {
+    let Rule::Alt(arms) = rule else {
+        return None;
+    };
+    arms.iter()
+        .map(|arm| match arm {
+            Rule::Node(node) => Some(grammar[*node].name.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn labels(rule: &Rule) -> Vec<String> {
+    match rule {
+        Rule::Labeled { label, .. } => vec![label.clone()],
+        Rule::Node(_) | Rule::Token(_) => Vec::new(),
+        Rule::Seq(rules) | Rule::Alt(rules) => rules.iter().flat_map(labels).collect(),
+        Rule::Opt(rule) | Rule::Rep(rule) => labels(rule),
+    }
+}
+
+struct Acc<'a> {
+    grammar: &'a Grammar,
+    fields: Vec<Field>,
+    occurrences: Vec<usize>,
+}
+
+impl Acc<'_> {
+    fn rule(&mut self, rule: &Rule, cardinality: Cardinality) {
+        match rule {
+            Rule::Labeled { label, rule } => {
+                let cardinality = cardinality.max(inner_cardinality(rule));
+                let mut nodes = Vec::new();
+                collect_nodes(self.grammar, rule, &mut nodes);
+                let field = match &nodes[..] {
+                    [] => Field::Token {
+                        name: label.clone(),
+                        cardinality,
+                    },
+                    [ty] => Field::Node {
+                        name: label.clone(),
+                        ty: ty.clone(),
+                        cardinality,
+                    },
+                    _ => Field::Node {
+                        name: label.clone(),
+                        ty: "SyntaxNode".to_string(),
+                        cardinality,
+                    },
+                };
+                self.push(field);
+            }
+            Rule::Node(node) => {
+                let ty = self.grammar[*node].name.clone();
+                self.push(Field::Node {
+                    name: to_snake_case(&ty),
+                    ty,
+                    cardinality,
+                });
+            }
+            Rule::Token(_) => (),
+            Rule::Seq(rules) => rules.iter().for_each(|it| self.rule(it, cardinality)),
+            Rule::Alt(rules) => {
+                let cardinality = cardinality.max(Cardinality::Optional);
+                rules.iter().for_each(|it| self.rule(it, cardinality));
+            }
+            Rule::Opt(rule) => self.rule(rule, cardinality.max(Cardinality::Optional)),
+            Rule::Rep(rule) => self.rule(rule, Cardinality::Many),
+        }
+    }
+
+    /// Adds a field, merging it with an earlier one of the same name: a
+    /// name that appears twice is a list.
+    fn push(&mut self, field: Field) {
+        let idx = match self.fields.iter().position(|it| it.name() == field.name()) {
+            Some(idx) => {
+                match &mut self.fields[idx] {
+                    Field::Token { cardinality, .. } | Field::Node { cardinality, .. } => {
+                        *cardinality = Cardinality::Many
+                    }
+                }
+                idx
+            }
+            None => {
+                self.fields.push(field);
+                self.fields.len() - 1
+            }
+        };
+        self.occurrences.push(idx);
+    }
+}
+
+/// How many times a labeled rule can match.
+fn inner_cardinality(rule: &Rule) -> Cardinality {
+    match rule {
+        Rule::Labeled { rule, .. } => inner_cardinality(rule),
+        Rule::Node(_) | Rule::Token(_) => Cardinality::One,
+        Rule::Seq(rules) => rules
+            .iter()
+            .map(inner_cardinality)
+            .max()
+            .unwrap_or(Cardinality::One),
+        Rule::Alt(rules) => rules
+            .iter()
+            .map(inner_cardinality)
+            .max()
+            .unwrap_or(Cardinality::One),
+        Rule::Opt(rule) => inner_cardinality(rule).max(Cardinality::Optional),
+        Rule::Rep(_) => Cardinality::Many,
+    }
+}
+
+fn collect_nodes(grammar: &Grammar, rule: &Rule, acc: &mut Vec<String>) {
+    match rule {
+        Rule::Node(node) => {
+            let name = &grammar[*node].name;
+            if !acc.contains(name) {
+                acc.push(name.clone());
+            }
+        }
+        Rule::Token(_) => (),
+        Rule::Labeled { rule, .. } | Rule::Opt(rule) | Rule::Rep(rule) => {
+            collect_nodes(grammar, rule, acc)
+        }
+        Rule::Seq(rules) | Rule::Alt(rules) => {
+            rules.iter().for_each(|it| collect_nodes(grammar, it, acc))
+        }
+    }
+}
+
+pub fn to_snake_case(name: &str) -> String {
+    let mut res = String::with_capacity(name.len());
+    for (idx, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if idx > 0 {
+                res.push('_');
+            }
+            res.push(c.to_ascii_lowercase());
+        } else {
+            res.push(c);
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(grammar: &str, node: &str) -> Vec<(String, String)> {
+        let grammar: Grammar = grammar.parse().unwrap();
+        let node = grammar.iter().find(|it| grammar[*it].name == node).unwrap();
+        let Lowered::Node(node) = lower_node(&grammar, node) else {
+            panic!("{node:?} is an enum");
+        };
+        node.fields
+            .iter()
+            .map(|it| (it.name().to_string(), it.ty()))
+            .collect()
+    }
+
+    #[test]
+    fn infers_cardinality() {
+        let grammar = include_str!("../../markup_ungrams/zork_keg.ungram");
+        assert_eq!(
+            fields(grammar, "ResourceUrl"),
+            [
+                (
+                    "scheme".to_string(),
+                    "Option<ResourceTransferScheme>".to_string()
+                ),
+                ("path".to_string(), "Path".to_string()),
+                ("query".to_string(), "QueryParams".to_string()),
+            ]
+        );
+        assert_eq!(
+            fields(grammar, "QueryParams"),
+            [("kv_params".to_string(), "AstChildren<KvParam>".to_string())]
+        );
+        assert_eq!(
+            fields(grammar, "HeaderBlock"),
+            [
+                ("depth".to_string(), "Vec<SyntaxToken>".to_string()),
+                (
+                    "token_sum_tys".to_string(),
+                    "AstChildren<TokenSumTy>".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn lowers_choices_of_nodes_to_enums() {
+        let grammar: Grammar = include_str!("../../markup_ungrams/zork_keg.ungram")
+            .parse()
+            .unwrap();
+        let ast = lower(&grammar);
+        let path = ast.enums.iter().find(|it| it.name == "Path").unwrap();
+        assert_eq!(path.variants, ["AbsolutePath", "RelativePath"]);
+        assert!(ast.nodes.iter().all(|it| it.name != "Path"));
+    }
+
+    #[test]
+    fn records_where_fields_come_from() {
+        let grammar: Grammar = "A = B x:C? (B | y:C)*\nB = 'b'\nC = 'c'".parse().unwrap();
+        let node = grammar.iter().find(|it| grammar[*it].name == "A").unwrap();
+        let Lowered::Node(node) = lower_node(&grammar, node) else {
+            panic!();
+        };
+        let fields: Vec<_> = node.fields.iter().map(|it| it.ty()).collect();
+        assert_eq!(fields, ["AstChildren<B>", "Option<C>", "AstChildren<C>"]);
+        assert_eq!(node.occurrences, [0, 1, 0, 2]);
+    }
+
+    #[test]
+    fn keeps_precedence_levels() {
+        let grammar: Grammar = include_str!("../../ungrammar_fork/rust.ungram")
+            .parse()
+            .unwrap();
+        let ast = lower(&grammar);
+        let bin_expr = ast.nodes.iter().find(|it| it.name == "BinExpr").unwrap();
+        let first = &bin_expr.precedence[0];
+        assert_eq!(first.assoc, Assoc::Right);
+        assert_eq!(first.tokens[..2], ["=", "+="]);
+        let last = bin_expr.precedence.last().unwrap();
+        assert_eq!(last.assoc, Assoc::Left);
+        assert_eq!(last.tokens, ["*", "/", "%"]);
+        let path = ast.nodes.iter().find(|it| it.name == "Path").unwrap();
+        assert!(path.precedence.is_empty());
+    }
+}
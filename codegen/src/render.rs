@@ -0,0 +1,193 @@
+//! Rust source for an [`AstSrc`]: a struct with typed accessors for every
+//! node, an enum for every choice of nodes, and their `AstNode` impls.
+use std::fmt::Write;
+
+use crate::{to_snake_case, AstEnumSrc, AstNodeSrc, AstSrc, Cardinality, Field};
+
+pub fn render(ast: &AstSrc) -> String {
+    let mut res = String::new();
+    res.push_str(
+        "//! Generated by `codegen` from the grammar, do not edit.\n\
+         \n\
+         use crate::{\n    \
+         ast::{support, AstChildren, AstNode},\n    \
+         SyntaxKind::{self, *},\n    \
+         SyntaxNode, SyntaxToken,\n\
+         };\n",
+    );
+    for node in &ast.nodes {
+        render_node(&mut res, node);
+    }
+    for enum_ in &ast.enums {
+        render_enum(&mut res, enum_);
+    }
+    res
+}
+
+fn render_node(res: &mut String, node: &AstNodeSrc) {
+    let name = &node.name;
+    writeln!(res).unwrap();
+    writeln!(res, "#[derive(Debug, Clone, PartialEq, Eq, Hash)]").unwrap();
+    writeln!(res, "pub struct {name} {{").unwrap();
+    writeln!(res, "    pub(crate) syntax: SyntaxNode,").unwrap();
+    writeln!(res, "}}").unwrap();
+    if !node.fields.is_empty() {
+        writeln!(res).unwrap();
+        writeln!(res, "impl {name} {{").unwrap();
+        for field in &node.fields {
+            let body = match (field, field.cardinality()) {
+                (Field::Node { .. }, Cardinality::One) => {
+                    "support::child(&self.syntax).unwrap()".to_string()
+                }
+                (Field::Node { .. }, Cardinality::Optional) => {
+                    "support::child(&self.syntax)".to_string()
+                }
+                (Field::Node { .. }, Cardinality::Many) => {
+                    "support::children(&self.syntax)".to_string()
+                }
+                // Tokens are found by their label.
+                (Field::Token { name, .. }, Cardinality::One) => {
+                    format!("support::token(&self.syntax, {name:?}).unwrap()")
+                }
+                (Field::Token { name, .. }, Cardinality::Optional) => {
+                    format!("support::token(&self.syntax, {name:?})")
+                }
+                (Field::Token { name, .. }, Cardinality::Many) => {
+                    format!("support::tokens(&self.syntax, {name:?})")
+                }
+            };
+            let fn_name = ident(field.name());
+            writeln!(res, "    pub fn {fn_name}(&self) -> {} {{", field.ty()).unwrap();
+            writeln!(res, "        {body}").unwrap();
+            writeln!(res, "    }}").unwrap();
+        }
+        writeln!(res, "}}").unwrap();
+    }
+    let kind = to_snake_case(name).to_uppercase();
+    writeln!(res).unwrap();
+    writeln!(res, "impl AstNode for {name} {{").unwrap();
+    writeln!(res, "    fn can_cast(kind: SyntaxKind) -> bool {{").unwrap();
+    writeln!(res, "        kind == {kind}").unwrap();
+    writeln!(res, "    }}").unwrap();
+    writeln!(res, "    fn cast(syntax: SyntaxNode) -> Option<Self> {{").unwrap();
+    writeln!(
+        res,
+        "        Self::can_cast(syntax.kind()).then_some(Self {{ syntax }})"
+    )
+    .unwrap();
+    writeln!(res, "    }}").unwrap();
+    writeln!(res, "    fn syntax(&self) -> &SyntaxNode {{").unwrap();
+    writeln!(res, "        &self.syntax").unwrap();
+    writeln!(res, "    }}").unwrap();
+    writeln!(res, "}}").unwrap();
+}
+
+/// Variants may be enums themselves, so casts go through their `AstNode`
+/// impls rather than matching on kinds.
+fn render_enum(res: &mut String, enum_: &AstEnumSrc) {
+    let name = &enum_.name;
+    writeln!(res).unwrap();
+    writeln!(res, "#[derive(Debug, Clone, PartialEq, Eq, Hash)]").unwrap();
+    writeln!(res, "pub enum {name} {{").unwrap();
+    for variant in &enum_.variants {
+        writeln!(res, "    {variant}({variant}),").unwrap();
+    }
+    writeln!(res, "}}").unwrap();
+    writeln!(res).unwrap();
+    writeln!(res, "impl AstNode for {name} {{").unwrap();
+    writeln!(res, "    fn can_cast(kind: SyntaxKind) -> bool {{").unwrap();
+    let can_cast: Vec<_> = enum_
+        .variants
+        .iter()
+        .map(|it| format!("{it}::can_cast(kind)"))
+        .collect();
+    writeln!(res, "        {}", can_cast.join("\n            || ")).unwrap();
+    writeln!(res, "    }}").unwrap();
+    writeln!(res, "    fn cast(syntax: SyntaxNode) -> Option<Self> {{").unwrap();
+    for (idx, variant) in enum_.variants.iter().enumerate() {
+        let cast = format!("{variant}::cast(syntax.clone()).map({name}::{variant})");
+        match idx {
+            0 => writeln!(res, "        {cast}").unwrap(),
+            _ => writeln!(res, "            .or_else(|| {cast})").unwrap(),
+        }
+    }
+    writeln!(res, "    }}").unwrap();
+    writeln!(res, "    fn syntax(&self) -> &SyntaxNode {{").unwrap();
+    writeln!(res, "        match self {{").unwrap();
+    for variant in &enum_.variants {
+        writeln!(res, "            {name}::{variant}(it) => it.syntax(),").unwrap();
+    }
+    writeln!(res, "        }}").unwrap();
+    writeln!(res, "    }}").unwrap();
+    writeln!(res, "}}").unwrap();
+}
+
+/// Labels that are Rust keywords become raw identifiers.
+fn ident(name: &str) -> String {
+    const KEYWORDS: &[&str] = &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+        "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+        "mut", "pub", "ref", "return", "static", "struct", "super", "trait", "true", "type",
+        "unsafe", "use", "where", "while",
+    ];
+    match KEYWORDS.contains(&name) {
+        true => format!("r#{name}"),
+        false => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ungrammar_fork::Grammar;
+
+    use super::*;
+    use crate::lower;
+
+    #[test]
+    fn renders_structs_and_enums() {
+        let grammar: Grammar =
+            "Expr = Lit | Bin\nBin = lhs:Expr op:('+' | '-') type:Lit?\nLit = 'lit'"
+                .parse()
+                .unwrap();
+        let source = render(&lower(&grammar));
+        assert!(source.contains(
+            "impl Bin {
+    pub fn lhs(&self) -> Expr {
+        support::child(&self.syntax).unwrap()
+    }
+    pub fn op(&self) -> SyntaxToken {
+        support::token(&self.syntax, \"op\").unwrap()
+    }
+    pub fn r#type(&self) -> Option<Lit> {
+        support::child(&self.syntax)
+    }
+}"
+        ));
+        assert!(source.contains(
+            "pub struct Lit {\n    pub(crate) syntax: SyntaxNode,\n}\n\nimpl AstNode for Lit {"
+        ));
+        assert!(source.contains(
+            "pub enum Expr {
+    Lit(Lit),
+    Bin(Bin),
+}
+
+impl AstNode for Expr {
+    fn can_cast(kind: SyntaxKind) -> bool {
+        Lit::can_cast(kind)
+            || Bin::can_cast(kind)
+    }
+    fn cast(syntax: SyntaxNode) -> Option<Self> {
+        Lit::cast(syntax.clone()).map(Expr::Lit)
+            .or_else(|| Bin::cast(syntax.clone()).map(Expr::Bin))
+    }
+    fn syntax(&self) -> &SyntaxNode {
+        match self {
+            Expr::Lit(it) => it.syntax(),
+            Expr::Bin(it) => it.syntax(),
+        }
+    }
+}"
+        ));
+    }
+}
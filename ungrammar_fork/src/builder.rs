@@ -0,0 +1,106 @@
+//! Programmatic construction of a [`Grammar`].
+use std::{collections::HashMap, ops};
+
+use crate::{
+    error::{bail, Result},
+    Grammar, Node, NodeData, PrecedenceData, Rule, Token, TokenData,
+};
+
+const DUMMY_RULE: Rule = Rule::Node(Node(!0));
+
+/// Assembles a [`Grammar`] one rule at a time.
+///
+/// This is what the textual parser uses under the hood; it is exposed for
+/// tools that produce rules some other way, like an incremental parser that
+/// only re-reads the rules that changed.
+#[derive(Default, Debug)]
+pub struct GrammarBuilder {
+    grammar: Grammar,
+    node_table: HashMap<String, Node>,
+    token_table: HashMap<String, Token>,
+}
+
+impl GrammarBuilder {
+    /// Creates a builder for an empty grammar.
+    pub fn new() -> GrammarBuilder {
+        GrammarBuilder::default()
+    }
+
+    /// Returns the node called `name`, creating it if this is the first time
+    /// it is mentioned.
+    pub fn node(&mut self, name: &str) -> Node {
+        let len = self.node_table.len();
+        let grammar = &mut self.grammar;
+        *self.node_table.entry(name.to_string()).or_insert_with(|| {
+            grammar.nodes.push(NodeData {
+                name: name.to_string(),
+                rule: DUMMY_RULE,
+            });
+            Node(len)
+        })
+    }
+
+    /// Returns the token called `name`, creating it if this is the first time
+    /// it is mentioned.
+    pub fn token(&mut self, name: &str) -> Token {
+        let len = self.token_table.len();
+        let grammar = &mut self.grammar;
+        *self.token_table.entry(name.to_string()).or_insert_with(|| {
+            grammar.tokens.push(TokenData {
+                name: name.to_string(),
+            });
+            Token(len)
+        })
+    }
+
+    /// Returns `true` if `node` already has a rule.
+    pub fn is_defined(&self, node: Node) -> bool {
+        !matches!(self.grammar[node].rule, DUMMY_RULE)
+    }
+
+    /// Sets the rule of `node`.
+    ///
+    /// Fails if `node` already has a rule.
+    pub fn define(&mut self, node: Node, rule: Rule) -> Result<()> {
+        if self.is_defined(node) {
+            bail!("duplicate rule: `{}`", self.grammar[node].name)
+        }
+        self.grammar.nodes[node.0].rule = rule;
+        Ok(())
+    }
+
+    /// Declares the precedence of the operators of a node.
+    ///
+    /// Fails if the node already has a precedence declaration.
+    pub fn precedence(&mut self, data: PrecedenceData) -> Result<()> {
+        if self.grammar.precedence(data.node).is_some() {
+            bail!(
+                "duplicate precedence declaration: `{}`",
+                self.grammar[data.node].name
+            )
+        }
+        self.grammar.precedences.push(data);
+        Ok(())
+    }
+
+    /// Finishes the grammar.
+    ///
+    /// Fails if some node was mentioned but never given a rule. Precedence
+    /// declarations are not checked against their rules here, see
+    /// [`Grammar::precedence_errors`].
+    pub fn finish(self) -> Result<Grammar> {
+        for node_data in &self.grammar.nodes {
+            if matches!(node_data.rule, DUMMY_RULE) {
+                bail!("Undefined node: {}", node_data.name)
+            }
+        }
+        Ok(self.grammar)
+    }
+}
+
+impl ops::Index<Node> for GrammarBuilder {
+    type Output = NodeData;
+    fn index(&self, node: Node) -> &NodeData {
+        &self.grammar[node]
+    }
+}
@@ -0,0 +1,628 @@
+//! Grammar coverage collection.
+//!
+//! A [`Coverage`] is fed with trees produced by some parser of the grammar
+//! (anything implementing [`CoverageTree`]) and records which nodes were
+//! seen, which arms of each `|` were taken and whether each `?`/`*` was
+//! exercised or skipped. The result can be rendered as an lcov tracefile or
+//! as an HTML page highlighting unused rules in the grammar source.
+use std::{collections::HashMap, fmt::Write};
+
+use crate::{lexer, Grammar, Node, Rule, Token};
+
+/// A tree whose nodes map back to [`Node`]s of a [`Grammar`].
+///
+/// Nodes of "enum-like" rules such as `Expr = Literal | PathExpr` are usually
+/// elided from concrete trees, so a child `Literal` is accepted where the rule
+/// expects an `Expr`; the elided node and its arm are then counted as well.
+pub trait CoverageTree: Sized {
+    /// Returns the grammar node this tree node was produced from.
+    ///
+    /// Tree nodes without a grammar counterpart (error nodes, for example)
+    /// are not counted, but their children are still visited.
+    fn grammar_node(&self) -> Option<Node>;
+
+    /// Returns the significant children of this tree node in source order.
+    ///
+    /// Trivia, like whitespace and comments, should be left out.
+    fn children(&self) -> Vec<Child<Self>>;
+}
+
+/// A child of a [`CoverageTree`] node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Child<T> {
+    /// A nested tree node.
+    Node(T),
+    /// A token, like `'struct'`.
+    Token(Token),
+}
+
+/// A point in a rule where the parser has to choose between several arms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DecisionKind {
+    /// An alternative, like `A | B`. Arms are indexed in source order.
+    Alt,
+    /// An optional rule, like `A?`. Arm `0` is "taken", arm `1` is "skipped".
+    Opt,
+    /// A repeated rule, like `A*`. Arm `0` is "at least once", arm `1` is
+    /// "never".
+    Rep,
+}
+
+/// Hit counts for a single decision point of a rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decision {
+    /// The kind of decision.
+    pub kind: DecisionKind,
+    /// How many times each arm was taken.
+    pub arms: Vec<u64>,
+}
+
+/// Coverage of a [`Grammar`] accumulated over any number of trees.
+///
+/// Decision points are numbered in pre-order within the rule of their node,
+/// so `A = (B | C)? D*` has the `?` as decision `0`, the `|` as decision `1`
+/// and the `*` as decision `2`.
+#[derive(Debug, Default, Clone)]
+pub struct Coverage {
+    nodes: HashMap<Node, u64>,
+    branches: HashMap<(Node, usize, usize), u64>,
+    mismatches: u64,
+}
+
+#[derive(Debug, Clone)]
+enum Event {
+    Node(Node),
+    Branch(Node, usize, usize),
+}
+
+impl Coverage {
+    /// Creates an empty coverage collector.
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    /// Records every node of `tree` against `grammar`.
+    pub fn collect<T: CoverageTree>(&mut self, grammar: &Grammar, tree: &T) {
+        let children = tree.children();
+        if let Some(node) = tree.grammar_node() {
+            *self.nodes.entry(node).or_default() += 1;
+            let matcher = Matcher { grammar, node };
+            let full = matcher
+                .match_rule(&grammar[node].rule, 0, &children, 0)
+                .into_iter()
+                .find(|(end, _)| *end == children.len());
+            match full {
+                Some((_, events)) => self.apply(events),
+                None => self.mismatches += 1,
+            }
+        }
+        for child in &children {
+            if let Child::Node(child) = child {
+                self.collect(grammar, child);
+            }
+        }
+    }
+
+    /// Adds the hits recorded by `other` to this collector.
+    pub fn merge(&mut self, other: &Coverage) {
+        for (node, hits) in &other.nodes {
+            *self.nodes.entry(*node).or_default() += hits;
+        }
+        for (key, hits) in &other.branches {
+            *self.branches.entry(*key).or_default() += hits;
+        }
+        self.mismatches += other.mismatches;
+    }
+
+    /// Returns how many times `node` was seen.
+    pub fn node_hits(&self, node: Node) -> u64 {
+        self.nodes.get(&node).copied().unwrap_or(0)
+    }
+
+    /// Returns the decision points of `node`'s rule with their hit counts.
+    pub fn decisions(&self, grammar: &Grammar, node: Node) -> Vec<Decision> {
+        let mut res = Vec::new();
+        decision_kinds(&grammar[node].rule, &mut res);
+        res.into_iter()
+            .enumerate()
+            .map(|(decision, (kind, arity))| Decision {
+                kind,
+                arms: (0..arity)
+                    .map(|arm| {
+                        let key = (node, decision, arm);
+                        self.branches.get(&key).copied().unwrap_or(0)
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Returns the nodes of `grammar` that were never seen.
+    pub fn unused_nodes<'a>(&'a self, grammar: &'a Grammar) -> impl Iterator<Item = Node> + 'a {
+        grammar
+            .iter()
+            .filter(move |&node| self.node_hits(node) == 0)
+    }
+
+    /// Returns how many trees did not match the rule of their node.
+    ///
+    /// A non-zero value usually means the trees were produced from a
+    /// different version of the grammar.
+    pub fn mismatches(&self) -> u64 {
+        self.mismatches
+    }
+
+    /// Renders the coverage as an lcov tracefile.
+    ///
+    /// Every node is reported as a function, every line of its rule as a
+    /// line and every decision arm as a branch on the node's first line.
+    /// `path` is the path of the grammar file, `source` its contents.
+    pub fn lcov(&self, grammar: &Grammar, path: &str, source: &str) -> String {
+        let spans = rule_spans(grammar, source);
+        let mut buf = String::new();
+        let _ = writeln!(buf, "TN:");
+        let _ = writeln!(buf, "SF:{}", path);
+        for node in grammar.iter() {
+            if let Some(span) = spans.get(&node) {
+                let _ = writeln!(buf, "FN:{},{}", span.start + 1, grammar[node].name);
+            }
+        }
+        let mut hit_fns = 0;
+        for node in grammar.iter() {
+            let hits = self.node_hits(node);
+            hit_fns += (hits > 0) as usize;
+            let _ = writeln!(buf, "FNDA:{},{}", hits, grammar[node].name);
+        }
+        let _ = writeln!(buf, "FNF:{}", grammar.nodes.len());
+        let _ = writeln!(buf, "FNH:{}", hit_fns);
+
+        let (mut found, mut hit) = (0, 0);
+        for node in grammar.iter() {
+            let line = match spans.get(&node) {
+                Some(span) => span.start + 1,
+                None => continue,
+            };
+            for (block, decision) in self.decisions(grammar, node).into_iter().enumerate() {
+                for (branch, taken) in decision.arms.into_iter().enumerate() {
+                    found += 1;
+                    hit += (taken > 0) as usize;
+                    let _ = writeln!(buf, "BRDA:{},{},{},{}", line, block, branch, taken);
+                }
+            }
+        }
+        let _ = writeln!(buf, "BRF:{}", found);
+        let _ = writeln!(buf, "BRH:{}", hit);
+
+        let (mut found, mut hit) = (0, 0);
+        let mut lines: Vec<_> = spans
+            .iter()
+            .flat_map(|(&node, span)| (span.start..=span.end).map(move |line| (line, node)))
+            .collect();
+        lines.sort();
+        for (line, node) in lines {
+            let hits = self.node_hits(node);
+            found += 1;
+            hit += (hits > 0) as usize;
+            let _ = writeln!(buf, "DA:{},{}", line + 1, hits);
+        }
+        let _ = writeln!(buf, "LF:{}", found);
+        let _ = writeln!(buf, "LH:{}", hit);
+        let _ = writeln!(buf, "end_of_record");
+        buf
+    }
+
+    /// Renders `source` as an HTML page, highlighting rules that were never
+    /// used and rules with untaken arms.
+    pub fn html(&self, grammar: &Grammar, source: &str) -> String {
+        let spans = rule_spans(grammar, source);
+        let mut line_class = vec![None; source.lines().count()];
+        let mut unused = 0;
+        for (&node, span) in &spans {
+            let class = if self.node_hits(node) == 0 {
+                unused += 1;
+                "unused"
+            } else if self
+                .decisions(grammar, node)
+                .iter()
+                .any(|it| it.arms.contains(&0))
+            {
+                "partial"
+            } else {
+                "covered"
+            };
+            for line in span.start..=span.end {
+                if let Some(slot) = line_class.get_mut(line) {
+                    *slot = Some((class, node));
+                }
+            }
+        }
+
+        let mut buf = String::new();
+        buf.push_str(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>Grammar coverage</title>\n<style>\n\
+             pre { line-height: 1.4; }\n\
+             .unused { background: #fdd; }\n\
+             .partial { background: #ffd; }\n\
+             .covered { background: #dfd; }\n\
+             </style>\n</head>\n<body>\n",
+        );
+        let _ = writeln!(
+            buf,
+            "<p>{} of {} nodes used</p>",
+            spans.len() - unused,
+            spans.len()
+        );
+        buf.push_str("<pre>");
+        for (text, class) in source.lines().zip(line_class) {
+            match class {
+                Some((class, node)) => {
+                    let _ = writeln!(
+                        buf,
+                        "<span class=\"{}\" title=\"{} hits\">{}</span>",
+                        class,
+                        self.node_hits(node),
+                        escape_html(text)
+                    );
+                }
+                None => {
+                    let _ = writeln!(buf, "{}", escape_html(text));
+                }
+            }
+        }
+        buf.push_str("</pre>\n</body>\n</html>\n");
+        buf
+    }
+
+    fn apply(&mut self, events: Vec<Event>) {
+        for event in events {
+            match event {
+                Event::Node(node) => *self.nodes.entry(node).or_default() += 1,
+                Event::Branch(node, decision, arm) => {
+                    *self.branches.entry((node, decision, arm)).or_default() += 1
+                }
+            }
+        }
+    }
+}
+
+struct Matcher<'a> {
+    grammar: &'a Grammar,
+    node: Node,
+}
+
+type Matches = Vec<(usize, Vec<Event>)>;
+
+impl Matcher<'_> {
+    /// Returns every way `rule` can match a prefix of `children[pos..]`, as
+    /// the end position and the events recorded along the way.
+    ///
+    /// `decision` is the pre-order index of the first decision point in
+    /// `rule`.
+    fn match_rule<T: CoverageTree>(
+        &self,
+        rule: &Rule,
+        decision: usize,
+        children: &[Child<T>],
+        pos: usize,
+    ) -> Matches {
+        match rule {
+            Rule::Labeled { rule, .. } => self.match_rule(rule, decision, children, pos),
+            Rule::Token(token) => match children.get(pos) {
+                Some(Child::Token(it)) if it == token => vec![(pos + 1, Vec::new())],
+                _ => Vec::new(),
+            },
+            Rule::Node(node) => match children.get(pos) {
+                Some(Child::Node(child)) => match child.grammar_node() {
+                    Some(child) => elided_path(self.grammar, *node, child)
+                        .map(|events| vec![(pos + 1, events)])
+                        .unwrap_or_default(),
+                    None => Vec::new(),
+                },
+                _ => Vec::new(),
+            },
+            Rule::Seq(rules) => {
+                let mut res = vec![(pos, Vec::new())];
+                let mut decision = decision;
+                for rule in rules {
+                    let mut next = Vec::new();
+                    for (pos, events) in res {
+                        for (end, more) in self.match_rule(rule, decision, children, pos) {
+                            let mut events = events.clone();
+                            events.extend(more);
+                            next.push((end, events));
+                        }
+                    }
+                    res = next;
+                    decision += count_decisions(rule);
+                }
+                res
+            }
+            Rule::Alt(rules) => {
+                let mut res = Vec::new();
+                let mut next_decision = decision + 1;
+                for (arm, rule) in rules.iter().enumerate() {
+                    for (end, more) in self.match_rule(rule, next_decision, children, pos) {
+                        let mut events = vec![Event::Branch(self.node, decision, arm)];
+                        events.extend(more);
+                        res.push((end, events));
+                    }
+                    next_decision += count_decisions(rule);
+                }
+                res
+            }
+            Rule::Opt(rule) => {
+                let mut res = Vec::new();
+                for (end, more) in self.match_rule(rule, decision + 1, children, pos) {
+                    let mut events = vec![Event::Branch(self.node, decision, 0)];
+                    events.extend(more);
+                    res.push((end, events));
+                }
+                res.push((pos, vec![Event::Branch(self.node, decision, 1)]));
+                res
+            }
+            Rule::Rep(rule) => {
+                let mut res = Vec::new();
+                let mut frontier = vec![(pos, Vec::new())];
+                while !frontier.is_empty() {
+                    let mut next = Vec::new();
+                    for (pos, events) in frontier {
+                        for (end, more) in self.match_rule(rule, decision + 1, children, pos) {
+                            if end == pos {
+                                continue;
+                            }
+                            let mut events = events.clone();
+                            events.extend(more);
+                            next.push((end, events));
+                        }
+                    }
+                    for (end, events) in &next {
+                        let mut all = vec![Event::Branch(self.node, decision, 0)];
+                        all.extend(events.iter().cloned());
+                        res.push((*end, all));
+                    }
+                    frontier = next;
+                }
+                // Prefer the longest repetition, like a greedy parser would.
+                res.reverse();
+                res.push((pos, vec![Event::Branch(self.node, decision, 1)]));
+                res
+            }
+        }
+    }
+}
+
+/// Checks whether a tree node of kind `child` may stand where the grammar
+/// expects `expected`, returning the elided enum-like nodes and arms.
+fn elided_path(grammar: &Grammar, expected: Node, child: Node) -> Option<Vec<Event>> {
+    fn go(
+        grammar: &Grammar,
+        expected: Node,
+        child: Node,
+        seen: &mut Vec<Node>,
+    ) -> Option<Vec<Event>> {
+        if expected == child {
+            return Some(Vec::new());
+        }
+        if seen.contains(&expected) {
+            return None;
+        }
+        seen.push(expected);
+        let arms = match &grammar[expected].rule {
+            Rule::Alt(arms) => arms,
+            _ => return None,
+        };
+        for (arm, rule) in arms.iter().enumerate() {
+            if let Rule::Node(inner) = rule {
+                if let Some(more) = go(grammar, *inner, child, seen) {
+                    let mut events = vec![Event::Node(expected), Event::Branch(expected, 0, arm)];
+                    events.extend(more);
+                    return Some(events);
+                }
+            }
+        }
+        None
+    }
+    go(grammar, expected, child, &mut Vec::new())
+}
+
+fn count_decisions(rule: &Rule) -> usize {
+    match rule {
+        Rule::Labeled { rule, .. } => count_decisions(rule),
+        Rule::Node(_) | Rule::Token(_) => 0,
+        Rule::Seq(rules) => rules.iter().map(count_decisions).sum(),
+        Rule::Alt(rules) => 1 + rules.iter().map(count_decisions).sum::<usize>(),
+        Rule::Opt(rule) | Rule::Rep(rule) => 1 + count_decisions(rule),
+    }
+}
+
+fn decision_kinds(rule: &Rule, acc: &mut Vec<(DecisionKind, usize)>) {
+    match rule {
+        Rule::Labeled { rule, .. } => decision_kinds(rule, acc),
+        Rule::Node(_) | Rule::Token(_) => (),
+        Rule::Seq(rules) => rules.iter().for_each(|it| decision_kinds(it, acc)),
+        Rule::Alt(rules) => {
+            acc.push((DecisionKind::Alt, rules.len()));
+            rules.iter().for_each(|it| decision_kinds(it, acc));
+        }
+        Rule::Opt(rule) => {
+            acc.push((DecisionKind::Opt, 2));
+            decision_kinds(rule, acc);
+        }
+        Rule::Rep(rule) => {
+            acc.push((DecisionKind::Rep, 2));
+            decision_kinds(rule, acc);
+        }
+    }
+}
+
+/// First and last (0-based) line of every rule definition in `source`.
+#[derive(Debug, Clone, Copy)]
+struct LineSpan {
+    start: usize,
+    end: usize,
+}
+
+fn rule_spans(grammar: &Grammar, source: &str) -> HashMap<Node, LineSpan> {
+    let tokens = match lexer::tokenize(source) {
+        Ok(it) => it,
+        Err(_) => return HashMap::new(),
+    };
+    let by_name: HashMap<&str, Node> = grammar
+        .iter()
+        .map(|node| (grammar[node].name.as_str(), node))
+        .collect();
+
+    let mut res = HashMap::new();
+    let mut current: Option<(Node, LineSpan)> = None;
+    for (idx, token) in tokens.iter().enumerate() {
+        let is_definition = matches!(
+            tokens.get(idx + 1),
+            Some(lexer::Token {
+                kind: lexer::TokenKind::Eq,
+                ..
+            })
+        );
+        match &token.kind {
+            lexer::TokenKind::Node(name) if is_definition => {
+                res.extend(current.take());
+                current = by_name.get(name.as_str()).map(|&node| {
+                    let line = token.loc.line;
+                    (
+                        node,
+                        LineSpan {
+                            start: line,
+                            end: line,
+                        },
+                    )
+                });
+            }
+            _ => {
+                if let Some((_, span)) = &mut current {
+                    span.end = token.loc.line;
+                }
+            }
+        }
+    }
+    res.extend(current);
+    res
+}
+
+fn escape_html(text: &str) -> String {
+    let mut buf = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => buf.push_str("&lt;"),
+            '>' => buf.push_str("&gt;"),
+            '&' => buf.push_str("&amp;"),
+            '"' => buf.push_str("&quot;"),
+            c => buf.push(c),
+        }
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRAMMAR: &str = "\
+Expr = Literal | PathExpr | ParenExpr
+Literal = 'int'
+PathExpr = 'ident' ('::' 'ident')*
+ParenExpr = '(' Expr ')' 'unused'?
+Unused = 'x'
+";
+
+    #[derive(Debug, Clone)]
+    enum Tree {
+        Node(Node, Vec<Tree>),
+        Token(Token),
+    }
+
+    impl CoverageTree for Tree {
+        fn grammar_node(&self) -> Option<Node> {
+            match self {
+                Tree::Node(node, _) => Some(*node),
+                Tree::Token(_) => None,
+            }
+        }
+
+        fn children(&self) -> Vec<Child<Self>> {
+            match self {
+                Tree::Node(_, children) => children
+                    .iter()
+                    .map(|it| match it {
+                        Tree::Token(token) => Child::Token(*token),
+                        node => Child::Node(node.clone()),
+                    })
+                    .collect(),
+                Tree::Token(_) => Vec::new(),
+            }
+        }
+    }
+
+    fn find_node(grammar: &Grammar, name: &str) -> Node {
+        grammar.iter().find(|&it| grammar[it].name == name).unwrap()
+    }
+
+    fn find_token(grammar: &Grammar, name: &str) -> Token {
+        grammar
+            .tokens()
+            .find(|&it| grammar[it].name == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn collects_nodes_and_arms() {
+        let grammar: Grammar = GRAMMAR.parse().unwrap();
+        let n = |name| find_node(&grammar, name);
+        let t = |name| Tree::Token(find_token(&grammar, name));
+
+        // `( a )`, with `Expr` elided in favour of `PathExpr`
+        let tree = Tree::Node(
+            n("ParenExpr"),
+            vec![t("("), Tree::Node(n("PathExpr"), vec![t("ident")]), t(")")],
+        );
+        let mut coverage = Coverage::new();
+        coverage.collect(&grammar, &tree);
+
+        assert_eq!(coverage.mismatches(), 0);
+        assert_eq!(coverage.node_hits(n("Expr")), 1);
+        assert_eq!(coverage.node_hits(n("Literal")), 0);
+        let unused: Vec<_> = coverage
+            .unused_nodes(&grammar)
+            .map(|it| grammar[it].name.as_str())
+            .collect();
+        assert_eq!(unused, ["Literal", "Unused"]);
+
+        let expr = coverage.decisions(&grammar, n("Expr"));
+        assert_eq!(expr[0].arms, [0, 1, 0]);
+        let path = coverage.decisions(&grammar, n("PathExpr"));
+        assert_eq!(path[0].kind, DecisionKind::Rep);
+        assert_eq!(path[0].arms, [0, 1]);
+        let paren = coverage.decisions(&grammar, n("ParenExpr"));
+        assert_eq!(paren[0].arms, [0, 1]);
+    }
+
+    #[test]
+    fn renders_lcov_and_html() {
+        let grammar: Grammar = GRAMMAR.parse().unwrap();
+        let literal = find_node(&grammar, "Literal");
+        let tree = Tree::Node(literal, vec![Tree::Token(find_token(&grammar, "int"))]);
+        let mut coverage = Coverage::new();
+        coverage.collect(&grammar, &tree);
+
+        let lcov = coverage.lcov(&grammar, "test.ungram", GRAMMAR);
+        assert!(lcov.contains("FN:2,Literal\n"));
+        assert!(lcov.contains("FNDA:1,Literal\n"));
+        assert!(lcov.contains("FNDA:0,Unused\n"));
+        assert!(lcov.contains("FNH:1\n"));
+        assert!(lcov.contains("DA:5,0\n"));
+        assert!(lcov.ends_with("end_of_record\n"));
+
+        let html = coverage.html(&grammar, GRAMMAR);
+        assert!(html.contains("<span class=\"covered\" title=\"1 hits\">Literal = 'int'</span>"));
+        assert!(html.contains("<span class=\"unused\" title=\"0 hits\">Unused = 'x'</span>"));
+    }
+}
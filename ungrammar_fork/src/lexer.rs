@@ -12,6 +12,7 @@ pub(crate) enum TokenKind {
     Colon,
     LParen,
     RParen,
+    Directive(String),
 }
 
 #[derive(Debug)]
@@ -99,6 +100,17 @@ fn advance(input: &mut &str) -> Result<TokenKind> {
             }
             TokenKind::Token(buf)
         }
+        '%' => {
+            let mut buf = String::new();
+            while let Some(c) = chars.clone().next().filter(|&c| is_ident_char(c)) {
+                chars.next();
+                buf.push(c);
+            }
+            if buf.is_empty() {
+                bail!("expected a directive name after `%`")
+            }
+            TokenKind::Directive(buf)
+        }
         c if is_ident_char(c) => {
             let mut buf = String::new();
             buf.push(c);
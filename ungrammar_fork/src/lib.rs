@@ -11,13 +11,18 @@
 #![deny(missing_docs)]
 #![deny(rust_2018_idioms)]
 
+mod builder;
+pub mod coverage;
 mod error;
 pub mod lexer;
 mod parser;
+mod precedence;
 
 use std::{ops, str::FromStr};
 
+pub use builder::GrammarBuilder;
 pub use error::{Error, Result};
+pub use precedence::{PrecedenceError, PrecedenceErrorKind};
 
 /// Returns a Rust grammar.
 pub fn rust_grammar() -> Grammar {
@@ -40,10 +45,11 @@ pub struct Node(usize);
 pub struct Token(usize);
 
 /// An Ungrammar grammar.
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct Grammar {
     nodes: Vec<NodeData>,
     tokens: Vec<TokenData>,
+    precedences: Vec<PrecedenceData>,
 }
 
 impl FromStr for Grammar {
@@ -64,6 +70,29 @@ impl Grammar {
     pub fn tokens(&self) -> impl Iterator<Item = Token> + '_ {
         (0..self.tokens.len()).map(Token)
     }
+
+    /// Returns an iterator over all precedence declarations, in source order.
+    pub fn precedences(&self) -> impl Iterator<Item = &PrecedenceData> + '_ {
+        self.precedences.iter()
+    }
+
+    /// Returns the precedence declaration of `node`, if it has one.
+    pub fn precedence(&self, node: Node) -> Option<&PrecedenceData> {
+        self.precedences.iter().find(|it| it.node == node)
+    }
+
+    /// Returns the operators of `node`: the tokens that make up a whole
+    /// alternative of a choice within a sequence, like `'+'` in
+    /// `lhs:Expr op:('+' | '-') rhs:Expr`.
+    pub fn operators(&self, node: Node) -> Vec<Token> {
+        precedence::operators(&self[node].rule)
+    }
+
+    /// Checks that every precedence declaration covers exactly the operators
+    /// of its node, each at a single level.
+    pub fn precedence_errors(&self) -> Vec<PrecedenceError> {
+        precedence::check(self)
+    }
 }
 
 impl ops::Index<Node> for Grammar {
@@ -81,7 +110,7 @@ impl ops::Index<Token> for Grammar {
 }
 
 /// Data about a node.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct NodeData {
     /// The name of the node.
     ///
@@ -94,14 +123,63 @@ pub struct NodeData {
 }
 
 /// Data about a token.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TokenData {
     /// The name of the token.
     pub name: String,
 }
 
+/// Precedence and associativity of the operators of a node.
+///
+/// Declared after the rule, from the loosest-binding level to the tightest:
+///
+/// ```text
+/// %precedence BinExpr
+///   %right '='
+///   %left '+' '-'
+///   %left '*' '/'
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrecedenceData {
+    /// The node whose operators are described.
+    pub node: Node,
+    /// The levels, from loosest to tightest binding.
+    pub levels: Vec<PrecedenceLevel>,
+}
+
+impl PrecedenceData {
+    /// Returns the level of `token`, `0` being the loosest, together with its
+    /// associativity.
+    pub fn level(&self, token: Token) -> Option<(usize, Assoc)> {
+        self.levels
+            .iter()
+            .position(|it| it.tokens.contains(&token))
+            .map(|idx| (idx, self.levels[idx].assoc))
+    }
+}
+
+/// A single level of a [`PrecedenceData`], like `%left '+' '-'`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrecedenceLevel {
+    /// How operators of this level group with each other.
+    pub assoc: Assoc,
+    /// The operators of this level.
+    pub tokens: Vec<Token>,
+}
+
+/// Associativity of an operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Assoc {
+    /// `a - b - c` is `(a - b) - c`, declared with `%left`.
+    Left,
+    /// `a = b = c` is `a = (b = c)`, declared with `%right`.
+    Right,
+    /// `a == b == c` is an error, declared with `%nonassoc`.
+    NonAssoc,
+}
+
 /// A production rule.
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Rule {
     /// A labeled rule, like `a:B` (`"a"` is the label, `B` is the rule).
     Labeled {
@@ -133,5 +211,35 @@ fn smoke() {
 
 #[test]
 fn test_rust_grammar() {
-    let _ = rust_grammar();
+    let grammar = rust_grammar();
+    assert!(grammar.precedences().next().is_some());
+}
+
+#[test]
+fn test_precedence() {
+    let src = "
+        Expr = lhs:Atom op:('+' | '*' | '=') rhs:Atom
+        Atom = 'int'
+        %precedence Expr
+          %right '='
+          %left '+'
+          %left '*'
+    ";
+    let grammar: Grammar = src.parse().unwrap();
+    let expr = grammar.iter().next().unwrap();
+    let precedence = grammar.precedence(expr).unwrap();
+    let level = |name: &str| {
+        let token = grammar.tokens().find(|&it| grammar[it].name == name);
+        precedence.level(token.unwrap())
+    };
+    assert_eq!(level("="), Some((0, Assoc::Right)));
+    assert_eq!(level("*"), Some((2, Assoc::Left)));
+    assert_eq!(level("int"), None);
+
+    let missing = src.replace("%left '*'", "");
+    let err = missing.parse::<Grammar>().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "operator '*' of `Expr` has no precedence level"
+    );
 }
@@ -1,10 +1,8 @@
 //! Simple hand-written ungrammar parser.
-use std::collections::HashMap;
-
 use crate::{
     error::{bail, format_err, Result},
     lexer::{self, TokenKind},
-    Grammar, Node, NodeData, Rule, Token, TokenData,
+    Assoc, Grammar, GrammarBuilder, PrecedenceData, PrecedenceLevel, Rule,
 };
 
 macro_rules! bail {
@@ -18,21 +16,23 @@ macro_rules! bail {
 pub(crate) fn parse(tokens: Vec<lexer::Token>) -> Result<Grammar> {
     let mut p = Parser::new(tokens);
     while !p.is_eof() {
-        node(&mut p)?;
+        match p.peek() {
+            Some(lexer::Token {
+                kind: TokenKind::Directive(_),
+                ..
+            }) => precedence(&mut p)?,
+            _ => node(&mut p)?,
+        }
     }
     p.finish()
 }
 
 #[derive(Default)]
 struct Parser {
-    grammar: Grammar,
+    builder: GrammarBuilder,
     tokens: Vec<lexer::Token>,
-    node_table: HashMap<String, Node>,
-    token_table: HashMap<String, Token>,
 }
 
-const DUMMY_RULE: Rule = Rule::Node(Node(!0));
-
 impl Parser {
     fn new(mut tokens: Vec<lexer::Token>) -> Parser {
         tokens.reverse();
@@ -64,48 +64,82 @@ impl Parser {
         self.tokens.is_empty()
     }
     fn finish(self) -> Result<Grammar> {
-        for node_data in &self.grammar.nodes {
-            if matches!(node_data.rule, DUMMY_RULE) {
-                crate::error::bail!("Undefined node: {}", node_data.name)
-            }
+        let grammar = self.builder.finish()?;
+        if let Some(err) = grammar.precedence_errors().into_iter().next() {
+            // These errors have no location for the `bail!` of this file.
+            self::bail!("{}", err);
         }
-        Ok(self.grammar)
-    }
-    fn intern_node(&mut self, name: String) -> Node {
-        let len = self.node_table.len();
-        let grammar = &mut self.grammar;
-        *self.node_table.entry(name.clone()).or_insert_with(|| {
-            grammar.nodes.push(NodeData {
-                name,
-                rule: DUMMY_RULE,
-            });
-            Node(len)
-        })
-    }
-    fn intern_token(&mut self, name: String) -> Token {
-        let len = self.token_table.len();
-        let grammar = &mut self.grammar;
-        *self.token_table.entry(name.clone()).or_insert_with(|| {
-            grammar.tokens.push(TokenData { name });
-            Token(len)
-        })
+        Ok(grammar)
     }
 }
 
 fn node(p: &mut Parser) -> Result<()> {
     let token = p.bump()?;
     let node = match token.kind {
-        TokenKind::Node(it) => p.intern_node(it),
+        TokenKind::Node(it) => p.builder.node(&it),
         _ => bail!(token.loc, "expected ident"),
     };
     p.expect(TokenKind::Eq, "=")?;
-    if !matches!(p.grammar[node].rule, DUMMY_RULE) {
-        bail!(token.loc, "duplicate rule: `{}`", p.grammar[node].name)
+    if p.builder.is_defined(node) {
+        bail!(token.loc, "duplicate rule: `{}`", p.builder[node].name)
     }
 
     let rule = rule(p)?;
-    p.grammar.nodes[node.0].rule = rule;
-    Ok(())
+    p.builder.define(node, rule)
+}
+
+fn precedence(p: &mut Parser) -> Result<()> {
+    let token = p.bump()?;
+    match &token.kind {
+        TokenKind::Directive(it) if it == "precedence" => (),
+        TokenKind::Directive(it) => bail!(token.loc, "unknown directive `%{}`", it),
+        _ => bail!(token.loc, "expected a directive"),
+    }
+    let name = p.bump()?;
+    let node = match name.kind {
+        TokenKind::Node(it) => p.builder.node(&it),
+        _ => bail!(name.loc, "expected the name of a node after `%precedence`"),
+    };
+
+    let mut levels = Vec::new();
+    while let Some(lexer::Token {
+        kind: TokenKind::Directive(directive),
+        loc,
+    }) = p.peek()
+    {
+        let assoc = match directive.as_str() {
+            "left" => Assoc::Left,
+            "right" => Assoc::Right,
+            "nonassoc" => Assoc::NonAssoc,
+            _ => break,
+        };
+        let loc = *loc;
+        p.bump()?;
+        let mut tokens = Vec::new();
+        while let Some(lexer::Token {
+            kind: TokenKind::Token(name),
+            ..
+        }) = p.peek()
+        {
+            let name = name.clone();
+            p.bump()?;
+            tokens.push(p.builder.token(&name));
+        }
+        if tokens.is_empty() {
+            bail!(loc, "expected at least one operator token")
+        }
+        levels.push(PrecedenceLevel { assoc, tokens });
+    }
+    if levels.is_empty() {
+        bail!(
+            token.loc,
+            "expected `%left`, `%right` or `%nonassoc` after `%precedence {}`",
+            p.builder[node].name
+        )
+    }
+    p.builder
+        .precedence(PrecedenceData { node, levels })
+        .map_err(|err| err.with_location(token.loc))
 }
 
 fn rule(p: &mut Parser) -> Result<Rule> {
@@ -190,13 +224,13 @@ fn opt_atom_rule(p: &mut Parser) -> Result<Option<Rule>> {
             }
             let name = name.clone();
             p.bump()?;
-            let node = p.intern_node(name);
+            let node = p.builder.node(&name);
             Rule::Node(node)
         }
         TokenKind::Token(name) => {
             let name = name.clone();
             p.bump()?;
-            let token = p.intern_token(name);
+            let token = p.builder.token(&name);
             Rule::Token(token)
         }
         TokenKind::LParen => {
@@ -0,0 +1,120 @@
+//! Consistency checks for precedence declarations.
+use std::fmt;
+
+use crate::{Grammar, Node, Rule, Token};
+
+/// A precedence declaration that does not match the rule of its node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrecedenceError {
+    /// The node whose declaration is inconsistent.
+    pub node: Node,
+    /// The offending operator.
+    pub token: Token,
+    /// What is wrong with it.
+    pub kind: PrecedenceErrorKind,
+    message: String,
+}
+
+/// The ways a precedence declaration can disagree with its node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PrecedenceErrorKind {
+    /// An operator of the node is not listed in any level.
+    MissingLevel,
+    /// A listed token is not an operator of the node.
+    NotAnOperator,
+    /// An operator is listed more than once.
+    DuplicateLevel,
+}
+
+impl fmt::Display for PrecedenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for PrecedenceError {}
+
+pub(crate) fn operators(rule: &Rule) -> Vec<Token> {
+    let mut res = Vec::new();
+    collect_operators(rule, false, &mut res);
+    res
+}
+
+/// Only choices inside a sequence count: in `A = 'int' | A op:('+' | '-') A`
+/// the operators are `'+'` and `'-'`, but not `'int'`.
+fn collect_operators(rule: &Rule, in_seq: bool, acc: &mut Vec<Token>) {
+    match rule {
+        Rule::Alt(arms) => {
+            for arm in arms {
+                match strip_labels(arm) {
+                    Rule::Token(token) if in_seq => {
+                        if !acc.contains(token) {
+                            acc.push(*token)
+                        }
+                    }
+                    arm => collect_operators(arm, false, acc),
+                }
+            }
+        }
+        Rule::Seq(rules) => rules.iter().for_each(|it| collect_operators(it, true, acc)),
+        Rule::Labeled { rule, .. } | Rule::Opt(rule) | Rule::Rep(rule) => {
+            collect_operators(rule, in_seq, acc)
+        }
+        Rule::Node(_) | Rule::Token(_) => (),
+    }
+}
+
+fn strip_labels(mut rule: &Rule) -> &Rule {
+    while let Rule::Labeled { rule: inner, .. } = rule {
+        rule = inner;
+    }
+    rule
+}
+
+pub(crate) fn check(grammar: &Grammar) -> Vec<PrecedenceError> {
+    let mut res = Vec::new();
+    for data in grammar.precedences() {
+        let node = data.node;
+        let error = |token: Token, kind: PrecedenceErrorKind| {
+            let (node_name, token_name) = (&grammar[node].name, &grammar[token].name);
+            let message = match kind {
+                PrecedenceErrorKind::MissingLevel => {
+                    format!(
+                        "operator '{}' of `{}` has no precedence level",
+                        token_name, node_name
+                    )
+                }
+                PrecedenceErrorKind::NotAnOperator => {
+                    format!("'{}' is not an operator of `{}`", token_name, node_name)
+                }
+                PrecedenceErrorKind::DuplicateLevel => format!(
+                    "operator '{}' of `{}` has more than one precedence level",
+                    token_name, node_name
+                ),
+            };
+            PrecedenceError {
+                node,
+                token,
+                kind,
+                message,
+            }
+        };
+
+        let operators = grammar.operators(node);
+        let mut seen = Vec::new();
+        for token in data.levels.iter().flat_map(|it| it.tokens.iter().copied()) {
+            if !operators.contains(&token) {
+                res.push(error(token, PrecedenceErrorKind::NotAnOperator));
+            } else if seen.contains(&token) {
+                res.push(error(token, PrecedenceErrorKind::DuplicateLevel));
+            }
+            seen.push(token);
+        }
+        for token in operators {
+            if !seen.contains(&token) {
+                res.push(error(token, PrecedenceErrorKind::MissingLevel));
+            }
+        }
+    }
+    res
+}
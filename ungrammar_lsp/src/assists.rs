@@ -0,0 +1,334 @@
+//! Code actions: small, mechanical edits of a grammar.
+//!
+//! Every assist is a set of text edits that leaves the file free of syntax
+//! errors, provided it was free of them before.
+use rowan::{TextRange, TextSize};
+
+use crate::{
+    db::{FileId, GrammarDatabase},
+    navigation::{self, Symbol},
+    printer::print_expr,
+    syntax::{ast, SyntaxKind::*, SyntaxNode},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AssistKind {
+    QuickFix,
+    RefactorInline,
+    RefactorExtract,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Assist {
+    pub(crate) label: String,
+    pub(crate) kind: AssistKind,
+    /// The code of the lint this assist fixes, if any.
+    pub(crate) fixes: Option<&'static str>,
+    /// What the assist is about, like the name of the node it creates.
+    pub(crate) target: TextRange,
+    pub(crate) edits: Vec<(TextRange, String)>,
+}
+
+/// The assists that apply to the selection `range`, or to the cursor if the
+/// range is empty.
+pub(crate) fn assists(db: &dyn GrammarDatabase, file: FileId, range: TextRange) -> Vec<Assist> {
+    let root = db.parse(file).syntax_node();
+    let mut res = Vec::new();
+    if let Some(token) = navigation::name_token_at(&root, range.start()) {
+        if let Some(Symbol::Node(name)) = navigation::classify(&token) {
+            let target = token.text_range();
+            res.extend(create_node(db, file, &root, &name, target));
+            res.extend(remove_unused_node(db, file, &root, &name, target));
+            res.extend(inline_node(db, file, &root, &name, target));
+        }
+    }
+    res.extend(extract_node(&root, range));
+    res
+}
+
+/// `A = B` where `B` has no rule: adds `B = 'TODO'` below.
+fn create_node(
+    db: &dyn GrammarDatabase,
+    file: FileId,
+    root: &SyntaxNode,
+    name: &str,
+    target: TextRange,
+) -> Option<Assist> {
+    if !db.grammar(file).undefined.iter().any(|it| it == name) {
+        return None;
+    }
+    let item = item_at(root, target.start())?;
+    let offset = line_end(root, item.text_range().end());
+    Some(Assist {
+        label: format!("Create node `{name}`"),
+        kind: AssistKind::QuickFix,
+        fixes: Some("undefined-node"),
+        target,
+        edits: vec![(TextRange::empty(offset), format!("\n{name} = 'TODO'"))],
+    })
+}
+
+/// Deletes the rule of a node nothing refers to.
+fn remove_unused_node(
+    db: &dyn GrammarDatabase,
+    file: FileId,
+    root: &SyntaxNode,
+    name: &str,
+    target: TextRange,
+) -> Option<Assist> {
+    let symbol = Symbol::Node(name.to_string());
+    if !navigation::references(db, file, &symbol, false).is_empty() {
+        return None;
+    }
+    let rule = rule_named(root, name)?;
+    Some(Assist {
+        label: format!("Remove unused node `{name}`"),
+        kind: AssistKind::QuickFix,
+        fixes: None,
+        target,
+        edits: vec![(deletion_range(root, &rule), String::new())],
+    })
+}
+
+/// Replaces the only reference to a node with the node's body, and deletes
+/// its rule.
+fn inline_node(
+    db: &dyn GrammarDatabase,
+    file: FileId,
+    root: &SyntaxNode,
+    name: &str,
+    target: TextRange,
+) -> Option<Assist> {
+    let symbol = Symbol::Node(name.to_string());
+    let [reference] = navigation::references(db, file, &symbol, false)[..] else {
+        return None;
+    };
+    if navigation::definitions(db, file, &symbol).len() != 1 {
+        return None;
+    }
+    let rule = rule_named(root, name)?;
+    let node_ref = root.covering_element(reference).parent()?;
+    let parent = node_ref.parent()?;
+    // Recursive nodes and precedence declarations cannot be inlined.
+    if !matches!(
+        parent.ancestors().find(|it| matches!(it.kind(), RULE | PRECEDENCE)),
+        Some(it) if it.kind() == RULE && it != rule.syntax
+    ) {
+        return None;
+    }
+    let body = rule.body()?;
+    let needs_parens = match &body {
+        ast::Expr::Node(_) | ast::Expr::Token(_) | ast::Expr::Paren(_) => false,
+        ast::Expr::Opt(_) | ast::Expr::Rep(_) => matches!(parent.kind(), OPT_RULE | REP_RULE),
+        ast::Expr::Labeled(_) => matches!(parent.kind(), OPT_RULE | REP_RULE | LABELED_RULE),
+        ast::Expr::Seq(_) => !matches!(parent.kind(), SEQ_RULE | ALT_RULE | RULE),
+        ast::Expr::Alt(_) => !matches!(parent.kind(), ALT_RULE | RULE),
+    };
+    let text = match needs_parens {
+        true => format!("({})", print_expr(&body)),
+        false => print_expr(&body),
+    };
+    Some(Assist {
+        label: format!("Inline node `{name}`"),
+        kind: AssistKind::RefactorInline,
+        fixes: None,
+        target,
+        edits: vec![
+            (node_ref.text_range(), text),
+            (deletion_range(root, &rule), String::new()),
+        ],
+    })
+}
+
+/// Turns the parenthesized rule around the selection into a node of its own.
+fn extract_node(root: &SyntaxNode, range: TextRange) -> Option<Assist> {
+    let paren = root
+        .covering_element(range)
+        .ancestors()
+        .find(|it| it.kind() == PAREN_RULE)?;
+    let inner = paren.children().find_map(ast::Expr::cast)?;
+    let item = item_at(root, paren.text_range().start())?;
+    let defined: Vec<_> = ast::SourceFile::cast(root.clone())?
+        .rules()
+        .filter_map(|it| it.name())
+        .map(|it| it.text().to_string())
+        .collect();
+    // Names cannot have digits, so the suffixes go `A`, `B`, ..., `AA`.
+    let name = (0..)
+        .map(|mut idx: usize| {
+            let mut suffix = Vec::new();
+            while idx > 0 {
+                idx -= 1;
+                suffix.push(char::from(b'A' + (idx % 26) as u8));
+                idx /= 26;
+            }
+            format!("NewNode{}", suffix.iter().rev().collect::<String>())
+        })
+        .find(|it| !defined.contains(it))?;
+    let offset = line_end(root, item.text_range().end());
+    Some(Assist {
+        label: "Extract into a new node".to_string(),
+        kind: AssistKind::RefactorExtract,
+        fixes: None,
+        target: paren.text_range(),
+        edits: vec![
+            (paren.text_range(), name.clone()),
+            (
+                TextRange::empty(offset),
+                format!("\n{name} = {}", print_expr(&inner)),
+            ),
+        ],
+    })
+}
+
+/// The rule or precedence declaration around `offset`.
+fn item_at(root: &SyntaxNode, offset: TextSize) -> Option<SyntaxNode> {
+    root.token_at_offset(offset)
+        .left_biased()?
+        .parent_ancestors()
+        .find(|it| matches!(it.kind(), RULE | PRECEDENCE))
+}
+
+fn rule_named(root: &SyntaxNode, name: &str) -> Option<ast::Rule> {
+    ast::SourceFile::cast(root.clone())?
+        .rules()
+        .find(|it| it.name().is_some_and(|it| it.text() == name))
+}
+
+/// The end of the line `offset` is on, before the newline. Comments that
+/// trail a rule stay with it.
+fn line_end(root: &SyntaxNode, offset: TextSize) -> TextSize {
+    let text = root.to_string();
+    let rest = &text[usize::from(offset)..];
+    offset + TextSize::from(rest.find('\n').unwrap_or(rest.len()) as u32)
+}
+
+/// The lines of `rule` and its doc comment.
+fn deletion_range(root: &SyntaxNode, rule: &ast::Rule) -> TextRange {
+    let start = match rule.doc_comment_tokens().first() {
+        Some(comment) => comment.text_range().start(),
+        None => rule.syntax.text_range().start(),
+    };
+    let end = line_end(root, rule.syntax.text_range().end());
+    let len = root.text_range().end();
+    if end < len {
+        // Take the newline too.
+        return TextRange::new(start, end + TextSize::from(1));
+    }
+    // The last line of the file: take the newline before it instead.
+    let text = root.to_string();
+    let start = match text[..usize::from(start)].rfind('\n') {
+        Some(idx) => TextSize::from(idx as u32),
+        None => start,
+    };
+    TextRange::new(start, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::db::{RootDatabase, SourceDatabase};
+
+    /// Applies the assist labeled `label` at the `$0` cursor, or the range
+    /// between two `$0`s.
+    fn apply(text: &str, label: &str) -> Option<String> {
+        let start = text.find("$0").unwrap();
+        let text = text.replacen("$0", "", 1);
+        let end = text.find("$0").unwrap_or(start);
+        let mut text = text.replace("$0", "");
+        let range = TextRange::new(TextSize::from(start as u32), TextSize::from(end as u32));
+
+        let mut db = RootDatabase::default();
+        let file = FileId(0);
+        db.set_file_text(file, Arc::new(text.clone()));
+        let assist = assists(&db, file, range)
+            .into_iter()
+            .find(|it| it.label == label)?;
+        let mut edits = assist.edits;
+        edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start()));
+        for (range, new_text) in edits {
+            text.replace_range(std::ops::Range::<usize>::from(range), &new_text);
+        }
+        db.set_file_text(file, Arc::new(text.clone()));
+        assert_eq!(*db.parse(file).errors, [], "{text}");
+        Some(text)
+    }
+
+    #[test]
+    fn creates_undefined_nodes() {
+        assert_eq!(
+            apply("A = B$0 C // c\nC = 'c'\n", "Create node `B`").unwrap(),
+            "A = B C // c\nB = 'TODO'\nC = 'c'\n"
+        );
+        assert_eq!(apply("A = B C$0\nC = 'c'\n", "Create node `C`"), None);
+    }
+
+    #[test]
+    fn removes_unused_nodes_with_their_docs() {
+        assert_eq!(
+            apply(
+                "A = 'a'\n// Unused.\nB$0 = 'b' // b\nC = A\n",
+                "Remove unused node `B`"
+            )
+            .unwrap(),
+            "A = 'a'\nC = A\n"
+        );
+        assert_eq!(apply("A = B\nB$0 = 'b'", "Remove unused node `B`"), None);
+        assert_eq!(
+            apply("A = 'a'\nB$0 = 'b'", "Remove unused node `B`").unwrap(),
+            "A = 'a'"
+        );
+    }
+
+    #[test]
+    fn inlines_nodes_used_once() {
+        assert_eq!(
+            apply(
+                "A = x:B$0 'a'\nB = C | D\nC = 'c'\nD = 'd'\n",
+                "Inline node `B`"
+            )
+            .unwrap(),
+            "A = x:(C | D) 'a'\nC = 'c'\nD = 'd'\n"
+        );
+        assert_eq!(
+            apply(
+                "A = 'a' B$0 'a'\nB = C D\nC = 'c'\nD = 'd'\n",
+                "Inline node `B`"
+            )
+            .unwrap(),
+            "A = 'a' C D 'a'\nC = 'c'\nD = 'd'\n"
+        );
+        assert_eq!(
+            apply("A = B*\nB$0 = C?\nC = 'c'\n", "Inline node `B`").unwrap(),
+            "A = (C?)*\nC = 'c'\n"
+        );
+        // Used twice, recursive or with operators.
+        assert_eq!(apply("A = B$0 B\nB = 'b'\n", "Inline node `B`"), None);
+        assert_eq!(apply("A = B\nB$0 = 'b' B?\n", "Inline node `B`"), None);
+        assert_eq!(
+            apply(
+                "A = B$0\nB = 'b'\n%precedence B %left 'b'\n",
+                "Inline node `B`"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn extracts_parenthesized_rules() {
+        assert_eq!(
+            apply(
+                "NewNode = 'n'\nA = x:$0('a' | B$0)* 'c' // c\nB = 'b'\n",
+                "Extract into a new node"
+            )
+            .unwrap(),
+            "NewNode = 'n'\nA = x:NewNodeA* 'c' // c\nNewNodeA = 'a' | B\nB = 'b'\n"
+        );
+        assert_eq!(
+            apply("A = 'a' B$0\nB = 'b'\n", "Extract into a new node"),
+            None
+        );
+    }
+}
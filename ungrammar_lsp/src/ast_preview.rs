@@ -0,0 +1,72 @@
+//! The Rust AST `codegen` makes of a grammar, as a read-only document.
+//!
+//! The preview of `file:///g/zork_keg.ungram` lives at
+//! `ungrammar-ast:///g/zork_keg.rs?file:///g/zork_keg.ungram`: the path gives
+//! editors a name and a language to show, the query says which grammar it
+//! comes from. Clients get its text with `workspace/textDocumentContent`, and
+//! the server asks them to get it again when the grammar changes.
+use lsp_types::Url;
+
+use crate::db::{FileId, GrammarDatabase};
+
+/// The command that opens the preview of a grammar.
+pub(crate) const COMMAND: &str = "ungrammar.previewAst";
+
+/// The URI scheme of previews.
+pub(crate) const SCHEME: &str = "ungrammar-ast";
+
+/// Where the preview of `grammar` lives.
+pub(crate) fn preview_uri(grammar: &Url) -> Url {
+    let path = grammar.path();
+    let path = path.strip_suffix(".ungram").unwrap_or(path);
+    let mut uri = Url::parse(&format!("{SCHEME}://")).unwrap();
+    uri.set_path(&format!("{path}.rs"));
+    uri.set_query(Some(grammar.as_str()));
+    uri
+}
+
+/// The grammar a preview comes from.
+pub(crate) fn grammar_uri(preview: &Url) -> Option<Url> {
+    if preview.scheme() != SCHEME {
+        return None;
+    }
+    Url::parse(preview.query()?).ok()
+}
+
+/// The source of the AST, from as much of the grammar as can be built.
+pub(crate) fn ast_source(db: &dyn GrammarDatabase, file: FileId) -> String {
+    let grammar = db.grammar(file).best_effort.clone();
+    codegen::render(&codegen::lower(&grammar))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::db::{RootDatabase, SourceDatabase};
+
+    #[test]
+    fn previews_point_back_to_their_grammar() {
+        let grammar = Url::parse("file:///g/zork%20keg.ungram").unwrap();
+        let preview = preview_uri(&grammar);
+        assert_eq!(
+            preview.as_str(),
+            "ungrammar-ast:///g/zork%20keg.rs?file:///g/zork%20keg.ungram"
+        );
+        assert_eq!(grammar_uri(&preview), Some(grammar.clone()));
+        assert_eq!(grammar_uri(&grammar), None);
+
+        let untitled = Url::parse("untitled:Untitled-1").unwrap();
+        assert_eq!(grammar_uri(&preview_uri(&untitled)), Some(untitled));
+    }
+
+    #[test]
+    fn renders_half_written_grammars() {
+        let mut db = RootDatabase::default();
+        db.set_file_text(FileId(0), Arc::new("A = B C\nB = 'b'\nD = \n".to_string()));
+        let source = ast_source(&db, FileId(0));
+        assert!(source.contains("pub struct A {"));
+        assert!(source.contains("pub fn c(&self) -> C {"));
+    }
+}
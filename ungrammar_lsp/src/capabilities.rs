@@ -0,0 +1,243 @@
+//! Negotiation of what the server offers with what the client supports.
+//!
+//! The client says what it can do in the `initialize` request. From that
+//! the server picks how to count characters in positions, whether to use
+//! snippets and Markdown, whether to ask for its settings, whether it pulls
+//! diagnostics or has them pushed, and which features to register
+//! dynamically rather than announce up front.
+use lsp_types::{
+    notification::{DidChangeConfiguration, DidChangeWatchedFiles, Notification},
+    request::{Formatting, RangeFormatting, Request},
+    CallHierarchyServerCapability, ClientCapabilities, CodeActionKind, CodeActionOptions,
+    CodeActionProviderCapability, CodeLensOptions, CompletionOptions,
+    DidChangeWatchedFilesRegistrationOptions, DocumentFilter, ExecuteCommandOptions,
+    FileSystemWatcher, FoldingRangeProviderCapability, GlobPattern, HoverProviderCapability,
+    MarkupKind, OneOf, PositionEncodingKind, Registration, RenameOptions,
+    SelectionRangeProviderCapability, SemanticTokensFullOptions, SemanticTokensOptions,
+    SemanticTokensServerCapabilities, ServerCapabilities, TextDocumentRegistrationOptions,
+    TextDocumentSyncCapability, TextDocumentSyncKind, TextDocumentSyncOptions,
+};
+
+use serde_json::Value;
+
+use crate::{
+    ast_preview, handlers,
+    line_index::PositionEncoding,
+    lsp_ext::{DiagnosticOptions, TextDocumentContentOptions},
+};
+
+/// What the server does differently depending on the client.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct ClientFeatures {
+    pub(crate) position_encoding: PositionEncoding,
+    /// Whether completions may use snippet syntax.
+    pub(crate) snippets: bool,
+    /// Whether hovers may use Markdown rather than plain text.
+    pub(crate) markdown_hover: bool,
+    /// Whether the documentation of completion items may use Markdown.
+    pub(crate) markdown_docs: bool,
+    /// Whether the settings can be asked for with `workspace/configuration`.
+    pub(crate) configuration: bool,
+    /// Whether the client shows progress the server reports with `$/progress`.
+    pub(crate) work_done_progress: bool,
+    /// Whether the client asks for diagnostics, rather than waiting for
+    /// `publishDiagnostics`.
+    pub(crate) pull_diagnostics: bool,
+    /// Whether the client can be told to pull diagnostics again.
+    pub(crate) diagnostic_refresh: bool,
+    /// Whether the client asks for the text of documents the server makes
+    /// up, like AST previews.
+    pub(crate) text_document_content: bool,
+    /// Features to register with `client/registerCapability` once the
+    /// client is initialized, rather than in the `initialize` result.
+    pub(crate) registrations: Vec<Registration>,
+}
+
+/// `raw` is `client` as sent: it has the capabilities `lsp-types` does not
+/// know about yet.
+pub(crate) fn client_features(client: &ClientCapabilities, raw: &Value) -> ClientFeatures {
+    let encodings = client
+        .general
+        .as_ref()
+        .and_then(|it| it.position_encodings.as_deref())
+        .unwrap_or_default();
+    // The syntax tree counts bytes too, so UTF-8 needs no conversion.
+    let position_encoding = match encodings.contains(&PositionEncodingKind::UTF8) {
+        true => PositionEncoding::Utf8,
+        false => PositionEncoding::Utf16,
+    };
+    let text_document = client.text_document.as_ref();
+    let completion_item = text_document
+        .and_then(|it| it.completion.as_ref())
+        .and_then(|it| it.completion_item.as_ref());
+    let snippets = completion_item.and_then(|it| it.snippet_support) == Some(true);
+    let markdown_docs = completion_item
+        .and_then(|it| it.documentation_format.as_ref())
+        .is_some_and(|it| it.contains(&MarkupKind::Markdown));
+    let markdown_hover = text_document
+        .and_then(|it| it.hover.as_ref())
+        .and_then(|it| it.content_format.as_ref())
+        .is_some_and(|it| it.contains(&MarkupKind::Markdown));
+
+    let mut registrations = Vec::new();
+    let dynamic = |it: Option<&lsp_types::DynamicRegistrationClientCapabilities>| {
+        it.and_then(|it| it.dynamic_registration) == Some(true)
+    };
+    if dynamic(text_document.and_then(|it| it.formatting.as_ref())) {
+        registrations.push(grammar_registration(Formatting::METHOD));
+    }
+    if dynamic(text_document.and_then(|it| it.range_formatting.as_ref())) {
+        registrations.push(grammar_registration(RangeFormatting::METHOD));
+    }
+    let workspace = client.workspace.as_ref();
+    let configuration = workspace.and_then(|it| it.configuration) == Some(true);
+    // Some clients only tell about changed settings once asked to.
+    if dynamic(workspace.and_then(|it| it.did_change_configuration.as_ref())) {
+        registrations.push(Registration {
+            id: DidChangeConfiguration::METHOD.to_string(),
+            method: DidChangeConfiguration::METHOD.to_string(),
+            register_options: None,
+        });
+    }
+    let watched_files = workspace.and_then(|it| it.did_change_watched_files);
+    if watched_files.and_then(|it| it.dynamic_registration) == Some(true) {
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![FileSystemWatcher {
+                glob_pattern: GlobPattern::String("**/*.ungram".to_string()),
+                kind: None,
+            }],
+        };
+        registrations.push(Registration {
+            id: DidChangeWatchedFiles::METHOD.to_string(),
+            method: DidChangeWatchedFiles::METHOD.to_string(),
+            register_options: Some(serde_json::to_value(options).unwrap()),
+        });
+    }
+    let work_done_progress =
+        client.window.as_ref().and_then(|it| it.work_done_progress) == Some(true);
+    let pull_diagnostics = raw["textDocument"]["diagnostic"].is_object();
+    let diagnostic_refresh = raw["workspace"]["diagnostics"]["refreshSupport"] == true;
+    let text_document_content = raw["workspace"]["textDocumentContent"].is_object();
+    ClientFeatures {
+        position_encoding,
+        snippets,
+        markdown_hover,
+        markdown_docs,
+        configuration,
+        work_done_progress,
+        pull_diagnostics,
+        diagnostic_refresh,
+        text_document_content,
+        registrations,
+    }
+}
+
+/// Registers `method` for grammar files only, so that the client does not
+/// offer it for other languages.
+fn grammar_registration(method: &str) -> Registration {
+    let options = TextDocumentRegistrationOptions {
+        document_selector: Some(vec![
+            DocumentFilter {
+                language: Some("ungrammar".to_string()),
+                scheme: None,
+                pattern: None,
+            },
+            DocumentFilter {
+                language: None,
+                scheme: None,
+                pattern: Some("**/*.ungram".to_string()),
+            },
+        ]),
+    };
+    Registration {
+        id: method.to_string(),
+        method: method.to_string(),
+        register_options: Some(serde_json::to_value(options).unwrap()),
+    }
+}
+
+/// The `diagnosticProvider` capability, for clients that pull diagnostics.
+pub(crate) fn diagnostic_provider(features: &ClientFeatures) -> Option<DiagnosticOptions> {
+    features.pull_diagnostics.then(|| DiagnosticOptions {
+        identifier: Some("ungrammar".to_string()),
+        // Samples are checked against their grammar.
+        inter_file_dependencies: true,
+        workspace_diagnostics: true,
+    })
+}
+
+/// The `workspace.textDocumentContent` capability, for clients that can show
+/// AST previews as documents.
+pub(crate) fn text_document_content(
+    features: &ClientFeatures,
+) -> Option<TextDocumentContentOptions> {
+    features
+        .text_document_content
+        .then(|| TextDocumentContentOptions {
+            schemes: vec![ast_preview::SCHEME.to_string()],
+        })
+}
+
+pub(crate) fn server_capabilities(features: &ClientFeatures) -> ServerCapabilities {
+    // Dynamically registered features are left out, so that the client
+    // does not get them twice.
+    let registered = |method: &str| features.registrations.iter().any(|it| it.method == method);
+    ServerCapabilities {
+        position_encoding: Some(features.position_encoding.kind()),
+        text_document_sync: Some(TextDocumentSyncCapability::Options(
+            TextDocumentSyncOptions {
+                open_close: Some(true),
+                change: Some(TextDocumentSyncKind::INCREMENTAL),
+                ..Default::default()
+            },
+        )),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions {
+            resolve_provider: Some(true),
+            trigger_characters: Some(vec!["'".to_string()]),
+            ..Default::default()
+        }),
+        rename_provider: Some(OneOf::Right(RenameOptions {
+            prepare_provider: Some(true),
+            work_done_progress_options: Default::default(),
+        })),
+        document_highlight_provider: Some(OneOf::Left(true)),
+        folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+        selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        workspace_symbol_provider: Some(OneOf::Left(true)),
+        document_formatting_provider: (!registered(Formatting::METHOD))
+            .then_some(OneOf::Left(true)),
+        document_range_formatting_provider: (!registered(RangeFormatting::METHOD))
+            .then_some(OneOf::Left(true)),
+        inlay_hint_provider: Some(OneOf::Left(true)),
+        code_action_provider: Some(CodeActionProviderCapability::Options(CodeActionOptions {
+            code_action_kinds: Some(vec![
+                CodeActionKind::QUICKFIX,
+                CodeActionKind::REFACTOR_INLINE,
+                CodeActionKind::REFACTOR_EXTRACT,
+            ]),
+            resolve_provider: None,
+            work_done_progress_options: Default::default(),
+        })),
+        execute_command_provider: Some(ExecuteCommandOptions {
+            commands: vec![ast_preview::COMMAND.to_string()],
+            work_done_progress_options: Default::default(),
+        }),
+        code_lens_provider: Some(CodeLensOptions {
+            resolve_provider: Some(false),
+        }),
+        call_hierarchy_provider: Some(CallHierarchyServerCapability::Simple(true)),
+        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+            SemanticTokensOptions {
+                legend: handlers::semantic_tokens_legend(),
+                full: Some(SemanticTokensFullOptions::Bool(true)),
+                range: Some(true),
+                ..Default::default()
+            },
+        )),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,570 @@
+//! `ungrammar_lsp check`: the checks of the server, without an editor.
+//!
+//! ```text
+//! ungrammar_lsp check [--format human|json|sarif] [--config <settings.json>] <path>...
+//! ```
+//!
+//! Paths are files, directories to search for grammars, or globs like
+//! `grammars/**/*.ungram`. Samples are checked against their grammar, see
+//! [`crate::playground`]. The settings file has the same object editors
+//! send, see [`crate::config`].
+//!
+//! The exit code is the highest severity found: 0 for nothing or hints, 1
+//! for information, 2 for warnings and 3 for errors. It is 4 if the check
+//! could not run at all.
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+};
+
+use lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString, Url};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+    config::Config,
+    db::{FileId, GrammarDatabase, RootDatabase, SourceDatabase},
+    diagnostics, playground, workspace,
+};
+
+pub(crate) const USAGE: &str = "\
+usage: ungrammar_lsp check [--format human|json|sarif] [--config <settings.json>] <path>...
+
+Checks grammars and samples. Paths are files, directories to search for
+grammars, or globs like `grammars/**/*.ungram`.
+
+The exit code is the highest severity found: 0 for nothing or hints, 1 for
+information, 2 for warnings and 3 for errors. It is 4 if the check could
+not run.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    /// Like compiler errors, with the source they are about.
+    Human,
+    /// A list of LSP diagnostics, each with the path of its file.
+    Json,
+    /// SARIF 2.1.0, for code scanning tools.
+    Sarif,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CheckArgs {
+    pub(crate) format: Format,
+    pub(crate) config: Option<PathBuf>,
+    pub(crate) paths: Vec<String>,
+}
+
+pub(crate) fn parse_args(args: impl IntoIterator<Item = String>) -> Result<CheckArgs, String> {
+    let mut format = Format::Human;
+    let mut config = None;
+    let mut paths = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                format = match args.next().as_deref() {
+                    Some("human") => Format::Human,
+                    Some("json") => Format::Json,
+                    Some("sarif") => Format::Sarif,
+                    other => {
+                        let other = other.unwrap_or("nothing");
+                        return Err(format!(
+                            "expected `human`, `json` or `sarif` after `--format`, got `{other}`"
+                        ));
+                    }
+                }
+            }
+            "--config" => match args.next() {
+                Some(path) => config = Some(PathBuf::from(path)),
+                None => return Err("expected a path after `--config`".to_string()),
+            },
+            _ if arg.starts_with("--") => return Err(format!("unknown option `{arg}`")),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        return Err("expected at least one path".to_string());
+    }
+    Ok(CheckArgs {
+        format,
+        config,
+        paths,
+    })
+}
+
+/// Runs `check` with the arguments after the subcommand.
+pub(crate) fn run(args: impl IntoIterator<Item = String>) -> ExitCode {
+    let args = match parse_args(args) {
+        Ok(it) => it,
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            return ExitCode::from(4);
+        }
+    };
+    let mut config = Config::default();
+    if let Some(path) = &args.config {
+        let value = fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|it| serde_json::from_str(&it).map_err(|err| err.to_string()));
+        match value {
+            Ok(value) => {
+                for err in config.update(&value) {
+                    eprintln!("warning: invalid setting {err}");
+                }
+            }
+            Err(err) => {
+                eprintln!("error: cannot read {}: {err}", path.display());
+                return ExitCode::from(4);
+            }
+        }
+    }
+    let paths = match expand(&args.paths) {
+        Ok(it) => it,
+        Err(err) => {
+            eprintln!("error: {err}");
+            return ExitCode::from(4);
+        }
+    };
+    let reports = match check(&paths, &config) {
+        Ok(it) => it,
+        Err(err) => {
+            eprintln!("error: {err}");
+            return ExitCode::from(4);
+        }
+    };
+    let output = match args.format {
+        Format::Human => human(&reports),
+        Format::Json => serde_json::to_string_pretty(&json_diagnostics(&reports)).unwrap() + "\n",
+        Format::Sarif => serde_json::to_string_pretty(&sarif(&reports)).unwrap() + "\n",
+    };
+    print!("{output}");
+    ExitCode::from(exit_code(&reports))
+}
+
+/// The files `paths` stand for, in order and without duplicates.
+fn expand(paths: &[String]) -> Result<Vec<PathBuf>, String> {
+    let mut res = Vec::new();
+    for path in paths {
+        let found = if path.contains(['*', '?']) {
+            workspace::glob(path)
+        } else if Path::new(path).is_dir() {
+            workspace::grammar_files(Path::new(path))
+        } else {
+            vec![PathBuf::from(path)]
+        };
+        if found.is_empty() {
+            return Err(format!("`{path}` matches no files"));
+        }
+        res.extend(found);
+    }
+    let mut seen = BTreeSet::new();
+    res.retain(|it| seen.insert(it.clone()));
+    Ok(res)
+}
+
+/// What is wrong with one file.
+pub(crate) struct Report {
+    pub(crate) path: PathBuf,
+    pub(crate) text: Arc<String>,
+    pub(crate) diagnostics: Vec<Diagnostic>,
+}
+
+/// Checks `paths`, loading the grammars of samples that are not among them.
+pub(crate) fn check(paths: &[PathBuf], config: &Config) -> Result<Vec<Report>, String> {
+    let mut db = RootDatabase::default();
+    db.set_lex_prefix(Arc::from(config.lex_prefix.as_str()));
+    db.set_root_nodes(Arc::new(config.root_nodes.clone()));
+    let mut files = HashMap::new();
+    let mut checked = Vec::new();
+    for path in paths {
+        let (uri, file) = load(&mut db, &mut files, path)?;
+        if playground::is_sample(&uri) {
+            let grammar = playground::parse_header(&db.file_text(file))
+                .ok()
+                .and_then(|it| playground::grammar_uri(&uri, &it))
+                .and_then(|it| it.to_file_path().ok())
+                .filter(|it| it.is_file());
+            // A sample without its grammar says so in its diagnostics.
+            if let Some(grammar) = grammar {
+                load(&mut db, &mut files, &grammar)?;
+            }
+        }
+        checked.push((path, uri, file));
+    }
+    let reports = checked
+        .into_iter()
+        .map(|(path, uri, file)| Report {
+            path: path.clone(),
+            text: db.file_text(file),
+            diagnostics: diagnostics::file_diagnostics(&db, config, &uri, file, &files),
+        })
+        .collect();
+    Ok(reports)
+}
+
+/// Feeds the file at `path` to the database, once.
+fn load(
+    db: &mut RootDatabase,
+    files: &mut HashMap<Url, FileId>,
+    path: &Path,
+) -> Result<(Url, FileId), String> {
+    let error = |err: &dyn std::fmt::Display| format!("cannot read {}: {err}", path.display());
+    let absolute = fs::canonicalize(path).map_err(|err| error(&err))?;
+    let uri = Url::from_file_path(&absolute).map_err(|()| error(&"not a local path"))?;
+    if let Some(&file) = files.get(&uri) {
+        return Ok((uri, file));
+    }
+    let text = fs::read_to_string(&absolute).map_err(|err| error(&err))?;
+    let file = FileId(files.len() as u32);
+    db.set_file_text(file, Arc::new(text));
+    files.insert(uri.clone(), file);
+    Ok((uri, file))
+}
+
+pub(crate) fn exit_code(reports: &[Report]) -> u8 {
+    reports
+        .iter()
+        .flat_map(|it| &it.diagnostics)
+        .map(|it| match it.severity {
+            Some(DiagnosticSeverity::ERROR) | None => 3,
+            Some(DiagnosticSeverity::WARNING) => 2,
+            Some(DiagnosticSeverity::INFORMATION) => 1,
+            Some(_) => 0,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+fn severity_name(severity: Option<DiagnosticSeverity>) -> &'static str {
+    match severity {
+        Some(DiagnosticSeverity::WARNING) => "warning",
+        Some(DiagnosticSeverity::INFORMATION) => "info",
+        Some(DiagnosticSeverity::HINT) => "hint",
+        _ => "error",
+    }
+}
+
+fn code(diagnostic: &Diagnostic) -> Option<&str> {
+    match &diagnostic.code {
+        Some(NumberOrString::String(it)) => Some(it),
+        _ => None,
+    }
+}
+
+/// Reports in the style of compiler errors:
+///
+/// ```text
+/// error[undefined-node]: undefined node: `B`
+///  --> a.ungram:1:5
+///   |
+/// 1 | A = B
+///   |     ^
+/// ```
+pub(crate) fn human(reports: &[Report]) -> String {
+    let mut out = String::new();
+    let mut counts = [0; 2];
+    for report in reports {
+        let lines: Vec<_> = report.text.lines().collect();
+        for diagnostic in &report.diagnostics {
+            let severity = severity_name(diagnostic.severity);
+            match severity {
+                "error" => counts[0] += 1,
+                "warning" => counts[1] += 1,
+                _ => (),
+            }
+            let code = code(diagnostic)
+                .map(|it| format!("[{it}]"))
+                .unwrap_or_default();
+            writeln!(out, "{severity}{code}: {}", diagnostic.message).unwrap();
+            let start = diagnostic.range.start;
+            let line = lines.get(start.line as usize).copied().unwrap_or("");
+            // Positions count UTF-16 code units, the snippet counts
+            // characters.
+            let column = utf16_to_chars(line, start.character);
+            let width = match diagnostic.range.end.line == start.line {
+                true => utf16_to_chars(line, diagnostic.range.end.character) - column,
+                false => line.chars().count() - column,
+            };
+            let number = (start.line + 1).to_string();
+            let gutter = " ".repeat(number.len());
+            writeln!(
+                out,
+                "{gutter}--> {}:{}:{}",
+                report.path.display(),
+                start.line + 1,
+                column + 1
+            )
+            .unwrap();
+            writeln!(out, "{gutter} |").unwrap();
+            writeln!(out, "{number} | {line}").unwrap();
+            writeln!(
+                out,
+                "{gutter} | {}{}",
+                " ".repeat(column),
+                "^".repeat(width.max(1))
+            )
+            .unwrap();
+            // Related information is always about the same file.
+            for related in diagnostic.related_information.iter().flatten() {
+                let position = related.location.range.start;
+                let line = lines.get(position.line as usize).copied().unwrap_or("");
+                writeln!(
+                    out,
+                    "{gutter} = note: {}: {}:{}:{}",
+                    related.message,
+                    report.path.display(),
+                    position.line + 1,
+                    utf16_to_chars(line, position.character) + 1
+                )
+                .unwrap();
+            }
+            writeln!(out).unwrap();
+        }
+    }
+    let plural = |count: usize, what: &str| match count {
+        1 => format!("1 {what}"),
+        _ => format!("{count} {what}s"),
+    };
+    writeln!(
+        out,
+        "checked {}: {}, {}",
+        plural(reports.len(), "file"),
+        plural(counts[0], "error"),
+        plural(counts[1], "warning")
+    )
+    .unwrap();
+    out
+}
+
+fn utf16_to_chars(line: &str, utf16: u32) -> usize {
+    let mut units = 0;
+    line.chars()
+        .take_while(|c| {
+            units += c.len_utf16() as u32;
+            units <= utf16
+        })
+        .count()
+}
+
+#[derive(Serialize)]
+struct JsonDiagnostic<'a> {
+    path: &'a Path,
+    #[serde(flatten)]
+    diagnostic: &'a Diagnostic,
+}
+
+fn json_diagnostics(reports: &[Report]) -> Vec<JsonDiagnostic<'_>> {
+    reports
+        .iter()
+        .flat_map(|report| {
+            report.diagnostics.iter().map(|diagnostic| JsonDiagnostic {
+                path: &report.path,
+                diagnostic,
+            })
+        })
+        .collect()
+}
+
+/// `path` as a SARIF artifact location, which separates with `/`.
+fn artifact_uri(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+fn sarif_region(range: &lsp_types::Range) -> serde_json::Value {
+    // SARIF counts from 1, and columns in UTF-16 code units by default.
+    json!({
+        "startLine": range.start.line + 1,
+        "startColumn": range.start.character + 1,
+        "endLine": range.end.line + 1,
+        "endColumn": range.end.character + 1,
+    })
+}
+
+pub(crate) fn sarif(reports: &[Report]) -> serde_json::Value {
+    let mut rules = BTreeSet::new();
+    let mut results = Vec::new();
+    for report in reports {
+        let uri = artifact_uri(&report.path);
+        for diagnostic in &report.diagnostics {
+            let rule = code(diagnostic).unwrap_or("syntax");
+            rules.insert(rule);
+            let level = match diagnostic.severity {
+                Some(DiagnosticSeverity::WARNING) => "warning",
+                Some(DiagnosticSeverity::INFORMATION) | Some(DiagnosticSeverity::HINT) => "note",
+                _ => "error",
+            };
+            let related: Vec<_> = diagnostic
+                .related_information
+                .iter()
+                .flatten()
+                .enumerate()
+                .map(|(idx, it)| {
+                    json!({
+                        "id": idx,
+                        "message": { "text": it.message },
+                        "physicalLocation": {
+                            "artifactLocation": { "uri": uri },
+                            "region": sarif_region(&it.location.range),
+                        },
+                    })
+                })
+                .collect();
+            let mut result = json!({
+                "ruleId": rule,
+                "level": level,
+                "message": { "text": diagnostic.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": uri },
+                        "region": sarif_region(&diagnostic.range),
+                    },
+                }],
+            });
+            if !related.is_empty() {
+                result["relatedLocations"] = related.into();
+            }
+            results.push(result);
+        }
+    }
+    let rules: Vec<_> = rules.into_iter().map(|it| json!({ "id": it })).collect();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "ungrammar_lsp",
+                    "rules": rules,
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_arguments() {
+        let args = |it: &[&str]| parse_args(it.iter().map(|it| it.to_string()));
+        assert_eq!(
+            args(&["--format", "sarif", "a.ungram", "grammars/**/*.ungram"]),
+            Ok(CheckArgs {
+                format: Format::Sarif,
+                config: None,
+                paths: vec!["a.ungram".to_string(), "grammars/**/*.ungram".to_string()],
+            })
+        );
+        assert_eq!(
+            args(&["--format", "xml", "a.ungram"]),
+            Err("expected `human`, `json` or `sarif` after `--format`, got `xml`".to_string())
+        );
+        assert_eq!(
+            args(&["--verbose"]),
+            Err("unknown option `--verbose`".to_string())
+        );
+        assert_eq!(args(&[]), Err("expected at least one path".to_string()));
+    }
+
+    #[test]
+    fn reports_every_format() {
+        let dir = std::env::temp_dir().join(format!("ungrammar_lsp_check_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.ungram"), "A = B\nA = 'a'\nD = 'é' C\n").unwrap();
+        fs::write(dir.join("b.ungram"), "B = 'b'\n").unwrap();
+
+        let mut config = Config::default();
+        config.update(&json!({ "lints": { "undefined-node": "warning" } }));
+        let paths = [dir.join("a.ungram"), dir.join("b.ungram")];
+        let reports = check(&paths, &config).unwrap();
+        assert_eq!(exit_code(&reports), 3);
+        let a = dir.join("a.ungram").display().to_string();
+        let expected = "\
+warning[undefined-node]: undefined node: `B`
+ --> A:1:5
+  |
+1 | A = B
+  |     ^
+
+error[duplicate-rule]: duplicate rule: `A`
+ --> A:2:1
+  |
+2 | A = 'a'
+  | ^
+  = note: first defined here: A:1:1
+
+warning[undefined-node]: undefined node: `C`
+ --> A:3:9
+  |
+3 | D = 'é' C
+  |         ^
+
+checked 2 files: 1 error, 2 warnings
+";
+        assert_eq!(human(&reports), expected.replace(" A:", &format!(" {a}:")));
+
+        let sarif = sarif(&reports);
+        let run = &sarif["runs"][0];
+        assert_eq!(
+            run["tool"]["driver"]["rules"],
+            json!([{ "id": "duplicate-rule" }, { "id": "undefined-node" }])
+        );
+        let result = &run["results"][1];
+        assert_eq!(result["level"], "error");
+        assert_eq!(
+            result["locations"][0]["physicalLocation"]["region"],
+            json!({ "startLine": 2, "startColumn": 1, "endLine": 2, "endColumn": 2 })
+        );
+        assert_eq!(
+            result["relatedLocations"][0]["message"]["text"],
+            "first defined here"
+        );
+
+        let json = serde_json::to_value(json_diagnostics(&reports)).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 3);
+        assert_eq!(json[0]["path"], a);
+        assert_eq!(json[0]["code"], "undefined-node");
+        assert_eq!(json[0]["severity"], 2);
+
+        config.update(&json!({ "lints": { "duplicate-rule": "off", "undefined-node": "hint" } }));
+        assert_eq!(exit_code(&check(&paths, &config).unwrap()), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn related_information_counts_characters() {
+        let related = lsp_types::DiagnosticRelatedInformation {
+            location: lsp_types::Location::new(
+                Url::parse("file:///g/b.ungram").unwrap(),
+                // `𝔸` is two UTF-16 code units.
+                lsp_types::Range::new(
+                    lsp_types::Position::new(0, 9),
+                    lsp_types::Position::new(0, 10),
+                ),
+            ),
+            message: "used here".to_string(),
+        };
+        let diagnostic = Diagnostic {
+            message: "unreachable node: `B`".to_string(),
+            related_information: Some(vec![related]),
+            ..Default::default()
+        };
+        let reports = [Report {
+            path: PathBuf::from("b.ungram"),
+            text: Arc::new("B = '𝔸' A\n".to_string()),
+            diagnostics: vec![diagnostic],
+        }];
+        let human = human(&reports);
+        assert!(
+            human.contains("  = note: used here: b.ungram:1:9\n"),
+            "{human}"
+        );
+    }
+}
@@ -0,0 +1,315 @@
+//! Completion of node names, tokens and labels.
+//!
+//! In a rule body, everything the grammar already mentions is offered,
+//! most used first. An identifier that starts a line is taken to be the
+//! start of a new rule instead, and gets snippets for new nodes.
+use std::collections::HashMap;
+
+use rowan::{TextRange, TextSize};
+
+use crate::{
+    db::{FileId, SourceDatabase},
+    syntax::{ast, escape_token, SyntaxKind::*, SyntaxNode},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CompletionKind {
+    Node,
+    Token,
+    /// A labeled node reference, like `segment:PathSegment`.
+    Label,
+    Snippet,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Completion {
+    pub(crate) label: String,
+    pub(crate) kind: CompletionKind,
+    /// Replaces `range`. Uses snippet syntax if `kind` is `Snippet`.
+    pub(crate) insert: String,
+    pub(crate) range: TextRange,
+    pub(crate) detail: Option<String>,
+    /// How many times the grammar already uses this, for ranking.
+    pub(crate) uses: usize,
+    /// The node whose documentation describes this item.
+    pub(crate) node: Option<String>,
+}
+
+pub(crate) fn completions(
+    db: &dyn SourceDatabase,
+    file: FileId,
+    offset: TextSize,
+) -> Vec<Completion> {
+    let root = db.parse(file).syntax_node();
+    let token = root
+        .token_at_offset(offset)
+        .left_biased()
+        .filter(|it| it.text_range().start() < offset);
+
+    match token.as_ref().map(|it| it.kind()) {
+        Some(TOKEN_LIT) => {
+            let range = token.unwrap().text_range();
+            return token_completions(&Usages::new(&root, range), range);
+        }
+        Some(COMMENT) => return Vec::new(),
+        _ => (),
+    }
+    let range = match &token {
+        Some(it) if it.kind() == IDENT => it.text_range(),
+        _ => TextRange::empty(offset),
+    };
+    let usages = Usages::new(&root, range);
+    if starts_line(&root, range.start()) {
+        return new_node_completions(&usages, range);
+    }
+
+    let mut res = Vec::new();
+    for (name, &uses) in &usages.nodes {
+        res.push(Completion {
+            label: name.clone(),
+            kind: CompletionKind::Node,
+            insert: name.clone(),
+            range,
+            detail: None,
+            uses,
+            node: usages.defined.contains(name).then(|| name.clone()),
+        });
+    }
+    for ((label, node), &uses) in &usages.labels {
+        let text = format!("{label}:{node}");
+        res.push(Completion {
+            label: text.clone(),
+            kind: CompletionKind::Label,
+            insert: text,
+            range,
+            detail: Some(format!("label used for `{node}`")),
+            uses,
+            node: usages.defined.contains(node).then(|| node.clone()),
+        });
+    }
+    res.extend(token_completions(&usages, range));
+    sort(&mut res);
+    res
+}
+
+fn token_completions(usages: &Usages, range: TextRange) -> Vec<Completion> {
+    let mut res: Vec<_> = usages
+        .tokens
+        .iter()
+        .map(|(value, &uses)| {
+            let text = escape_token(value);
+            Completion {
+                label: text.clone(),
+                kind: CompletionKind::Token,
+                insert: text,
+                range,
+                detail: None,
+                uses,
+                node: None,
+            }
+        })
+        .collect();
+    sort(&mut res);
+    res
+}
+
+/// Snippets for a new rule, plus nodes that are used but not defined yet.
+fn new_node_completions(usages: &Usages, range: TextRange) -> Vec<Completion> {
+    let mut res: Vec<_> = usages
+        .nodes
+        .iter()
+        .filter(|(name, _)| !usages.defined.contains(*name))
+        .map(|(name, &uses)| Completion {
+            label: format!("{name} ="),
+            kind: CompletionKind::Snippet,
+            insert: format!("{name} =\n  $0"),
+            range,
+            detail: Some("define undefined node".to_string()),
+            uses,
+            node: None,
+        })
+        .collect();
+    sort(&mut res);
+    let snippets = [
+        ("new node", "${1:Name} = $0"),
+        (
+            "new node with alternatives",
+            "${1:Name} =\n  ${2:First}\n| ${3:Second}",
+        ),
+    ];
+    res.extend(snippets.map(|(label, insert)| Completion {
+        label: label.to_string(),
+        kind: CompletionKind::Snippet,
+        insert: insert.to_string(),
+        range,
+        detail: Some("create new node".to_string()),
+        uses: 0,
+        node: None,
+    }));
+    res
+}
+
+/// The text a snippet inserts for clients without snippet support: tab
+/// stops go away and placeholders become plain text.
+pub(crate) fn strip_snippet(snippet: &str) -> String {
+    let mut res = String::with_capacity(snippet.len());
+    let mut chars = snippet.chars().peekable();
+    let mut placeholders = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => res.extend(chars.next()),
+            '$' if chars.peek() == Some(&'{') => {
+                chars.by_ref().take_while(|it| *it != ':').for_each(drop);
+                placeholders += 1;
+            }
+            '$' => while chars.next_if(char::is_ascii_digit).is_some() {},
+            '}' if placeholders > 0 => placeholders -= 1,
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+/// Most used first, then alphabetically.
+fn sort(completions: &mut [Completion]) {
+    completions.sort_by(|a, b| b.uses.cmp(&a.uses).then_with(|| a.label.cmp(&b.label)));
+}
+
+/// Whether `offset` is at the very start of a line, where only rule names
+/// can go.
+fn starts_line(root: &SyntaxNode, offset: TextSize) -> bool {
+    if offset == TextSize::from(0) {
+        return true;
+    }
+    let Some(before) = root.token_at_offset(offset).left_biased() else {
+        return false;
+    };
+    let len = usize::from(offset - before.text_range().start());
+    before.kind() == WHITESPACE && before.text()[..len].ends_with('\n')
+}
+
+/// How often each name is used in a file.
+struct Usages {
+    nodes: HashMap<String, usize>,
+    defined: Vec<String>,
+    tokens: HashMap<String, usize>,
+    labels: HashMap<(String, String), usize>,
+}
+
+impl Usages {
+    /// Counts the names in `root`, except for the one being typed at `typed`.
+    fn new(root: &SyntaxNode, typed: TextRange) -> Usages {
+        let mut res = Usages {
+            nodes: HashMap::new(),
+            defined: Vec::new(),
+            tokens: HashMap::new(),
+            labels: HashMap::new(),
+        };
+        for node in root.descendants() {
+            if node.text_range() == typed && matches!(node.kind(), NODE_REF | TOKEN_REF) {
+                continue;
+            }
+            if let Some(rule) = ast::Rule::cast(node.clone()) {
+                if let Some(name) = rule.name() {
+                    res.defined.push(name.text().to_string());
+                    res.nodes.entry(name.text().to_string()).or_default();
+                }
+            } else if let Some(node_ref) = ast::NodeRef::cast(node.clone()) {
+                if let Some(name) = node_ref.name() {
+                    *res.nodes.entry(name.text().to_string()).or_default() += 1;
+                }
+            } else if let Some(token_ref) = ast::TokenRef::cast(node.clone()) {
+                if let Some(value) = token_ref.value().filter(|it| !it.is_empty()) {
+                    *res.tokens.entry(value).or_default() += 1;
+                }
+            } else if let Some(labeled) = ast::LabeledRule::cast(node) {
+                let node_ref = labeled
+                    .syntax
+                    .descendants()
+                    .take_while(|it| {
+                        matches!(it.kind(), LABELED_RULE | OPT_RULE | REP_RULE | NODE_REF)
+                    })
+                    .find_map(ast::NodeRef::cast)
+                    .filter(|it| it.syntax.text_range() != typed);
+                let (Some(label), Some(name)) =
+                    (labeled.label(), node_ref.and_then(|it| it.name()))
+                else {
+                    continue;
+                };
+                let key = (label.text().to_string(), name.text().to_string());
+                *res.labels.entry(key).or_default() += 1;
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::db::RootDatabase;
+
+    fn labels(text: &str) -> Vec<String> {
+        let offset = TextSize::from(text.find("$0").unwrap() as u32);
+        let text = text.replace("$0", "");
+        let mut db = RootDatabase::default();
+        let file = FileId(0);
+        db.set_file_text(file, Arc::new(text));
+        completions(&db, file, offset)
+            .into_iter()
+            .map(|it| it.label)
+            .collect()
+    }
+
+    const PATHS: &str = "\
+Path = AbsolutePath | RelativePath
+AbsolutePath = ('/' segment:PathSegment)* '/'?
+RelativePath = './'? segment:PathSegment ('/' PathSegment)*
+PathSegment = 'lex:base64url'
+";
+
+    #[test]
+    fn ranks_by_usage() {
+        let text = format!("{PATHS}Url = scheme:'lex:scheme' Pa$0");
+        assert_eq!(
+            labels(&text),
+            [
+                "'/'",
+                "PathSegment",
+                "segment:PathSegment",
+                "'./'",
+                "'lex:base64url'",
+                "'lex:scheme'",
+                "AbsolutePath",
+                "RelativePath",
+                "Path",
+                "Url",
+            ]
+        );
+    }
+
+    #[test]
+    fn completes_inside_token_literals() {
+        let text = format!("{PATHS}Url = '.$0");
+        assert_eq!(labels(&text), ["'/'", "'./'", "'lex:base64url'"]);
+    }
+
+    #[test]
+    fn offers_new_nodes_at_line_start() {
+        let text = "A = B C\nB = 'b'\n$0";
+        assert_eq!(
+            labels(text),
+            ["C =", "new node", "new node with alternatives"]
+        );
+    }
+
+    #[test]
+    fn strips_snippets() {
+        assert_eq!(
+            strip_snippet("${1:Name} =\n  ${2:First}\n| '\\}' $0"),
+            "Name =\n  First\n| '}' "
+        );
+    }
+}
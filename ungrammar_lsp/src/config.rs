@@ -0,0 +1,194 @@
+//! Settings of the server, as the client sends them.
+//!
+//! The same object comes from `initializationOptions`, from the `ungrammar`
+//! section of `workspace/configuration` and from
+//! `workspace/didChangeConfiguration`:
+//!
+//! ```json
+//! {
+//!   "lints": { "unreachable-node": "error", "duplicate-rule": "off" },
+//!   "rootNodes": ["SourceFile"],
+//!   "format": { "alignRules": true, "indent": 2 },
+//!   "lexPrefix": "lex:"
+//! }
+//! ```
+//!
+//! `initializationOptions` apply to the defaults, and the settings from the
+//! client apply to those, each time in full: a setting the client stops
+//! sending goes back to what it was before.
+use std::collections::HashMap;
+
+use lsp_types::DiagnosticSeverity;
+use serde_json::Value;
+
+use crate::{diagnostics::DiagnosticExt, formatting::FormatStyle, lints::Lint};
+
+/// The section of `workspace/configuration` the settings live in.
+pub(crate) const SECTION: &str = "ungrammar";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Config {
+    /// Severities by lint code, `None` to turn a lint off. Lints that are not
+    /// listed keep their default severity.
+    pub(crate) lints: HashMap<String, Option<DiagnosticSeverity>>,
+    /// The nodes every other node should be reachable from. Without any,
+    /// reachability is not checked.
+    pub(crate) root_nodes: Vec<String>,
+    pub(crate) format: FormatStyle,
+    /// Token literals that start with this stand for what the lexer
+    /// produces, like `'lex:ident'`.
+    pub(crate) lex_prefix: String,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            lints: HashMap::new(),
+            root_nodes: Vec::new(),
+            format: FormatStyle::default(),
+            lex_prefix: "lex:".to_string(),
+        }
+    }
+}
+
+impl Config {
+    /// The severity to report `lint` with, or `None` if it is turned off.
+    pub(crate) fn severity(&self, lint: &Lint) -> Option<DiagnosticSeverity> {
+        match self.lints.get(lint.kind.code()) {
+            Some(severity) => *severity,
+            None => Some(lint.severity()),
+        }
+    }
+
+    /// Applies the settings in `value`. Returns what was wrong with it;
+    /// settings with wrong values are left alone.
+    pub(crate) fn update(&mut self, value: &Value) -> Vec<String> {
+        let mut errors = Vec::new();
+        let Some(object) = value.as_object() else {
+            if !value.is_null() {
+                errors.push(format!("expected an object for the settings, got {value}"));
+            }
+            return errors;
+        };
+        for (key, value) in object {
+            let res = match key.as_str() {
+                "lints" => self.update_lints(value),
+                "rootNodes" => string_list(value).map(|it| self.root_nodes = it),
+                "format" => self.update_format(value),
+                "lexPrefix" => match value.as_str() {
+                    Some(prefix) => {
+                        self.lex_prefix = prefix.to_string();
+                        Ok(())
+                    }
+                    None => Err(format!("expected a string, got {value}")),
+                },
+                _ => Err("unknown setting".to_string()),
+            };
+            if let Err(err) = res {
+                errors.push(format!("`{key}`: {err}"));
+            }
+        }
+        errors
+    }
+
+    fn update_lints(&mut self, value: &Value) -> Result<(), String> {
+        let object = value
+            .as_object()
+            .ok_or_else(|| format!("expected an object, got {value}"))?;
+        for (code, severity) in object {
+            let severity = match severity.as_str() {
+                Some("error") => Some(DiagnosticSeverity::ERROR),
+                Some("warning") => Some(DiagnosticSeverity::WARNING),
+                Some("info") => Some(DiagnosticSeverity::INFORMATION),
+                Some("hint") => Some(DiagnosticSeverity::HINT),
+                Some("off") => None,
+                _ => {
+                    return Err(format!(
+                        "expected one of \"error\", \"warning\", \"info\", \"hint\" or \"off\" for `{code}`, got {severity}"
+                    ))
+                }
+            };
+            self.lints.insert(code.clone(), severity);
+        }
+        Ok(())
+    }
+
+    fn update_format(&mut self, value: &Value) -> Result<(), String> {
+        let object = value
+            .as_object()
+            .ok_or_else(|| format!("expected an object, got {value}"))?;
+        for (key, value) in object {
+            match key.as_str() {
+                "alignRules" => {
+                    self.format.align_rules = value
+                        .as_bool()
+                        .ok_or_else(|| format!("expected a boolean for `{key}`, got {value}"))?
+                }
+                // Alternatives start with `| `, which needs two columns.
+                "indent" => {
+                    self.format.indent = value
+                        .as_u64()
+                        .filter(|it| (2..=8).contains(it))
+                        .ok_or_else(|| format!("expected 2 to 8 for `{key}`, got {value}"))?
+                        as usize
+                }
+                _ => return Err(format!("unknown setting `{key}`")),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn string_list(value: &Value) -> Result<Vec<String>, String> {
+    let error = || format!("expected a list of strings, got {value}");
+    value
+        .as_array()
+        .ok_or_else(error)?
+        .iter()
+        .map(|it| it.as_str().map(str::to_string).ok_or_else(error))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn updates_only_what_is_mentioned() {
+        let mut config = Config::default();
+        let errors = config.update(&json!({
+            "lints": { "unreachable-node": "error", "duplicate-rule": "off" },
+            "rootNodes": ["SourceFile"],
+            "format": { "indent": 4 },
+        }));
+        assert_eq!(errors, Vec::<String>::new());
+        assert_eq!(config.root_nodes, ["SourceFile"]);
+        assert_eq!(config.format.indent, 4);
+        assert!(config.format.align_rules);
+        assert_eq!(config.lex_prefix, "lex:");
+
+        let errors = config.update(&json!({
+            "lints": { "unreachable-node": "loud" },
+            "format": { "indent": 1 },
+            "lexPrefix": "lexer:",
+            "colour": "blue",
+        }));
+        assert_eq!(
+            errors,
+            [
+                "`colour`: unknown setting",
+                "`format`: expected 2 to 8 for `indent`, got 1",
+                "`lints`: expected one of \"error\", \"warning\", \"info\", \"hint\" or \"off\" for `unreachable-node`, got \"loud\"",
+            ]
+        );
+        assert_eq!(
+            config.lints["unreachable-node"],
+            Some(DiagnosticSeverity::ERROR)
+        );
+        assert_eq!(config.lints["duplicate-rule"], None);
+        assert_eq!(config.format.indent, 4);
+        assert_eq!(config.lex_prefix, "lexer:");
+    }
+}
@@ -0,0 +1,441 @@
+//! Incremental analysis of grammar files, on top of salsa.
+//!
+//! The query graph is
+//!
+//! ```text
+//! file_text -> rule_index -+-> file_rules -------------------------------+
+//!                          +-> rule_text -> parse_rule -+-> lower_rule ---+-> grammar -> lints
+//!                                                       +-> parse ----------------------/
+//! ```
+//!
+//! `rule_index` splits the file into rules and is cheap, so it runs on every
+//! change. Rules are identified by name (and occurrence, for duplicates)
+//! rather than by position, so `rule_text` comes out unchanged for every rule
+//! but the edited one, and salsa skips re-parsing and re-lowering those.
+//!
+//! Requests run on snapshots of the database in other threads. Changing an
+//! input waits for those snapshots to go away, so the queries they run stop
+//! early with [`Canceled`] once a change is pending.
+use std::{panic, sync::Arc};
+
+use rowan::{GreenNode, GreenToken, NodeOrToken, TextSize};
+
+use crate::{
+    config::Config,
+    dependencies::{self, NodeUse},
+    grammar::{self, ResolvedGrammar, RuleData},
+    line_index::{LineIndex, PositionEncoding},
+    lints::{self, Lint},
+    symbols::{self, FileSymbol},
+    syntax::{self, Parse, Piece, SyntaxKind},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct FileId(pub(crate) u32);
+
+/// Identity of a rule across edits: the file it lives in, its name and how
+/// many rules of the same name come before it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct RuleLoc {
+    pub(crate) file: FileId,
+    pub(crate) name: Option<String>,
+    pub(crate) nth: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct RuleId(salsa::InternId);
+
+impl salsa::InternKey for RuleId {
+    fn from_intern_id(v: salsa::InternId) -> Self {
+        RuleId(v)
+    }
+    fn as_intern_id(&self) -> salsa::InternId {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Segment {
+    Trivia(SyntaxKind, String),
+    Rule(RuleId),
+}
+
+/// A file split into rules, see [`syntax::split_rules`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RuleIndex {
+    pub(crate) segments: Vec<Segment>,
+    texts: Vec<(RuleId, Arc<str>)>,
+}
+
+impl RuleIndex {
+    pub(crate) fn rules(&self) -> impl Iterator<Item = RuleId> + '_ {
+        self.texts.iter().map(|(rule, _)| *rule)
+    }
+}
+
+/// The payload of the panic that unwinds a query whose inputs are about to
+/// change. Its result would be stale anyway.
+#[derive(Debug)]
+pub(crate) struct Canceled;
+
+impl Canceled {
+    /// Unwinds without going through the panic hook, which would print it.
+    pub(crate) fn throw() -> ! {
+        panic::resume_unwind(Box::new(Canceled))
+    }
+}
+
+/// Stops the current query if the database is about to change.
+pub(crate) fn check_canceled(db: &dyn SourceDatabase) {
+    if db.salsa_runtime().is_current_revision_canceled() {
+        Canceled::throw()
+    }
+}
+
+#[salsa::query_group(SourceDatabaseStorage)]
+pub(crate) trait SourceDatabase: salsa::Database {
+    #[salsa::input]
+    fn file_text(&self, file: FileId) -> Arc<String>;
+
+    /// How the client counts characters, see [`LineIndex`].
+    #[salsa::input]
+    fn position_encoding(&self) -> PositionEncoding;
+
+    /// See [`Config::lex_prefix`](crate::config::Config::lex_prefix).
+    #[salsa::input]
+    fn lex_prefix(&self) -> Arc<str>;
+
+    #[salsa::interned]
+    fn intern_rule(&self, loc: RuleLoc) -> RuleId;
+
+    fn rule_index(&self, file: FileId) -> Arc<RuleIndex>;
+
+    /// The rules of a file, in order. Unlike [`rule_index`], this does not
+    /// change when only the text of rules does.
+    ///
+    /// [`rule_index`]: SourceDatabase::rule_index
+    fn file_rules(&self, file: FileId) -> Arc<Vec<RuleId>>;
+
+    fn rule_text(&self, rule: RuleId) -> Arc<str>;
+
+    /// Parses a single rule. Ranges are relative to the start of the rule.
+    fn parse_rule(&self, rule: RuleId) -> Parse;
+
+    /// The syntax tree of a whole file, assembled from its rules.
+    fn parse(&self, file: FileId) -> Parse;
+
+    fn line_index(&self, file: FileId) -> Arc<LineIndex>;
+
+    /// The nodes a file defines, for searching the whole workspace.
+    #[salsa::invoke(symbols::file_symbols)]
+    fn file_symbols(&self, file: FileId) -> Arc<Vec<FileSymbol>>;
+
+    /// The nodes a file mentions, for finding what depends on a node.
+    #[salsa::invoke(dependencies::node_uses)]
+    fn node_uses(&self, file: FileId) -> Arc<Vec<NodeUse>>;
+}
+
+#[salsa::query_group(GrammarDatabaseStorage)]
+pub(crate) trait GrammarDatabase: SourceDatabase {
+    /// See [`Config::root_nodes`](crate::config::Config::root_nodes).
+    #[salsa::input]
+    fn root_nodes(&self) -> Arc<Vec<String>>;
+
+    fn lower_rule(&self, rule: RuleId) -> Arc<RuleData>;
+
+    fn grammar(&self, file: FileId) -> Arc<ResolvedGrammar>;
+
+    #[salsa::invoke(lints::lints)]
+    fn lints(&self, file: FileId) -> Arc<Vec<Lint>>;
+}
+
+fn rule_index(db: &dyn SourceDatabase, file: FileId) -> Arc<RuleIndex> {
+    let text = db.file_text(file);
+    let mut segments = Vec::new();
+    let mut texts = Vec::new();
+    let mut seen: Vec<Option<&str>> = Vec::new();
+    for piece in syntax::split_rules(&text) {
+        match piece {
+            Piece::Trivia(kind, text) => segments.push(Segment::Trivia(kind, text.to_string())),
+            Piece::Rule { name, text } => {
+                let nth = seen.iter().filter(|&&it| it == name).count();
+                seen.push(name);
+                let rule = db.intern_rule(RuleLoc {
+                    file,
+                    name: name.map(str::to_string),
+                    nth,
+                });
+                segments.push(Segment::Rule(rule));
+                texts.push((rule, Arc::from(text)));
+            }
+        }
+    }
+    Arc::new(RuleIndex { segments, texts })
+}
+
+fn file_rules(db: &dyn SourceDatabase, file: FileId) -> Arc<Vec<RuleId>> {
+    Arc::new(db.rule_index(file).rules().collect())
+}
+
+fn rule_text(db: &dyn SourceDatabase, rule: RuleId) -> Arc<str> {
+    let index = db.rule_index(db.lookup_intern_rule(rule).file);
+    index
+        .texts
+        .iter()
+        .find(|(it, _)| *it == rule)
+        .map(|(_, text)| text.clone())
+        .unwrap_or_else(|| Arc::from(""))
+}
+
+fn parse_rule(db: &dyn SourceDatabase, rule: RuleId) -> Parse {
+    check_canceled(db);
+    syntax::parse_rule(&db.rule_text(rule))
+}
+
+fn parse(db: &dyn SourceDatabase, file: FileId) -> Parse {
+    let index = db.rule_index(file);
+    let mut children = Vec::with_capacity(index.segments.len());
+    let mut errors = Vec::new();
+    let mut offset = TextSize::default();
+    for segment in &index.segments {
+        match segment {
+            Segment::Trivia(kind, text) => {
+                offset += TextSize::of(text.as_str());
+                children.push(NodeOrToken::Token(GreenToken::new((*kind).into(), text)));
+            }
+            Segment::Rule(rule) => {
+                let parse = db.parse_rule(*rule);
+                errors.extend(parse.errors.iter().map(|it| it.shifted(offset)));
+                offset += parse.green.text_len();
+                children.push(NodeOrToken::Node(parse.green));
+            }
+        }
+    }
+    Parse {
+        green: GreenNode::new(SyntaxKind::SOURCE_FILE.into(), children),
+        errors: Arc::new(errors),
+    }
+}
+
+fn line_index(db: &dyn SourceDatabase, file: FileId) -> Arc<LineIndex> {
+    Arc::new(LineIndex::new(&db.file_text(file), db.position_encoding()))
+}
+
+fn lower_rule(db: &dyn GrammarDatabase, rule: RuleId) -> Arc<RuleData> {
+    check_canceled(db);
+    Arc::new(grammar::lower(&db.parse_rule(rule)))
+}
+
+fn grammar(db: &dyn GrammarDatabase, file: FileId) -> Arc<ResolvedGrammar> {
+    let rules: Vec<_> = db
+        .file_rules(file)
+        .iter()
+        .map(|&it| db.lower_rule(it))
+        .collect();
+    Arc::new(grammar::resolve(&rules))
+}
+
+#[salsa::database(SourceDatabaseStorage, GrammarDatabaseStorage)]
+pub(crate) struct RootDatabase {
+    storage: salsa::Storage<RootDatabase>,
+    /// Queries that were executed, rather than served from memos.
+    #[cfg(test)]
+    executed: std::sync::Mutex<Vec<String>>,
+}
+
+impl salsa::Database for RootDatabase {
+    /// Another thread panicked while computing a query this one waited for:
+    /// it was most likely canceled too.
+    fn on_propagated_panic(&self) -> ! {
+        Canceled::throw()
+    }
+
+    #[cfg(test)]
+    fn salsa_event(&self, event: salsa::Event) {
+        if let salsa::EventKind::WillExecute { database_key } = event.kind {
+            let query = format!("{:?}", database_key.debug(self));
+            self.executed.lock().unwrap().push(query);
+        }
+    }
+}
+
+impl Default for RootDatabase {
+    fn default() -> RootDatabase {
+        let mut db = RootDatabase {
+            storage: Default::default(),
+            #[cfg(test)]
+            executed: Default::default(),
+        };
+        let config = Config::default();
+        db.set_position_encoding(PositionEncoding::default());
+        db.set_lex_prefix(Arc::from(config.lex_prefix));
+        db.set_root_nodes(Arc::new(config.root_nodes));
+        db
+    }
+}
+
+impl salsa::ParallelDatabase for RootDatabase {
+    fn snapshot(&self) -> salsa::Snapshot<RootDatabase> {
+        salsa::Snapshot::new(RootDatabase {
+            storage: self.storage.snapshot(),
+            #[cfg(test)]
+            executed: Default::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lints::LintKind;
+
+    const TEXT: &str = "\
+// Paths
+Path = AbsolutePath | RelativePath
+AbsolutePath = ('/' segment:PathSegment)* '/'?
+RelativePath = './'? segment:PathSegment ('/' PathSegment)*
+PathSegment = 'lex:base64url'
+";
+
+    impl RootDatabase {
+        /// Computes the lints of `file` and returns how many times
+        /// `parse_rule`, `lower_rule`, `grammar` and `lints` were executed.
+        fn executions(&self, file: FileId) -> [usize; 4] {
+            self.executed.lock().unwrap().clear();
+            self.lints(file);
+            let executed = std::mem::take(&mut *self.executed.lock().unwrap());
+            ["parse_rule", "lower_rule", "grammar", "lints"].map(|query| {
+                let prefix = format!("{query}(");
+                executed.iter().filter(|it| it.starts_with(&prefix)).count()
+            })
+        }
+    }
+
+    fn edit(db: &mut RootDatabase, file: FileId, from: &str, to: &str) {
+        let text = db.file_text(file).replacen(from, to, 1);
+        db.set_file_text(file, Arc::new(text));
+    }
+
+    #[test]
+    fn editing_a_rule_reparses_only_that_rule() {
+        let mut db = RootDatabase::default();
+        let file = FileId(0);
+        db.set_file_text(file, Arc::new(TEXT.to_string()));
+        assert_eq!(db.executions(file), [4, 4, 1, 1]);
+        assert!(db.grammar(file).grammar.is_some());
+
+        // Change the meaning of one rule.
+        edit(&mut db, file, "'/'?", "'/'* '.'");
+        assert_eq!(db.executions(file), [1, 1, 1, 1]);
+
+        // Whitespace-only edits re-parse the rule but stop there.
+        edit(
+            &mut db,
+            file,
+            "Path = AbsolutePath",
+            "Path =   AbsolutePath",
+        );
+        assert_eq!(db.executions(file), [1, 1, 0, 1]);
+
+        // Edits to comments between rules touch no rule at all.
+        edit(&mut db, file, "// Paths", "// All the paths");
+        assert_eq!(db.executions(file), [0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn lints_point_at_problems() {
+        let mut db = RootDatabase::default();
+        let file = FileId(0);
+        let text = "A = B C\nA = 'a'\n";
+        db.set_file_text(file, Arc::new(text.to_string()));
+        let lints = db.lints(file);
+        let found: Vec<_> = lints.iter().map(|it| (it.kind, &text[it.range])).collect();
+        assert_eq!(
+            found,
+            [
+                (LintKind::UndefinedNode, "B"),
+                (LintKind::UndefinedNode, "C"),
+                (LintKind::DuplicateRule, "A"),
+            ]
+        );
+        assert_eq!(&text[lints[2].related[0].0], "A");
+        assert!(db.grammar(file).grammar.is_none());
+    }
+
+    #[test]
+    fn unreachable_lints_follow_the_roots() {
+        let mut db = RootDatabase::default();
+        let file = FileId(0);
+        let text = "Root = A\nA = 'a' A\nB = C\nC = 'c'\n";
+        db.set_file_text(file, Arc::new(text.to_string()));
+        assert_eq!(*db.lints(file), []);
+
+        db.set_root_nodes(Arc::new(vec!["Root".to_string()]));
+        let lints = db.lints(file);
+        let found: Vec<_> = lints.iter().map(|it| &text[it.range]).collect();
+        assert_eq!(found, ["B", "C"]);
+        assert_eq!(lints[0].message, "unreachable node: `B`");
+
+        // Grammars without any of the roots are not checked.
+        db.set_root_nodes(Arc::new(vec!["SourceFile".to_string()]));
+        assert_eq!(*db.lints(file), []);
+    }
+
+    #[test]
+    fn precedence_lints() {
+        let mut db = RootDatabase::default();
+        let file = FileId(0);
+        let text = "\
+E = 'int' | E op:('+' | '*' | '-') E
+%precedence E
+  %left '+' '+' '+'
+  %left '/' '/'
+";
+        db.set_file_text(file, Arc::new(text.to_string()));
+        let lints = db.lints(file);
+        let found: Vec<_> = lints
+            .iter()
+            .map(|it| (&text[it.range], it.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("E", "operator '*' of `E` has no precedence level"),
+                ("E", "operator '-' of `E` has no precedence level"),
+                (
+                    "'+'",
+                    "operator '+' of `E` has more than one precedence level"
+                ),
+                (
+                    "'+'",
+                    "operator '+' of `E` has more than one precedence level"
+                ),
+                ("'/'", "'/' is not an operator of `E`"),
+                ("'/'", "'/' is not an operator of `E`"),
+            ]
+        );
+        // One lint for every listing after the first.
+        let starts: Vec<_> = lints.iter().map(|it| u32::from(it.range.start())).collect();
+        assert_eq!(starts, [49, 49, 63, 67, 79, 83]);
+        assert!(lints.iter().all(|it| it.kind == LintKind::Precedence));
+        assert!(db.grammar(file).grammar.is_none());
+    }
+
+    #[test]
+    fn repository_grammars_resolve() {
+        let mut db = RootDatabase::default();
+        let grammars = [
+            include_str!("../../ungrammar_fork/rust.ungram"),
+            include_str!("../../ungrammar_fork/ungrammar.ungram"),
+            include_str!("../../markup_ungrams/zork_keg.ungram"),
+        ];
+        for (idx, text) in grammars.into_iter().enumerate() {
+            let file = FileId(idx as u32);
+            db.set_file_text(file, Arc::new(text.to_string()));
+            assert_eq!(*db.parse(file).errors, []);
+            assert_eq!(db.parse(file).syntax_node().to_string(), text);
+            let expected: ungrammar_fork::Grammar = text.parse().unwrap();
+            assert_eq!(db.grammar(file).grammar.as_deref(), Some(&expected));
+        }
+    }
+}
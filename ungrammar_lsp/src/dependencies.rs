@@ -0,0 +1,76 @@
+//! Which rules mention which nodes, for code lenses that count them and for
+//! the call hierarchy, which walks them as "used by" and "uses" edges.
+use std::sync::Arc;
+
+use rowan::TextRange;
+
+use crate::{
+    db::{FileId, SourceDatabase},
+    syntax::ast,
+};
+
+/// A mention of a node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct NodeUse {
+    /// The node mentioned.
+    pub(crate) node: String,
+    pub(crate) range: TextRange,
+    /// The name of the rule the mention is in, `None` for a `%precedence`
+    /// declaration.
+    pub(crate) rule: Option<TextRange>,
+}
+
+/// The nodes `file` mentions, in order.
+pub(crate) fn node_uses(db: &dyn SourceDatabase, file: FileId) -> Arc<Vec<NodeUse>> {
+    let root = db.parse(file).syntax_node();
+    let res = root
+        .descendants()
+        .filter_map(ast::NodeRef::cast)
+        .filter_map(|node_ref| {
+            let name = node_ref.name()?;
+            let rule = node_ref
+                .syntax
+                .ancestors()
+                .find_map(ast::Rule::cast)
+                .and_then(|it| it.name())
+                .map(|it| it.text_range());
+            Some(NodeUse {
+                node: name.text().to_string(),
+                range: name.text_range(),
+                rule,
+            })
+        })
+        .collect();
+    Arc::new(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::RootDatabase;
+
+    #[test]
+    fn finds_the_rule_of_every_mention() {
+        let mut db = RootDatabase::default();
+        let text = "A = B | C\nB = A 'b' A\n%precedence B %left 'b'\n";
+        db.set_file_text(FileId(0), Arc::new(text.to_string()));
+        let node_uses = db.node_uses(FileId(0));
+        let found: Vec<_> = node_uses
+            .iter()
+            .map(|it| {
+                let rule = it.rule.map(|it| &text[it]);
+                (it.node.as_str(), usize::from(it.range.start()), rule)
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("B", 4, Some("A")),
+                ("C", 8, Some("A")),
+                ("A", 14, Some("B")),
+                ("A", 20, Some("B")),
+                ("B", 34, None),
+            ]
+        );
+    }
+}
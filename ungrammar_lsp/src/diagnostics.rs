@@ -0,0 +1,163 @@
+//! Conversion of syntax errors and lints into LSP diagnostics.
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use lsp_types::{
+    Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, Location, NumberOrString, Url,
+};
+use rowan::TextRange;
+
+use crate::{
+    config::Config,
+    db::{FileId, GrammarDatabase},
+    line_index::LineIndex,
+    lints::{Lint, LintKind},
+    playground::{self, SampleError},
+    syntax::SyntaxError,
+};
+
+/// What is wrong with `file`, in order, with lints as severe as `config`
+/// says. Samples are checked against their grammar in `files`.
+pub(crate) fn file_diagnostics(
+    db: &dyn GrammarDatabase,
+    config: &Config,
+    uri: &Url,
+    file: FileId,
+    files: &HashMap<Url, FileId>,
+) -> Vec<Diagnostic> {
+    let line_index = db.line_index(file);
+    let source = Some("ungrammar_lsp".to_string());
+    if playground::is_sample(uri) {
+        return playground::check_sample(db, uri, file, files)
+            .errors
+            .into_iter()
+            .map(|err| err.into_lsp_diagnostic(uri, &line_index, source.clone()))
+            .collect();
+    }
+    let syntax_errors = db
+        .parse(file)
+        .errors
+        .iter()
+        .cloned()
+        .map(|err| err.into_lsp_diagnostic(uri, &line_index, source.clone()))
+        .collect::<Vec<_>>();
+    let lints = db
+        .lints(file)
+        .iter()
+        .cloned()
+        .filter_map(|lint| {
+            let severity = config.severity(&lint)?;
+            let mut diagnostic = lint.into_lsp_diagnostic(uri, &line_index, source.clone());
+            diagnostic.severity = Some(severity);
+            Some(diagnostic)
+        })
+        .collect::<Vec<_>>();
+
+    let mut diagnostics = [syntax_errors, lints].concat();
+    diagnostics.sort_by_key(|it| (it.range.start.line, it.range.start.character));
+    diagnostics
+}
+
+/// Names a set of diagnostics, so that a client that pulls them can be told
+/// when they did not change.
+pub(crate) fn result_id(diagnostics: &[Diagnostic]) -> String {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_string(diagnostics)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+pub(crate) trait DiagnosticExt {
+    fn range(&self) -> TextRange;
+    fn msg(&self) -> String;
+    fn code(&self) -> Option<&'static str> {
+        None
+    }
+    fn related(&self) -> Vec<(TextRange, String)> {
+        Vec::new()
+    }
+    fn severity(&self) -> DiagnosticSeverity {
+        DiagnosticSeverity::ERROR
+    }
+    fn into_lsp_diagnostic(
+        self,
+        uri: &Url,
+        line_index: &LineIndex,
+        source: Option<String>,
+    ) -> Diagnostic
+    where
+        Self: Sized,
+    {
+        let related = self.related();
+        Diagnostic {
+            range: line_index.range(self.range()),
+            message: self.msg(),
+            code: self.code().map(|it| NumberOrString::String(it.into())),
+            severity: Some(self.severity()),
+            source,
+            related_information: (!related.is_empty()).then(|| {
+                related
+                    .into_iter()
+                    .map(|(range, message)| DiagnosticRelatedInformation {
+                        location: Location::new(uri.clone(), line_index.range(range)),
+                        message,
+                    })
+                    .collect()
+            }),
+
+            code_description: Default::default(),
+            tags: Default::default(),
+            data: Default::default(),
+        }
+    }
+}
+
+impl DiagnosticExt for SyntaxError {
+    fn range(&self) -> TextRange {
+        self.range
+    }
+
+    fn msg(&self) -> String {
+        self.message.clone()
+    }
+}
+
+impl DiagnosticExt for SampleError {
+    fn range(&self) -> TextRange {
+        self.range
+    }
+
+    fn msg(&self) -> String {
+        self.message.clone()
+    }
+}
+
+impl DiagnosticExt for Lint {
+    fn range(&self) -> TextRange {
+        self.range
+    }
+
+    fn msg(&self) -> String {
+        self.message.clone()
+    }
+
+    fn code(&self) -> Option<&'static str> {
+        Some(self.kind.code())
+    }
+
+    fn related(&self) -> Vec<(TextRange, String)> {
+        self.related.clone()
+    }
+
+    /// Undefined nodes and duplicate rules keep the grammar from loading, so
+    /// they are errors. The rest are warnings.
+    fn severity(&self) -> DiagnosticSeverity {
+        match self.kind {
+            LintKind::UndefinedNode | LintKind::DuplicateRule => DiagnosticSeverity::ERROR,
+            LintKind::Precedence | LintKind::UnreachableNode => DiagnosticSeverity::WARNING,
+        }
+    }
+}
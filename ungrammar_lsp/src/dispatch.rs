@@ -0,0 +1,124 @@
+//! Routing of requests to their handlers.
+//!
+//! Handlers that only read run on the task pool against a snapshot of the
+//! state. Their responses go back through the main loop, which drops the
+//! ones that were canceled and turns the ones computed from outdated text
+//! into `ContentModified` errors.
+use std::panic::{self, AssertUnwindSafe};
+
+use lsp_server::{ErrorCode, ExtractError, Request as RequestData, RequestId, Response};
+use lsp_types::request::Request;
+
+use crate::{db::Canceled, handlers, task_pool::TaskPool, State, StateSnapshot};
+
+pub(crate) struct RequestDispatcher<'a> {
+    req: Option<RequestData>,
+    state: &'a mut State,
+    pool: &'a TaskPool<Response>,
+    response: Option<Response>,
+}
+
+impl<'a> RequestDispatcher<'a> {
+    pub(crate) fn new(
+        req: RequestData,
+        state: &'a mut State,
+        pool: &'a TaskPool<Response>,
+    ) -> RequestDispatcher<'a> {
+        RequestDispatcher {
+            req: Some(req),
+            state,
+            pool,
+            response: None,
+        }
+    }
+
+    /// Runs the handler on the task pool.
+    pub(crate) fn on<R: Request>(
+        &mut self,
+        f: fn(&StateSnapshot, R::Params) -> handlers::Result<R::Result>,
+    ) -> &mut Self
+    where
+        R::Params: Send + 'static,
+        R::Result: 'static,
+    {
+        let Some((id, params)) = self.parse::<R>() else {
+            return self;
+        };
+        let snap = self.state.snapshot();
+        self.state.pending.insert(id.clone(), self.state.revision);
+        self.pool.spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| f(&snap, params)));
+            match result {
+                Ok(result) => result_to_response::<R>(id, result),
+                Err(payload) if payload.is::<Canceled>() => content_modified(id),
+                Err(payload) => {
+                    let message = match payload.downcast_ref::<&str>() {
+                        Some(it) => it.to_string(),
+                        None => payload
+                            .downcast_ref::<String>()
+                            .cloned()
+                            .unwrap_or_default(),
+                    };
+                    let message = format!("{} panicked: {message}", R::METHOD);
+                    Response::new_err(id, ErrorCode::InternalError as i32, message)
+                }
+            }
+        });
+        self
+    }
+
+    /// The response to send right away, as handlers answer later: an error
+    /// if the params of the request were invalid or no handler took it.
+    pub(crate) fn finish(&mut self) -> Option<Response> {
+        if let Some(req) = self.req.take() {
+            log::warn!("unknown request {:?}", req.method);
+            let message = format!("unknown request {:?}", req.method);
+            return Some(Response::new_err(
+                req.id,
+                ErrorCode::MethodNotFound as i32,
+                message,
+            ));
+        }
+        self.response.take()
+    }
+
+    fn parse<R: Request>(&mut self) -> Option<(RequestId, R::Params)> {
+        if self.req.as_ref()?.method != R::METHOD {
+            return None;
+        }
+        let req = self.req.take()?;
+        let id = req.id.clone();
+        match req.extract(R::METHOD) {
+            Ok(it) => Some(it),
+            Err(ExtractError::JsonError { method, error }) => {
+                let message = format!("invalid parameters for {method}: {error}");
+                self.response = Some(Response::new_err(
+                    id,
+                    ErrorCode::InvalidParams as i32,
+                    message,
+                ));
+                None
+            }
+            Err(ExtractError::MethodMismatch(_)) => unreachable!(),
+        }
+    }
+}
+
+fn result_to_response<R: Request>(id: RequestId, result: handlers::Result<R::Result>) -> Response {
+    match result.and_then(|it| Ok(serde_json::to_value(it)?)) {
+        Ok(value) => Response::new_ok(id, value),
+        Err(err) => {
+            let code = match err.downcast_ref::<handlers::LspError>() {
+                Some(err) => err.code,
+                None => ErrorCode::InternalError,
+            };
+            Response::new_err(id, code as i32, err.to_string())
+        }
+    }
+}
+
+/// The answer to a request whose result would describe text that is gone.
+pub(crate) fn content_modified(id: RequestId) -> Response {
+    let message = "the document changed while the request ran".to_string();
+    Response::new_err(id, ErrorCode::ContentModified as i32, message)
+}
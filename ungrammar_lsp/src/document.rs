@@ -0,0 +1,179 @@
+//! Keeps track of the documents the client has open.
+//!
+//! While a document is open the client owns its contents: the server never
+//! reads it from disk, which also makes unsaved edits and `untitled:` buffers
+//! work. The client sends the full text on `didOpen` and then only the edited
+//! ranges on `didChange`.
+use std::{collections::HashMap, fmt};
+
+use lsp_types::{TextDocumentContentChangeEvent, Url};
+
+use crate::line_index::{LineIndex, PositionEncoding};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Document {
+    pub(crate) version: i32,
+    pub(crate) text: String,
+}
+
+impl Document {
+    /// Applies `changes` in order, each one to the result of the previous.
+    fn apply(&mut self, changes: Vec<TextDocumentContentChangeEvent>, encoding: PositionEncoding) {
+        for change in changes {
+            match change.range {
+                Some(range) => {
+                    let line_index = LineIndex::new(&self.text, encoding);
+                    let start = line_index.offset(range.start);
+                    let end = line_index.offset(range.end).max(start);
+                    self.text
+                        .replace_range(usize::from(start)..usize::from(end), &change.text);
+                }
+                None => self.text = change.text,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum DocumentError {
+    NotOpen(Url),
+    AlreadyOpen(Url),
+}
+
+impl fmt::Display for DocumentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DocumentError::NotOpen(uri) => write!(f, "document {uri} is not open"),
+            DocumentError::AlreadyOpen(uri) => write!(f, "document {uri} is already open"),
+        }
+    }
+}
+
+impl std::error::Error for DocumentError {}
+
+#[derive(Debug, Default)]
+pub(crate) struct DocumentManager {
+    documents: HashMap<Url, Document>,
+    /// How the ranges of changes count characters.
+    encoding: PositionEncoding,
+}
+
+impl DocumentManager {
+    pub(crate) fn new(encoding: PositionEncoding) -> DocumentManager {
+        DocumentManager {
+            documents: HashMap::new(),
+            encoding,
+        }
+    }
+
+    pub(crate) fn open(
+        &mut self,
+        uri: Url,
+        version: i32,
+        text: String,
+    ) -> Result<&Document, DocumentError> {
+        if self.documents.contains_key(&uri) {
+            return Err(DocumentError::AlreadyOpen(uri));
+        }
+        Ok(self
+            .documents
+            .entry(uri)
+            .or_insert(Document { version, text }))
+    }
+
+    pub(crate) fn change(
+        &mut self,
+        uri: &Url,
+        version: i32,
+        changes: Vec<TextDocumentContentChangeEvent>,
+    ) -> Result<&Document, DocumentError> {
+        let document = self
+            .documents
+            .get_mut(uri)
+            .ok_or_else(|| DocumentError::NotOpen(uri.clone()))?;
+        if version <= document.version {
+            log::warn!(
+                "version of {uri} went from {} to {version}",
+                document.version
+            );
+        }
+        document.apply(changes, self.encoding);
+        document.version = version;
+        Ok(document)
+    }
+
+    pub(crate) fn close(&mut self, uri: &Url) -> Result<Document, DocumentError> {
+        self.documents
+            .remove(uri)
+            .ok_or_else(|| DocumentError::NotOpen(uri.clone()))
+    }
+
+    pub(crate) fn get(&self, uri: &Url) -> Option<&Document> {
+        self.documents.get(uri)
+    }
+
+    /// The version of every open document.
+    pub(crate) fn versions(&self) -> impl Iterator<Item = (&Url, i32)> {
+        self.documents.iter().map(|(uri, it)| (uri, it.version))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{Position, Range};
+
+    use super::*;
+
+    fn edit(range: ((u32, u32), (u32, u32)), text: &str) -> TextDocumentContentChangeEvent {
+        let ((l1, c1), (l2, c2)) = range;
+        TextDocumentContentChangeEvent {
+            range: Some(Range::new(Position::new(l1, c1), Position::new(l2, c2))),
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn applies_incremental_changes_in_order() {
+        let mut documents = DocumentManager::default();
+        let uri = Url::parse("untitled:Untitled-1").unwrap();
+        documents
+            .open(uri.clone(), 1, "A = 'é' B\nB = 'b'\n".to_string())
+            .unwrap();
+        let changes = vec![
+            // Replace `B` after the two-byte `é`, which is one UTF-16 unit.
+            edit(((0, 8), (0, 9)), "C"),
+            // Then rename the second rule, relying on the first edit.
+            edit(((1, 0), (1, 1)), "C"),
+            // Insert a new line at the very end.
+            edit(((2, 0), (2, 0)), "D = C\n"),
+        ];
+        let document = documents.change(&uri, 2, changes).unwrap();
+        assert_eq!(document.text, "A = 'é' C\nC = 'b'\nD = C\n");
+        assert_eq!(document.version, 2);
+
+        let full = TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: "E = 'e'".to_string(),
+        };
+        assert_eq!(
+            documents.change(&uri, 3, vec![full]).unwrap().text,
+            "E = 'e'"
+        );
+    }
+
+    #[test]
+    fn tracks_open_documents() {
+        let mut documents = DocumentManager::default();
+        let uri = Url::parse("file:///tmp/a.ungram").unwrap();
+        assert_eq!(
+            documents.change(&uri, 1, Vec::new()),
+            Err(DocumentError::NotOpen(uri.clone()))
+        );
+        documents.open(uri.clone(), 1, String::new()).unwrap();
+        assert!(documents.open(uri.clone(), 1, String::new()).is_err());
+        assert_eq!(documents.close(&uri).unwrap().version, 1);
+        assert!(documents.get(&uri).is_none());
+    }
+}
@@ -0,0 +1,106 @@
+//! What a grammar file folds into: rules and blocks of comments.
+use rowan::{NodeOrToken, TextRange};
+
+use crate::{
+    db::{FileId, SourceDatabase},
+    syntax::{SyntaxKind::*, SyntaxToken},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FoldKind {
+    /// A rule or a `%precedence` declaration.
+    Rule,
+    /// Comment lines one right after the other.
+    Comment,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Fold {
+    pub(crate) range: TextRange,
+    pub(crate) kind: FoldKind,
+}
+
+/// The folds of `file`, in order. Whether a fold spans more than one line
+/// is left to the caller, which knows about lines.
+pub(crate) fn folds(db: &dyn SourceDatabase, file: FileId) -> Vec<Fold> {
+    let root = db.parse(file).syntax_node();
+    let mut res = Vec::new();
+    let mut block: Option<TextRange> = None;
+    for element in root.descendants_with_tokens() {
+        let token = match element {
+            NodeOrToken::Node(node) => {
+                if matches!(node.kind(), RULE | PRECEDENCE) {
+                    res.push(Fold {
+                        range: node.text_range(),
+                        kind: FoldKind::Rule,
+                    });
+                }
+                continue;
+            }
+            NodeOrToken::Token(token) => token,
+        };
+        match token.kind() {
+            COMMENT if starts_line(&token) => {
+                block = Some(match block {
+                    Some(range) => range.cover(token.text_range()),
+                    None => token.text_range(),
+                });
+            }
+            // A single line break keeps the block going.
+            WHITESPACE if token.text().matches('\n').count() <= 1 => (),
+            _ => {
+                if let Some(range) = block.take() {
+                    res.push(Fold {
+                        range,
+                        kind: FoldKind::Comment,
+                    });
+                }
+            }
+        }
+    }
+    if let Some(range) = block {
+        res.push(Fold {
+            range,
+            kind: FoldKind::Comment,
+        });
+    }
+    res.sort_by_key(|it| it.range.start());
+    res
+}
+
+/// Whether only whitespace is before `token` on its line.
+fn starts_line(token: &SyntaxToken) -> bool {
+    match token.prev_token() {
+        Some(prev) => prev.kind() == WHITESPACE && prev.text().contains('\n'),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::db::RootDatabase;
+
+    #[test]
+    fn folds_rules_and_comment_blocks() {
+        let mut db = RootDatabase::default();
+        let text = "// A file\n// header\n\n// Doc\nA =\n  B // why\n  // inner\n| C\n\nB = 'b'\n";
+        db.set_file_text(FileId(0), Arc::new(text.to_string()));
+        let found: Vec<_> = folds(&db, FileId(0))
+            .into_iter()
+            .map(|it| (it.kind, &text[it.range]))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (FoldKind::Comment, "// A file\n// header"),
+                (FoldKind::Comment, "// Doc"),
+                (FoldKind::Rule, "A =\n  B // why\n  // inner\n| C"),
+                (FoldKind::Comment, "// inner"),
+                (FoldKind::Rule, "B = 'b'"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,436 @@
+//! Formatting grammars into a canonical layout.
+//!
+//! Rules are grouped in paragraphs separated by blank lines, and the `=` of
+//! all rules in a paragraph are aligned. A rule with alternatives gets one
+//! line per alternative, each but the first starting with `|`:
+//!
+//! ```text
+//! Path         =
+//!   AbsolutePath
+//! | RelativePath
+//! AbsolutePath = ('/' segment:PathSegment)* '/'?
+//! ```
+//!
+//! Comments stay where they are: a comment that ends a line keeps ending the
+//! line of the same alternative, and a comment on its own line stays above
+//! what it was above.
+use std::fmt;
+
+use rowan::{NodeOrToken, TextRange, TextSize};
+
+use crate::{
+    db::{FileId, SourceDatabase},
+    printer::print_expr,
+    syntax::{ast, SyntaxKind::*, SyntaxNode, SyntaxToken},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FormatStyle {
+    /// Whether to line up the `=` of the rules in a paragraph.
+    pub(crate) align_rules: bool,
+    /// How far alternatives and comments inside rules are indented. The `|`
+    /// of alternatives goes two columns before the alternative.
+    pub(crate) indent: usize,
+}
+
+impl Default for FormatStyle {
+    fn default() -> FormatStyle {
+        FormatStyle {
+            align_rules: true,
+            indent: 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FormatError(String);
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+/// Computes the edits that format `file`, or only the paragraphs that
+/// intersect `range`.
+pub(crate) fn format(
+    db: &dyn SourceDatabase,
+    file: FileId,
+    range: Option<TextRange>,
+    style: &FormatStyle,
+) -> Result<Vec<(TextRange, String)>, FormatError> {
+    let parse = db.parse(file);
+    if let Some(error) = parse.errors.first() {
+        let line = db.line_index(file).position(error.range.start()).line + 1;
+        return Err(FormatError(format!(
+            "cannot format a grammar with syntax errors (line {line}: {})",
+            error.message
+        )));
+    }
+    let root = parse.syntax_node();
+    let paragraphs = paragraphs(&root);
+    let Some(range) = range else {
+        let text = root.to_string();
+        let mut formatted: Vec<_> = paragraphs.iter().map(|it| it.format(style)).collect();
+        if formatted.is_empty() {
+            return Ok(Vec::new());
+        }
+        formatted.last_mut().unwrap().push('\n');
+        let formatted = formatted.join("\n\n");
+        if formatted == text {
+            return Ok(Vec::new());
+        }
+        return Ok(vec![(root.text_range(), formatted)]);
+    };
+    let mut res = Vec::new();
+    for paragraph in &paragraphs {
+        let p_range = paragraph.range();
+        if p_range.end() < range.start() || range.end() < p_range.start() {
+            continue;
+        }
+        let formatted = paragraph.format(style);
+        if formatted != root.text().slice(p_range).to_string() {
+            res.push((p_range, formatted));
+        }
+    }
+    Ok(res)
+}
+
+/// A rule, a precedence declaration or a comment, with the comment that
+/// trails it on the same line.
+struct Entry {
+    element: NodeOrToken<SyntaxNode, SyntaxToken>,
+    trailing: Option<SyntaxToken>,
+}
+
+/// Entries not separated by blank lines.
+struct Paragraph(Vec<Entry>);
+
+fn paragraphs(root: &SyntaxNode) -> Vec<Paragraph> {
+    let mut res: Vec<Paragraph> = Vec::new();
+    let mut newlines = None;
+    for element in root.children_with_tokens() {
+        if element.kind() == WHITESPACE {
+            newlines = Some(element.to_string().matches('\n').count());
+            continue;
+        }
+        let current = match newlines.take() {
+            // The start of the file.
+            _ if res.is_empty() => None,
+            Some(0) => {
+                let last = res.last_mut().unwrap().0.last_mut().unwrap();
+                if element.kind() == COMMENT && last.trailing.is_none() {
+                    last.trailing = element.into_token();
+                    continue;
+                }
+                res.last_mut()
+            }
+            Some(1) => res.last_mut(),
+            Some(_) => None,
+            None => res.last_mut(),
+        };
+        let entry = Entry {
+            element,
+            trailing: None,
+        };
+        match current {
+            Some(paragraph) => paragraph.0.push(entry),
+            None => res.push(Paragraph(vec![entry])),
+        }
+    }
+    res
+}
+
+impl Paragraph {
+    fn range(&self) -> TextRange {
+        let first = self.0.first().unwrap();
+        let last = self.0.last().unwrap();
+        let end = match &last.trailing {
+            Some(it) => it.text_range().end(),
+            None => last.element.text_range().end(),
+        };
+        TextRange::new(first.element.text_range().start(), end)
+    }
+
+    fn format(&self, style: &FormatStyle) -> String {
+        let width = self
+            .0
+            .iter()
+            .filter(|_| style.align_rules)
+            .filter_map(|it| it.element.as_node().cloned().and_then(ast::Rule::cast))
+            .filter_map(|it| it.name())
+            .map(|it| it.text().chars().count())
+            .max()
+            .unwrap_or(0);
+        let mut lines = Vec::new();
+        for entry in &self.0 {
+            let mut text = match &entry.element {
+                NodeOrToken::Token(comment) => comment.text().trim_end().to_string(),
+                NodeOrToken::Node(node) => match ast::Rule::cast(node.clone()) {
+                    Some(rule) => format_rule(&rule, width, style),
+                    None => format_precedence(node, style),
+                },
+            };
+            if let Some(trailing) = &entry.trailing {
+                text.push(' ');
+                text.push_str(trailing.text().trim_end());
+            }
+            lines.push(text);
+        }
+        lines.join("\n")
+    }
+}
+
+/// A line of a rule, with the comments around it.
+struct Item {
+    start: TextSize,
+    text: String,
+    /// Comments on their own lines above the item.
+    leading: Vec<String>,
+    /// Comments at the end of the item's line.
+    trailing: Vec<String>,
+}
+
+impl Item {
+    fn new(start: TextSize, text: String) -> Item {
+        Item {
+            start,
+            text,
+            leading: Vec::new(),
+            trailing: Vec::new(),
+        }
+    }
+}
+
+fn format_rule(rule: &ast::Rule, width: usize, style: &FormatStyle) -> String {
+    let name = rule
+        .name()
+        .map_or(String::new(), |it| it.text().to_string());
+    let header = format!("{name:width$} =");
+    match rule.body() {
+        Some(body @ ast::Expr::Alt(_)) => {
+            let items = body
+                .children()
+                .map(|it| Item::new(it.syntax().text_range().start(), print_expr(&it)))
+                .collect();
+            let indent = " ".repeat(style.indent);
+            let bar = format!("{}| ", &indent[2..]);
+            layout(&rule.syntax, header, items, false, style, &[&indent, &bar])
+        }
+        Some(body) => {
+            let item = Item::new(body.syntax().text_range().start(), print_expr(&body));
+            layout(&rule.syntax, header, vec![item], true, style, &[])
+        }
+        None => layout(&rule.syntax, header, Vec::new(), true, style, &[]),
+    }
+}
+
+fn format_precedence(node: &SyntaxNode, style: &FormatStyle) -> String {
+    let precedence = ast::Precedence::cast(node.clone()).unwrap();
+    let name = precedence.node().and_then(|it| it.name());
+    let header = format!(
+        "%precedence {}",
+        name.map_or(String::new(), |it| it.text().to_string())
+    );
+    let items = precedence
+        .levels()
+        .map(|level| {
+            let text = level
+                .syntax
+                .children_with_tokens()
+                .filter(|it| !it.kind().is_trivia())
+                .map(|it| it.to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            Item::new(level.syntax.text_range().start(), text)
+        })
+        .collect();
+    layout(node, header, items, false, style, &[])
+}
+
+/// Puts `items` on lines below `header`, or on the same line if `inline` and
+/// comments allow it. The first item gets the first prefix, the rest the
+/// last one; without prefixes, items are just indented.
+fn layout(
+    node: &SyntaxNode,
+    header: String,
+    mut items: Vec<Item>,
+    inline: bool,
+    style: &FormatStyle,
+    prefixes: &[&str],
+) -> String {
+    let indent = " ".repeat(style.indent);
+    let mut header_trailing = Vec::new();
+    let comments = node
+        .descendants_with_tokens()
+        .filter_map(|it| it.into_token())
+        .filter(|it| it.kind() == COMMENT);
+    for comment in comments {
+        let text = comment.text().trim_end().to_string();
+        let start = comment.text_range().start();
+        let own_line = comment
+            .prev_token()
+            .is_some_and(|it| it.kind() == WHITESPACE && it.text().contains('\n'));
+        if own_line {
+            match items.iter_mut().find(|it| it.start > start) {
+                Some(item) => item.leading.push(text),
+                None => header_trailing.push(text),
+            }
+        } else {
+            match items.iter_mut().rev().find(|it| it.start < start) {
+                Some(item) => item.trailing.push(text),
+                None => header_trailing.push(text),
+            }
+        }
+    }
+
+    let trailing = |comments: &[String]| match comments {
+        [] => String::new(),
+        _ => format!(" {}", comments.join(" ")),
+    };
+    if let [item] = &items[..] {
+        if inline && item.leading.is_empty() && header_trailing.is_empty() {
+            return format!("{header} {}{}", item.text, trailing(&item.trailing));
+        }
+    }
+    let mut res = format!("{header}{}", trailing(&header_trailing));
+    for (idx, item) in items.iter().enumerate() {
+        let prefix = match prefixes {
+            [] => &indent,
+            _ => prefixes[idx.min(prefixes.len() - 1)],
+        };
+        for comment in &item.leading {
+            res.push_str(&format!("\n{indent}{comment}"));
+        }
+        res.push_str(&format!(
+            "\n{prefix}{}{}",
+            item.text,
+            trailing(&item.trailing)
+        ));
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::db::RootDatabase;
+
+    fn format_text(text: &str) -> Result<String, FormatError> {
+        format_with(text, &FormatStyle::default())
+    }
+
+    fn format_with(text: &str, style: &FormatStyle) -> Result<String, FormatError> {
+        let mut db = RootDatabase::default();
+        let file = FileId(0);
+        db.set_file_text(file, Arc::new(text.to_string()));
+        let mut text = text.to_string();
+        for (range, new_text) in format(&db, file, None, style)? {
+            text.replace_range(std::ops::Range::<usize>::from(range), &new_text);
+        }
+        Ok(text)
+    }
+
+    fn check(before: &str, after: &str) {
+        let formatted = format_text(before).unwrap();
+        assert_eq!(formatted, after);
+        assert_eq!(format_text(&formatted).unwrap(), after, "not idempotent");
+    }
+
+    #[test]
+    fn aligns_rules_in_paragraphs() {
+        check(
+            "// Paths\n\n\nPath= AbsolutePath|RelativePath\nAbsolutePath=('/' segment : PathSegment) * '/' ?\n\n\n\nPathSegment   =  'lex:base64url'",
+            "\
+// Paths
+
+Path         =
+  AbsolutePath
+| RelativePath
+AbsolutePath = ('/' segment:PathSegment)* '/'?
+
+PathSegment = 'lex:base64url'
+",
+        );
+    }
+
+    #[test]
+    fn keeps_comments() {
+        check(
+            "\
+// Doc
+A = B // after B
+  // above C
+  | C D  // after D
+B = 'b' C // tail of B
+C = // after =
+  'c'
+D = 'd'
+",
+            "\
+// Doc
+A =
+  B // after B
+  // above C
+| C D // after D
+B = 'b' C // tail of B
+C = // after =
+  'c'
+D = 'd'
+",
+        );
+    }
+
+    #[test]
+    fn follows_the_style() {
+        let style = FormatStyle {
+            align_rules: false,
+            indent: 4,
+        };
+        assert_eq!(
+            format_with(
+                "Path= A\n  // b\n  |B\nAbsolutePath= 'x'\n%precedence E %left '+'\n",
+                &style
+            )
+            .unwrap(),
+            "Path =\n    A\n    // b\n  | B\nAbsolutePath = 'x'\n%precedence E\n    %left '+'\n"
+        );
+    }
+
+    #[test]
+    fn formats_precedence_declarations() {
+        check(
+            "E = E op:('+' | '*') E | 'int'\n%precedence E %left '+'\n    %left '*'\n",
+            "E =\n  E op:('+' | '*') E\n| 'int'\n%precedence E\n  %left '+'\n  %left '*'\n",
+        );
+    }
+
+    #[test]
+    fn refuses_broken_grammars() {
+        assert_eq!(
+            format_text("A = B\nB = (C").unwrap_err().to_string(),
+            "cannot format a grammar with syntax errors (line 2: unexpected token, expected `)`)"
+        );
+    }
+
+    #[test]
+    fn repository_grammars_are_stable() {
+        for text in [
+            include_str!("../../markup_ungrams/zork_keg.ungram"),
+            include_str!("../../ungrammar_fork/rust.ungram"),
+            include_str!("../../ungrammar_fork/ungrammar.ungram"),
+        ] {
+            let formatted = format_text(text).unwrap();
+            assert_eq!(format_text(&formatted).unwrap(), formatted);
+            let grammar = |text: &str| text.parse::<ungrammar_fork::Grammar>().unwrap();
+            assert_eq!(grammar(&formatted), grammar(text));
+            let mut db = RootDatabase::default();
+            db.set_file_text(FileId(0), Arc::new(formatted));
+            assert_eq!(*db.parse(FileId(0)).errors, []);
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! From syntax trees to `ungrammar_fork::Grammar`.
+//!
+//! Every rule is first lowered on its own into a [`RuleData`], which refers
+//! to other nodes by name. Because it carries no positions, editing a comment
+//! or moving a rule around produces an identical `RuleData` and the database
+//! does not need to resolve the grammar again. [`resolve`] then interns all
+//! names into a `Grammar`.
+use std::{collections::HashSet, sync::Arc};
+
+use ungrammar_fork::{
+    Assoc, Grammar, GrammarBuilder, PrecedenceData, PrecedenceErrorKind, PrecedenceLevel, Rule,
+};
+
+use crate::syntax::{ast, Parse};
+
+/// A rule expression, with nodes and tokens referred to by name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum RuleExpr {
+    Labeled { label: String, rule: Box<RuleExpr> },
+    Node(String),
+    Token(String),
+    Seq(Vec<RuleExpr>),
+    Alt(Vec<RuleExpr>),
+    Opt(Box<RuleExpr>),
+    Rep(Box<RuleExpr>),
+}
+
+/// A precedence declaration, with operators referred to by name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PrecedenceDecl {
+    pub(crate) node: String,
+    pub(crate) levels: Vec<(Assoc, Vec<String>)>,
+}
+
+/// A single rule of a grammar file, lowered from its syntax tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RuleData {
+    /// `None` for text that is not a rule at all.
+    pub(crate) name: Option<String>,
+    pub(crate) body: Option<RuleExpr>,
+    /// Set instead of `name` and `body` for a `%precedence` declaration.
+    pub(crate) precedence: Option<PrecedenceDecl>,
+    /// `false` if the rule has syntax errors, in which case `body` may be
+    /// missing some parts.
+    pub(crate) complete: bool,
+}
+
+pub(crate) fn lower(parse: &Parse) -> RuleData {
+    let syntax = parse.syntax_node();
+    let precedence = ast::Precedence::cast(syntax.clone()).and_then(|it| lower_precedence(&it));
+    let rule = ast::Rule::cast(syntax);
+    let name = rule
+        .as_ref()
+        .and_then(|it| it.name())
+        .map(|it| it.text().to_string());
+    let body = rule.and_then(|it| it.body()).and_then(|it| lower_expr(&it));
+    RuleData {
+        name,
+        complete: parse.errors.is_empty() && (body.is_some() || precedence.is_some()),
+        body,
+        precedence,
+    }
+}
+
+fn lower_precedence(precedence: &ast::Precedence) -> Option<PrecedenceDecl> {
+    let node = precedence.node()?.name()?.text().to_string();
+    let levels = precedence
+        .levels()
+        .filter_map(|level| {
+            let tokens = level.tokens().filter_map(|it| it.value()).collect();
+            Some((level.assoc()?, tokens))
+        })
+        .collect();
+    Some(PrecedenceDecl { node, levels })
+}
+
+fn lower_expr(expr: &ast::Expr) -> Option<RuleExpr> {
+    let res = match expr {
+        ast::Expr::Alt(_) => {
+            RuleExpr::Alt(expr.children().filter_map(|it| lower_expr(&it)).collect())
+        }
+        ast::Expr::Seq(_) => {
+            RuleExpr::Seq(expr.children().filter_map(|it| lower_expr(&it)).collect())
+        }
+        ast::Expr::Labeled(it) => RuleExpr::Labeled {
+            label: it.label()?.text().to_string(),
+            rule: Box::new(lower_expr(&it.rule()?)?),
+        },
+        ast::Expr::Node(it) => RuleExpr::Node(it.name()?.text().to_string()),
+        ast::Expr::Token(it) => RuleExpr::Token(it.value()?),
+        ast::Expr::Paren(_) => lower_expr(&expr.children().next()?)?,
+        ast::Expr::Opt(_) => RuleExpr::Opt(Box::new(lower_expr(&expr.children().next()?)?)),
+        ast::Expr::Rep(_) => RuleExpr::Rep(Box::new(lower_expr(&expr.children().next()?)?)),
+    };
+    Some(res)
+}
+
+/// A grammar file after name resolution.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ResolvedGrammar {
+    /// The grammar, if the file is free of errors.
+    pub(crate) grammar: Option<Arc<Grammar>>,
+    /// The grammar as far as it can be built: undefined nodes have empty
+    /// rules and only the first rule of a node counts.
+    pub(crate) best_effort: Arc<Grammar>,
+    /// Nodes that are referenced but have no rule, in order of appearance.
+    pub(crate) undefined: Vec<String>,
+    /// Nodes that have more than one rule.
+    pub(crate) duplicates: Vec<String>,
+    /// Precedence declarations that do not match their nodes.
+    pub(crate) precedence_errors: Vec<PrecedenceProblem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum PrecedenceProblem {
+    /// A node with more than one `%precedence` declaration.
+    Duplicate(String),
+    /// An operator of `node` that is missing from, or listed wrongly in, its
+    /// declaration.
+    Operator {
+        node: String,
+        token: String,
+        kind: PrecedenceErrorKind,
+        message: String,
+    },
+}
+
+pub(crate) fn resolve(rules: &[Arc<RuleData>]) -> ResolvedGrammar {
+    let mut builder = GrammarBuilder::new();
+    let mut complete = true;
+    let mut defined = HashSet::new();
+    let mut referenced = Vec::new();
+    let mut duplicates = Vec::new();
+    let mut precedence_errors = Vec::new();
+    for data in rules {
+        complete &= data.complete;
+        if let Some(decl) = &data.precedence {
+            referenced.push(decl.node.clone());
+            let node = builder.node(&decl.node);
+            let levels = decl
+                .levels
+                .iter()
+                .map(|(assoc, tokens)| PrecedenceLevel {
+                    assoc: *assoc,
+                    tokens: tokens.iter().map(|it| builder.token(it)).collect(),
+                })
+                .collect();
+            if builder.precedence(PrecedenceData { node, levels }).is_err() {
+                precedence_errors.push(PrecedenceProblem::Duplicate(decl.node.clone()));
+            }
+        }
+        let Some(name) = &data.name else { continue };
+        let node = builder.node(name);
+        if !defined.insert(name.as_str()) {
+            if !duplicates.contains(name) {
+                duplicates.push(name.clone());
+            }
+            continue;
+        }
+        let rule = match &data.body {
+            Some(body) => intern(&mut builder, body, &mut referenced),
+            None => Rule::Seq(Vec::new()),
+        };
+        builder
+            .define(node, rule)
+            .expect("checked for duplicates above");
+    }
+
+    let mut undefined: Vec<String> = Vec::new();
+    for name in referenced {
+        if !defined.contains(name.as_str()) && !undefined.contains(&name) {
+            let node = builder.node(&name);
+            builder
+                .define(node, Rule::Seq(Vec::new()))
+                .expect("undefined nodes have no rule");
+            undefined.push(name);
+        }
+    }
+    // Every node has a rule by now, so this always succeeds and precedence
+    // can be checked even while the rest of the grammar is broken.
+    let grammar = builder.finish().expect("all nodes are defined");
+    precedence_errors.extend(grammar.precedence_errors().into_iter().map(|err| {
+        PrecedenceProblem::Operator {
+            node: grammar[err.node].name.clone(),
+            token: grammar[err.token].name.clone(),
+            kind: err.kind,
+            message: err.to_string(),
+        }
+    }));
+    let clean =
+        complete && duplicates.is_empty() && undefined.is_empty() && precedence_errors.is_empty();
+    let grammar = Arc::new(grammar);
+    ResolvedGrammar {
+        grammar: clean.then(|| grammar.clone()),
+        best_effort: grammar,
+        undefined,
+        duplicates,
+        precedence_errors,
+    }
+}
+
+fn intern(builder: &mut GrammarBuilder, expr: &RuleExpr, referenced: &mut Vec<String>) -> Rule {
+    match expr {
+        RuleExpr::Labeled { label, rule } => Rule::Labeled {
+            label: label.clone(),
+            rule: Box::new(intern(builder, rule, referenced)),
+        },
+        RuleExpr::Node(name) => {
+            referenced.push(name.clone());
+            Rule::Node(builder.node(name))
+        }
+        RuleExpr::Token(name) => Rule::Token(builder.token(name)),
+        RuleExpr::Seq(rules) => Rule::Seq(
+            rules
+                .iter()
+                .map(|it| intern(builder, it, referenced))
+                .collect(),
+        ),
+        RuleExpr::Alt(rules) => Rule::Alt(
+            rules
+                .iter()
+                .map(|it| intern(builder, it, referenced))
+                .collect(),
+        ),
+        RuleExpr::Opt(rule) => Rule::Opt(Box::new(intern(builder, rule, referenced))),
+        RuleExpr::Rep(rule) => Rule::Rep(Box::new(intern(builder, rule, referenced))),
+    }
+}
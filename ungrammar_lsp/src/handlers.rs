@@ -0,0 +1,1011 @@
+//! Handlers for LSP requests: they translate between protocol types and the
+//! analyses in the rest of the crate.
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt,
+};
+
+use lsp_server::ErrorCode;
+use lsp_types::{
+    CallHierarchyIncomingCall, CallHierarchyIncomingCallsParams, CallHierarchyItem,
+    CallHierarchyOutgoingCall, CallHierarchyOutgoingCallsParams, CallHierarchyPrepareParams,
+    CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams, CodeActionResponse,
+    CodeLens, CodeLensParams, Command, CompletionItem, CompletionItemKind, CompletionParams,
+    CompletionResponse, CompletionTextEdit, Diagnostic, DocumentFormattingParams,
+    DocumentHighlight, DocumentHighlightKind, DocumentHighlightParams,
+    DocumentRangeFormattingParams, DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse,
+    Documentation, ExecuteCommandParams, FoldingRange, FoldingRangeKind, FoldingRangeParams,
+    GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams, InlayHint,
+    InlayHintKind, InlayHintLabel, InlayHintParams, InsertTextFormat, Location, MarkupContent,
+    MarkupKind, PrepareRenameResponse, Range, ReferenceParams, RenameParams, SelectionRange,
+    SelectionRangeParams, SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokens,
+    SemanticTokensLegend, SemanticTokensParams, SemanticTokensRangeParams,
+    SemanticTokensRangeResult, SemanticTokensResult, SymbolInformation, SymbolKind,
+    TextDocumentPositionParams, TextEdit, Url, WorkspaceEdit, WorkspaceSymbolParams,
+    WorkspaceSymbolResponse,
+};
+use rowan::{TextRange, TextSize};
+
+use crate::{
+    assists::{self, AssistKind},
+    ast_preview,
+    completion::{self, CompletionKind},
+    db::{FileId, SourceDatabase},
+    diagnostics,
+    folding::{self, FoldKind},
+    formatting,
+    highlight::{self, HlTag},
+    hover, inlay_hints,
+    line_index::LineIndex,
+    lsp_ext::{
+        AstPreview, DocumentDiagnosticParams, DocumentDiagnosticReport,
+        FullDocumentDiagnosticReport, SampleTreeParams, TextDocumentContentParams,
+        TextDocumentContentResult, UnchangedDocumentDiagnosticReport, WorkspaceDiagnosticParams,
+        WorkspaceDiagnosticReport, WorkspaceDocumentDiagnosticReport,
+    },
+    navigation::{self, Symbol},
+    playground, rename, selection,
+    symbols::{self, FileSymbol, StructureKind, StructureNode},
+    StateSnapshot,
+};
+
+pub(crate) type Result<T> = std::result::Result<T, Box<dyn Error + Sync + Send>>;
+
+/// An error the client should show as is, rather than as an internal error.
+#[derive(Debug)]
+pub(crate) struct LspError {
+    pub(crate) code: ErrorCode,
+    pub(crate) message: String,
+}
+
+impl fmt::Display for LspError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for LspError {}
+
+/// The file and offset a request points at.
+fn file_position(
+    snap: &StateSnapshot,
+    params: &TextDocumentPositionParams,
+) -> Result<(FileId, TextSize)> {
+    let uri = &params.text_document.uri;
+    let file = snap
+        .file(uri)
+        .ok_or_else(|| format!("unknown document {uri}"))?;
+    let offset = snap.db.line_index(file).offset(params.position);
+    Ok((file, offset))
+}
+
+pub(crate) fn handle_goto_definition(
+    snap: &StateSnapshot,
+    params: GotoDefinitionParams,
+) -> Result<Option<GotoDefinitionResponse>> {
+    let position = params.text_document_position_params;
+    let (file, offset) = file_position(snap, &position)?;
+    let Some((symbol, _)) = navigation::symbol_at(&*snap.db, file, offset) else {
+        return Ok(None);
+    };
+    let line_index = snap.db.line_index(file);
+    let locations: Vec<_> = navigation::definitions(&*snap.db, file, &symbol)
+        .into_iter()
+        .map(|range| Location::new(position.text_document.uri.clone(), line_index.range(range)))
+        .collect();
+    let res = match <[Location; 1]>::try_from(locations) {
+        Ok([location]) => GotoDefinitionResponse::Scalar(location),
+        Err(locations) if locations.is_empty() => return Ok(None),
+        Err(locations) => GotoDefinitionResponse::Array(locations),
+    };
+    Ok(Some(res))
+}
+
+pub(crate) fn handle_references(
+    snap: &StateSnapshot,
+    params: ReferenceParams,
+) -> Result<Option<Vec<Location>>> {
+    let position = params.text_document_position;
+    let (file, offset) = file_position(snap, &position)?;
+    let Some((symbol, _)) = navigation::symbol_at(&*snap.db, file, offset) else {
+        return Ok(None);
+    };
+    // Grammars do not import each other: a node of the same name in another
+    // file is another node.
+    let include_declaration = params.context.include_declaration;
+    let line_index = snap.db.line_index(file);
+    let locations = navigation::references(&*snap.db, file, &symbol, include_declaration)
+        .into_iter()
+        .map(|range| Location::new(position.text_document.uri.clone(), line_index.range(range)))
+        .collect();
+    Ok(Some(locations))
+}
+
+/// Every mention of the node or token under the cursor in the document,
+/// rule names as writes and the rest as reads.
+pub(crate) fn handle_document_highlight(
+    snap: &StateSnapshot,
+    params: DocumentHighlightParams,
+) -> Result<Option<Vec<DocumentHighlight>>> {
+    let position = params.text_document_position_params;
+    if playground::is_sample(&position.text_document.uri) {
+        return Ok(None);
+    }
+    let (file, offset) = file_position(snap, &position)?;
+    let Some((symbol, _)) = navigation::symbol_at(&*snap.db, file, offset) else {
+        return Ok(None);
+    };
+    let line_index = snap.db.line_index(file);
+    let definitions = navigation::definitions(&*snap.db, file, &symbol)
+        .into_iter()
+        .map(|it| (it, DocumentHighlightKind::WRITE));
+    let references = navigation::references(&*snap.db, file, &symbol, false)
+        .into_iter()
+        .map(|it| (it, DocumentHighlightKind::READ));
+    let mut highlights: Vec<_> = definitions.chain(references).collect();
+    highlights.sort_by_key(|(range, _)| range.start());
+    let res = highlights
+        .into_iter()
+        .map(|(range, kind)| DocumentHighlight {
+            range: line_index.range(range),
+            kind: Some(kind),
+        })
+        .collect();
+    Ok(Some(res))
+}
+
+/// Folds rules and comment blocks that span more than one line.
+pub(crate) fn handle_folding_range(
+    snap: &StateSnapshot,
+    params: FoldingRangeParams,
+) -> Result<Option<Vec<FoldingRange>>> {
+    let uri = &params.text_document.uri;
+    let file = snap
+        .file(uri)
+        .ok_or_else(|| format!("unknown document {uri}"))?;
+    if playground::is_sample(uri) {
+        return Ok(None);
+    }
+    let line_index = snap.db.line_index(file);
+    let res = folding::folds(&*snap.db, file)
+        .into_iter()
+        .filter_map(|fold| {
+            let range = line_index.range(fold.range);
+            if range.start.line == range.end.line {
+                return None;
+            }
+            let kind = match fold.kind {
+                FoldKind::Rule => None,
+                FoldKind::Comment => Some(FoldingRangeKind::Comment),
+            };
+            Some(FoldingRange {
+                start_line: range.start.line,
+                start_character: Some(range.start.character),
+                end_line: range.end.line,
+                end_character: Some(range.end.character),
+                kind,
+                collapsed_text: None,
+            })
+        })
+        .collect();
+    Ok(Some(res))
+}
+
+pub(crate) fn handle_selection_range(
+    snap: &StateSnapshot,
+    params: SelectionRangeParams,
+) -> Result<Option<Vec<SelectionRange>>> {
+    let uri = &params.text_document.uri;
+    let file = snap
+        .file(uri)
+        .ok_or_else(|| format!("unknown document {uri}"))?;
+    if playground::is_sample(uri) {
+        return Ok(None);
+    }
+    let line_index = snap.db.line_index(file);
+    let res = params
+        .positions
+        .into_iter()
+        .map(|position| {
+            let offset = line_index.offset(position);
+            let ranges = selection::selection_ranges(&*snap.db, file, offset);
+            // Built from the outside in, as every range points to its parent.
+            let mut res = None;
+            for range in ranges.into_iter().rev() {
+                res = Some(SelectionRange {
+                    range: line_index.range(range),
+                    parent: res.map(Box::new),
+                });
+            }
+            res.unwrap_or(SelectionRange {
+                range: Range::new(position, position),
+                parent: None,
+            })
+        })
+        .collect();
+    Ok(Some(res))
+}
+
+pub(crate) fn handle_prepare_rename(
+    snap: &StateSnapshot,
+    params: TextDocumentPositionParams,
+) -> Result<Option<PrepareRenameResponse>> {
+    let (file, offset) = file_position(snap, &params)?;
+    let Some((range, placeholder)) = rename::prepare_rename(&*snap.db, file, offset) else {
+        return Ok(None);
+    };
+    let range = snap.db.line_index(file).range(range);
+    Ok(Some(PrepareRenameResponse::RangeWithPlaceholder {
+        range,
+        placeholder,
+    }))
+}
+
+pub(crate) fn handle_rename(
+    snap: &StateSnapshot,
+    params: RenameParams,
+) -> Result<Option<WorkspaceEdit>> {
+    let position = params.text_document_position;
+    let (file, offset) = file_position(snap, &position)?;
+    let edits =
+        rename::rename(&*snap.db, file, offset, &params.new_name).map_err(|err| LspError {
+            code: ErrorCode::RequestFailed,
+            message: err.to_string(),
+        })?;
+    let line_index = snap.db.line_index(file);
+    let edits = edits
+        .into_iter()
+        .map(|(range, new_text)| TextEdit::new(line_index.range(range), new_text))
+        .collect();
+    let changes = HashMap::from([(position.text_document.uri, edits)]);
+    Ok(Some(WorkspaceEdit::new(changes)))
+}
+
+pub(crate) fn handle_hover(snap: &StateSnapshot, params: HoverParams) -> Result<Option<Hover>> {
+    let (file, offset) = file_position(snap, &params.text_document_position_params)?;
+    let Some((range, markdown)) = hover::hover(&*snap.db, file, offset) else {
+        return Ok(None);
+    };
+    Ok(Some(Hover {
+        contents: HoverContents::Markup(markup(markdown, snap.features.markdown_hover)),
+        range: Some(snap.db.line_index(file).range(range)),
+    }))
+}
+
+pub(crate) fn handle_completion(
+    snap: &StateSnapshot,
+    params: CompletionParams,
+) -> Result<Option<CompletionResponse>> {
+    let position = params.text_document_position;
+    let (file, offset) = file_position(snap, &position)?;
+    let line_index = snap.db.line_index(file);
+    let items = completion::completions(&*snap.db, file, offset)
+        .into_iter()
+        .enumerate()
+        .map(|(idx, it)| {
+            let kind = match it.kind {
+                CompletionKind::Node => CompletionItemKind::STRUCT,
+                CompletionKind::Token => CompletionItemKind::CONSTANT,
+                CompletionKind::Label => CompletionItemKind::FIELD,
+                CompletionKind::Snippet => CompletionItemKind::SNIPPET,
+            };
+            let (format, insert) = match it.kind {
+                CompletionKind::Snippet if snap.features.snippets => {
+                    (InsertTextFormat::SNIPPET, it.insert)
+                }
+                CompletionKind::Snippet => (
+                    InsertTextFormat::PLAIN_TEXT,
+                    completion::strip_snippet(&it.insert),
+                ),
+                _ => (InsertTextFormat::PLAIN_TEXT, it.insert),
+            };
+            let edit = TextEdit::new(line_index.range(it.range), insert);
+            CompletionItem {
+                label: it.label,
+                kind: Some(kind),
+                detail: it.detail,
+                // Items come ranked already.
+                sort_text: Some(format!("{idx:05}")),
+                insert_text_format: Some(format),
+                text_edit: Some(CompletionTextEdit::Edit(edit)),
+                data: it.node.map(
+                    |node| serde_json::json!({ "uri": position.text_document.uri, "node": node }),
+                ),
+                ..Default::default()
+            }
+        })
+        .collect();
+    Ok(Some(CompletionResponse::Array(items)))
+}
+
+/// Fills in the documentation of the node behind a completion item.
+pub(crate) fn handle_completion_resolve(
+    snap: &StateSnapshot,
+    mut item: CompletionItem,
+) -> Result<CompletionItem> {
+    let Some(data) = &item.data else {
+        return Ok(item);
+    };
+    let uri: Url = serde_json::from_value(data["uri"].clone())?;
+    let node = data["node"].as_str().unwrap_or_default();
+    let Some(file) = snap.file(&uri) else {
+        return Ok(item);
+    };
+    let root = snap.db.parse(file).syntax_node();
+    if let Some(docs) = hover::rule_docs(&root, node) {
+        let docs = markup(docs, snap.features.markdown_docs);
+        item.documentation = Some(Documentation::MarkupContent(docs));
+    }
+    Ok(item)
+}
+
+/// `markdown` as is, or as plain text for clients that cannot render it.
+fn markup(markdown: String, supported: bool) -> MarkupContent {
+    match supported {
+        true => MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: markdown,
+        },
+        false => MarkupContent {
+            kind: MarkupKind::PlainText,
+            value: hover::to_plain_text(&markdown),
+        },
+    }
+}
+
+pub(crate) fn handle_document_symbol(
+    snap: &StateSnapshot,
+    params: DocumentSymbolParams,
+) -> Result<Option<DocumentSymbolResponse>> {
+    let uri = &params.text_document.uri;
+    let file = snap
+        .file(uri)
+        .ok_or_else(|| format!("unknown document {uri}"))?;
+    let line_index = snap.db.line_index(file);
+    let root = snap.db.parse(file).syntax_node();
+    let symbols = symbols::file_structure(&root)
+        .into_iter()
+        .map(|it| document_symbol(&line_index, it))
+        .collect();
+    Ok(Some(DocumentSymbolResponse::Nested(symbols)))
+}
+
+fn document_symbol(line_index: &LineIndex, node: StructureNode) -> DocumentSymbol {
+    let children = node
+        .children
+        .into_iter()
+        .map(|it| document_symbol(line_index, it))
+        .collect();
+    #[allow(deprecated)]
+    DocumentSymbol {
+        name: node.name,
+        detail: None,
+        kind: symbol_kind(node.kind),
+        tags: None,
+        deprecated: None,
+        range: line_index.range(node.range),
+        selection_range: line_index.range(node.selection_range),
+        children: Some(children),
+    }
+}
+
+fn symbol_kind(kind: StructureKind) -> SymbolKind {
+    match kind {
+        StructureKind::Section => SymbolKind::NAMESPACE,
+        StructureKind::Enum => SymbolKind::ENUM,
+        StructureKind::Rule => SymbolKind::STRUCT,
+        StructureKind::Label => SymbolKind::FIELD,
+        StructureKind::Precedence => SymbolKind::OPERATOR,
+    }
+}
+
+/// Searches the node names of every grammar in the index: the files under
+/// the workspace roots and the open documents.
+pub(crate) fn handle_workspace_symbol(
+    snap: &StateSnapshot,
+    params: WorkspaceSymbolParams,
+) -> Result<Option<WorkspaceSymbolResponse>> {
+    let mut found = Vec::new();
+    let grammars = snap
+        .files
+        .iter()
+        .filter(|(uri, _)| !playground::is_sample(uri));
+    for (uri, &file) in grammars {
+        let line_index = snap.db.line_index(file);
+        for symbol in snap.db.file_symbols(file).iter() {
+            let Some(score) = symbols::fuzzy_score(&params.query, &symbol.name) else {
+                continue;
+            };
+            #[allow(deprecated)]
+            let symbol = SymbolInformation {
+                kind: symbol_kind(symbol.kind),
+                tags: None,
+                deprecated: None,
+                location: Location::new(uri.clone(), line_index.range(symbol.range)),
+                container_name: symbol.container.clone(),
+                name: symbol.name.clone(),
+            };
+            found.push((score, symbol));
+        }
+    }
+    found.sort_by(|(a_score, a), (b_score, b)| {
+        b_score
+            .cmp(a_score)
+            .then_with(|| a.name.cmp(&b.name))
+            .then_with(|| a.location.uri.cmp(&b.location.uri))
+    });
+    let symbols = found.into_iter().map(|(_, it)| it).collect();
+    Ok(Some(WorkspaceSymbolResponse::Flat(symbols)))
+}
+
+const TOKEN_TYPES: [SemanticTokenType; 8] = [
+    SemanticTokenType::TYPE,
+    SemanticTokenType::new("unresolvedReference"),
+    SemanticTokenType::STRING,
+    SemanticTokenType::REGEXP,
+    SemanticTokenType::PROPERTY,
+    SemanticTokenType::OPERATOR,
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::COMMENT,
+];
+
+const TOKEN_MODIFIERS: [SemanticTokenModifier; 2] = [
+    SemanticTokenModifier::DECLARATION,
+    SemanticTokenModifier::DOCUMENTATION,
+];
+
+pub(crate) fn semantic_tokens_legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TOKEN_TYPES.to_vec(),
+        token_modifiers: TOKEN_MODIFIERS.to_vec(),
+    }
+}
+
+/// The index of the token type in the legend, and the bit set of modifiers.
+fn semantic_token_type(tag: HlTag) -> (u32, u32) {
+    let (token_type, modifier) = match tag {
+        HlTag::NodeDef => (
+            SemanticTokenType::TYPE,
+            Some(SemanticTokenModifier::DECLARATION),
+        ),
+        HlTag::NodeRef => (SemanticTokenType::TYPE, None),
+        HlTag::UndefinedRef => (SemanticTokenType::new("unresolvedReference"), None),
+        HlTag::TokenLit => (SemanticTokenType::STRING, None),
+        HlTag::LexToken => (SemanticTokenType::REGEXP, None),
+        HlTag::Label => (SemanticTokenType::PROPERTY, None),
+        HlTag::Operator => (SemanticTokenType::OPERATOR, None),
+        HlTag::Directive => (SemanticTokenType::KEYWORD, None),
+        HlTag::Comment => (SemanticTokenType::COMMENT, None),
+        HlTag::DocComment => (
+            SemanticTokenType::COMMENT,
+            Some(SemanticTokenModifier::DOCUMENTATION),
+        ),
+    };
+    let index = TOKEN_TYPES.iter().position(|it| *it == token_type).unwrap();
+    let modifiers = modifier.map_or(0, |modifier| {
+        1 << TOKEN_MODIFIERS
+            .iter()
+            .position(|it| *it == modifier)
+            .unwrap()
+    });
+    (index as u32, modifiers)
+}
+
+/// Encodes highlighted ranges relative to each other, as the protocol wants.
+fn semantic_tokens(line_index: &LineIndex, tags: Vec<(rowan::TextRange, HlTag)>) -> SemanticTokens {
+    let mut data = Vec::new();
+    let (mut prev_line, mut prev_start) = (0, 0);
+    for (range, tag) in tags {
+        let range = line_index.range(range);
+        // Tokens never span lines.
+        if range.start.line != range.end.line {
+            continue;
+        }
+        let delta_line = range.start.line - prev_line;
+        let delta_start = match delta_line {
+            0 => range.start.character - prev_start,
+            _ => range.start.character,
+        };
+        let (token_type, token_modifiers_bitset) = semantic_token_type(tag);
+        data.push(SemanticToken {
+            delta_line,
+            delta_start,
+            length: range.end.character - range.start.character,
+            token_type,
+            token_modifiers_bitset,
+        });
+        (prev_line, prev_start) = (range.start.line, range.start.character);
+    }
+    SemanticTokens {
+        result_id: None,
+        data,
+    }
+}
+
+pub(crate) fn handle_semantic_tokens_full(
+    snap: &StateSnapshot,
+    params: SemanticTokensParams,
+) -> Result<Option<SemanticTokensResult>> {
+    let uri = &params.text_document.uri;
+    let file = snap
+        .file(uri)
+        .ok_or_else(|| format!("unknown document {uri}"))?;
+    let tags = highlight::highlight(&*snap.db, file, None);
+    let tokens = semantic_tokens(&snap.db.line_index(file), tags);
+    Ok(Some(SemanticTokensResult::Tokens(tokens)))
+}
+
+pub(crate) fn handle_semantic_tokens_range(
+    snap: &StateSnapshot,
+    params: SemanticTokensRangeParams,
+) -> Result<Option<SemanticTokensRangeResult>> {
+    let uri = &params.text_document.uri;
+    let file = snap
+        .file(uri)
+        .ok_or_else(|| format!("unknown document {uri}"))?;
+    let line_index = snap.db.line_index(file);
+    let range = rowan::TextRange::new(
+        line_index.offset(params.range.start),
+        line_index.offset(params.range.end),
+    );
+    let tags = highlight::highlight(&*snap.db, file, Some(range));
+    let tokens = semantic_tokens(&line_index, tags);
+    Ok(Some(SemanticTokensRangeResult::Tokens(tokens)))
+}
+
+pub(crate) fn handle_formatting(
+    snap: &StateSnapshot,
+    params: DocumentFormattingParams,
+) -> Result<Option<Vec<TextEdit>>> {
+    let uri = &params.text_document.uri;
+    let file = snap
+        .file(uri)
+        .ok_or_else(|| format!("unknown document {uri}"))?;
+    format_edits(snap, file, None)
+}
+
+pub(crate) fn handle_range_formatting(
+    snap: &StateSnapshot,
+    params: DocumentRangeFormattingParams,
+) -> Result<Option<Vec<TextEdit>>> {
+    let uri = &params.text_document.uri;
+    let file = snap
+        .file(uri)
+        .ok_or_else(|| format!("unknown document {uri}"))?;
+    let line_index = snap.db.line_index(file);
+    let range = rowan::TextRange::new(
+        line_index.offset(params.range.start),
+        line_index.offset(params.range.end),
+    );
+    format_edits(snap, file, Some(range))
+}
+
+fn format_edits(
+    snap: &StateSnapshot,
+    file: FileId,
+    range: Option<rowan::TextRange>,
+) -> Result<Option<Vec<TextEdit>>> {
+    let edits = formatting::format(&*snap.db, file, range, &snap.config.format).map_err(|err| {
+        LspError {
+            code: ErrorCode::RequestFailed,
+            message: err.to_string(),
+        }
+    })?;
+    let line_index = snap.db.line_index(file);
+    Ok(Some(
+        edits
+            .into_iter()
+            .map(|(range, new_text)| TextEdit::new(line_index.range(range), new_text))
+            .collect(),
+    ))
+}
+
+pub(crate) fn handle_code_action(
+    snap: &StateSnapshot,
+    params: CodeActionParams,
+) -> Result<Option<CodeActionResponse>> {
+    let uri = &params.text_document.uri;
+    let file = snap
+        .file(uri)
+        .ok_or_else(|| format!("unknown document {uri}"))?;
+    let line_index = snap.db.line_index(file);
+    let range = rowan::TextRange::new(
+        line_index.offset(params.range.start),
+        line_index.offset(params.range.end),
+    );
+    let mut res = Vec::new();
+    for assist in assists::assists(&*snap.db, file, range) {
+        let kind = match assist.kind {
+            AssistKind::QuickFix => CodeActionKind::QUICKFIX,
+            AssistKind::RefactorInline => CodeActionKind::REFACTOR_INLINE,
+            AssistKind::RefactorExtract => CodeActionKind::REFACTOR_EXTRACT,
+        };
+        // `only` lists kinds or their parents, like `refactor` for
+        // `refactor.inline`.
+        if let Some(only) = &params.context.only {
+            let wanted = only.iter().any(|it| {
+                kind.as_str() == it.as_str()
+                    || kind.as_str().starts_with(&format!("{}.", it.as_str()))
+            });
+            if !wanted {
+                continue;
+            }
+        }
+        let target = line_index.range(assist.target);
+        let diagnostics: Vec<_> = params
+            .context
+            .diagnostics
+            .iter()
+            .filter(|it| {
+                let code = match &it.code {
+                    Some(lsp_types::NumberOrString::String(code)) => Some(code.as_str()),
+                    _ => None,
+                };
+                assist.fixes.is_some() && code == assist.fixes && it.range == target
+            })
+            .cloned()
+            .collect();
+        let edits = assist
+            .edits
+            .into_iter()
+            .map(|(range, new_text)| TextEdit::new(line_index.range(range), new_text))
+            .collect();
+        res.push(CodeActionOrCommand::CodeAction(CodeAction {
+            title: assist.label,
+            kind: Some(kind),
+            diagnostics: (!diagnostics.is_empty()).then_some(diagnostics),
+            edit: Some(WorkspaceEdit::new(HashMap::from([(uri.clone(), edits)]))),
+            ..Default::default()
+        }));
+    }
+    Ok(Some(res))
+}
+
+pub(crate) fn handle_inlay_hint(
+    snap: &StateSnapshot,
+    params: InlayHintParams,
+) -> Result<Option<Vec<InlayHint>>> {
+    let uri = &params.text_document.uri;
+    let file = snap
+        .file(uri)
+        .ok_or_else(|| format!("unknown document {uri}"))?;
+    let line_index = snap.db.line_index(file);
+    let range = rowan::TextRange::new(
+        line_index.offset(params.range.start),
+        line_index.offset(params.range.end),
+    );
+    let hints = inlay_hints::inlay_hints(&*snap.db, file, Some(range))
+        .into_iter()
+        .map(|hint| InlayHint {
+            position: line_index.position(hint.offset),
+            label: InlayHintLabel::String(hint.label),
+            kind: Some(InlayHintKind::TYPE),
+            text_edits: None,
+            tooltip: None,
+            padding_left: None,
+            padding_right: None,
+            data: None,
+        })
+        .collect();
+    Ok(Some(hints))
+}
+
+/// Shows above every rule how often its node is mentioned, and by how many
+/// rules.
+pub(crate) fn handle_code_lens(
+    snap: &StateSnapshot,
+    params: CodeLensParams,
+) -> Result<Option<Vec<CodeLens>>> {
+    let uri = &params.text_document.uri;
+    let file = snap
+        .file(uri)
+        .ok_or_else(|| format!("unknown document {uri}"))?;
+    if playground::is_sample(uri) {
+        return Ok(None);
+    }
+    let line_index = snap.db.line_index(file);
+    let mut references: HashMap<String, Vec<Location>> = HashMap::new();
+    let mut dependents: HashMap<String, HashSet<TextRange>> = HashMap::new();
+    for node_use in snap.db.node_uses(file).iter() {
+        let location = Location::new(uri.clone(), line_index.range(node_use.range));
+        references
+            .entry(node_use.node.clone())
+            .or_default()
+            .push(location);
+        if let Some(rule) = node_use.rule {
+            dependents
+                .entry(node_use.node.clone())
+                .or_default()
+                .insert(rule);
+        }
+    }
+    let plural = |count: usize, what: &str| match count {
+        1 => format!("1 {what}"),
+        _ => format!("{count} {what}s"),
+    };
+    let lenses = snap
+        .db
+        .file_symbols(file)
+        .iter()
+        .map(|symbol| {
+            let range = line_index.range(symbol.range);
+            let locations = references.remove(&symbol.name).unwrap_or_default();
+            let dependents = dependents.get(&symbol.name).map_or(0, |it| it.len());
+            let title = format!(
+                "{} · {}",
+                plural(locations.len(), "reference"),
+                plural(dependents, "dependent")
+            );
+            // The same arguments as VS Code's `editor.action.showReferences`.
+            let arguments = vec![
+                serde_json::to_value(uri)?,
+                serde_json::to_value(range.start)?,
+                serde_json::to_value(locations)?,
+            ];
+            Ok(CodeLens {
+                range,
+                command: Some(Command::new(
+                    title,
+                    SHOW_REFERENCES.to_string(),
+                    Some(arguments),
+                )),
+                data: None,
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok(Some(lenses))
+}
+
+/// The command of code lenses, which the client runs to show the
+/// references of a node.
+pub(crate) const SHOW_REFERENCES: &str = "ungrammar.showReferences";
+
+/// The rule of the node under the cursor, as an item of the call hierarchy.
+pub(crate) fn handle_call_hierarchy_prepare(
+    snap: &StateSnapshot,
+    params: CallHierarchyPrepareParams,
+) -> Result<Option<Vec<CallHierarchyItem>>> {
+    let position = params.text_document_position_params;
+    let (file, offset) = file_position(snap, &position)?;
+    let Some((Symbol::Node(name), _)) = navigation::symbol_at(&*snap.db, file, offset) else {
+        return Ok(None);
+    };
+    let item = rule_item(snap, &position.text_document.uri, file, &name);
+    Ok(item.map(|it| vec![it]))
+}
+
+/// The rules of the same grammar that use the node of `item`.
+pub(crate) fn handle_call_hierarchy_incoming(
+    snap: &StateSnapshot,
+    params: CallHierarchyIncomingCallsParams,
+) -> Result<Option<Vec<CallHierarchyIncomingCall>>> {
+    let item = params.item;
+    let Some(file) = snap.file(&item.uri) else {
+        return Ok(None);
+    };
+    let line_index = snap.db.line_index(file);
+    let symbols = snap.db.file_symbols(file);
+    let mut by_rule: Vec<(TextRange, Vec<Range>)> = Vec::new();
+    for node_use in snap.db.node_uses(file).iter() {
+        let Some(rule) = node_use.rule.filter(|_| node_use.node == item.name) else {
+            continue;
+        };
+        let range = line_index.range(node_use.range);
+        match by_rule.iter_mut().find(|(it, _)| *it == rule) {
+            Some((_, ranges)) => ranges.push(range),
+            None => by_rule.push((rule, vec![range])),
+        }
+    }
+    let calls = by_rule
+        .into_iter()
+        .filter_map(|(rule, from_ranges)| {
+            let symbol = symbols.iter().find(|it| it.range == rule)?;
+            Some(CallHierarchyIncomingCall {
+                from: call_hierarchy_item(&item.uri, &line_index, symbol),
+                from_ranges,
+            })
+        })
+        .collect();
+    Ok(Some(calls))
+}
+
+/// The nodes the rule of `item` uses.
+pub(crate) fn handle_call_hierarchy_outgoing(
+    snap: &StateSnapshot,
+    params: CallHierarchyOutgoingCallsParams,
+) -> Result<Option<Vec<CallHierarchyOutgoingCall>>> {
+    let item = params.item;
+    let Some(file) = snap.file(&item.uri) else {
+        return Ok(None);
+    };
+    let line_index = snap.db.line_index(file);
+    let mut by_node: Vec<(&str, Vec<Range>)> = Vec::new();
+    let node_uses = snap.db.node_uses(file);
+    let in_item = node_uses
+        .iter()
+        .filter(|it| it.rule.map(|it| line_index.range(it)) == Some(item.selection_range));
+    for node_use in in_item {
+        let range = line_index.range(node_use.range);
+        match by_node.iter_mut().find(|(it, _)| *it == node_use.node) {
+            Some((_, ranges)) => ranges.push(range),
+            None => by_node.push((&node_use.node, vec![range])),
+        }
+    }
+    let calls = by_node
+        .into_iter()
+        .filter_map(|(name, from_ranges)| {
+            // Undefined nodes lead nowhere.
+            let to = rule_item(snap, &item.uri, file, name)?;
+            Some(CallHierarchyOutgoingCall { to, from_ranges })
+        })
+        .collect();
+    Ok(Some(calls))
+}
+
+/// The first rule of `name` in `file`, as an item of the call hierarchy.
+fn rule_item(
+    snap: &StateSnapshot,
+    uri: &Url,
+    file: FileId,
+    name: &str,
+) -> Option<CallHierarchyItem> {
+    let symbols = snap.db.file_symbols(file);
+    let symbol = symbols.iter().find(|it| it.name == name)?;
+    Some(call_hierarchy_item(uri, &snap.db.line_index(file), symbol))
+}
+
+fn call_hierarchy_item(
+    uri: &Url,
+    line_index: &LineIndex,
+    symbol: &FileSymbol,
+) -> CallHierarchyItem {
+    CallHierarchyItem {
+        name: symbol.name.clone(),
+        kind: symbol_kind(symbol.kind),
+        tags: None,
+        detail: symbol.container.clone(),
+        uri: uri.clone(),
+        range: line_index.range(symbol.rule_range),
+        selection_range: line_index.range(symbol.range),
+        data: None,
+    }
+}
+
+pub(crate) fn handle_document_diagnostic(
+    snap: &StateSnapshot,
+    params: DocumentDiagnosticParams,
+) -> Result<DocumentDiagnosticReport> {
+    let uri = &params.text_document.uri;
+    let items = match snap.file(uri) {
+        Some(file) => {
+            diagnostics::file_diagnostics(&*snap.db, &snap.config, uri, file, &snap.files)
+        }
+        None => Vec::new(),
+    };
+    Ok(diagnostic_report(
+        items,
+        params.previous_result_id.as_deref(),
+    ))
+}
+
+/// Reports on every file in the index, and on the files the client still
+/// has diagnostics for that are no longer in it.
+pub(crate) fn handle_workspace_diagnostic(
+    snap: &StateSnapshot,
+    params: WorkspaceDiagnosticParams,
+) -> Result<WorkspaceDiagnosticReport> {
+    let previous: HashMap<_, _> = params
+        .previous_result_ids
+        .iter()
+        .map(|it| (&it.uri, it.value.as_str()))
+        .collect();
+    let mut items: Vec<_> = snap
+        .files
+        .iter()
+        .map(|(uri, &file)| {
+            let diagnostics =
+                diagnostics::file_diagnostics(&*snap.db, &snap.config, uri, file, &snap.files);
+            WorkspaceDocumentDiagnosticReport {
+                uri: uri.clone(),
+                version: snap.versions.get(uri).copied(),
+                report: diagnostic_report(diagnostics, previous.get(uri).copied()),
+            }
+        })
+        .collect();
+    for uri in previous.keys().filter(|it| !snap.files.contains_key(*it)) {
+        items.push(WorkspaceDocumentDiagnosticReport {
+            uri: (*uri).clone(),
+            version: None,
+            report: DocumentDiagnosticReport::Full(FullDocumentDiagnosticReport {
+                result_id: None,
+                items: Vec::new(),
+            }),
+        });
+    }
+    items.sort_by(|a, b| a.uri.cmp(&b.uri));
+    Ok(WorkspaceDiagnosticReport { items })
+}
+
+/// Leaves out the diagnostics if they are the ones the client already has.
+fn diagnostic_report(items: Vec<Diagnostic>, previous: Option<&str>) -> DocumentDiagnosticReport {
+    let result_id = diagnostics::result_id(&items);
+    match previous == Some(result_id.as_str()) {
+        true => {
+            DocumentDiagnosticReport::Unchanged(UnchangedDocumentDiagnosticReport { result_id })
+        }
+        false => DocumentDiagnosticReport::Full(FullDocumentDiagnosticReport {
+            result_id: Some(result_id),
+            items,
+        }),
+    }
+}
+
+pub(crate) fn handle_sample_tree(snap: &StateSnapshot, params: SampleTreeParams) -> Result<String> {
+    let uri = &params.text_document.uri;
+    let file = snap
+        .file(uri)
+        .ok_or_else(|| format!("unknown document {uri}"))?;
+    if !playground::is_sample(uri) {
+        return Err(Box::new(LspError {
+            code: ErrorCode::InvalidParams,
+            message: format!("{uri} is not a sample"),
+        }));
+    }
+    Ok(playground::check_sample(&*snap.db, uri, file, &snap.files).to_string())
+}
+
+pub(crate) fn handle_execute_command(
+    snap: &StateSnapshot,
+    params: ExecuteCommandParams,
+) -> Result<Option<serde_json::Value>> {
+    if params.command != ast_preview::COMMAND {
+        return Err(Box::new(LspError {
+            code: ErrorCode::InvalidParams,
+            message: format!("unknown command {:?}", params.command),
+        }));
+    }
+    let grammar = params
+        .arguments
+        .first()
+        .and_then(|it| serde_json::from_value::<Url>(it.clone()).ok());
+    let Some(grammar) = grammar else {
+        return Err(Box::new(LspError {
+            code: ErrorCode::InvalidParams,
+            message: format!("{} takes the URI of a grammar", ast_preview::COMMAND),
+        }));
+    };
+    let file = previewed_grammar(snap, &grammar)?;
+    let preview = AstPreview {
+        uri: ast_preview::preview_uri(&grammar),
+        text: ast_preview::ast_source(&*snap.db, file),
+    };
+    Ok(Some(serde_json::to_value(preview)?))
+}
+
+/// The text of an AST preview.
+pub(crate) fn handle_text_document_content(
+    snap: &StateSnapshot,
+    params: TextDocumentContentParams,
+) -> Result<TextDocumentContentResult> {
+    let Some(grammar) = ast_preview::grammar_uri(&params.uri) else {
+        return Err(Box::new(LspError {
+            code: ErrorCode::InvalidParams,
+            message: format!("{} is not an AST preview", params.uri),
+        }));
+    };
+    let file = previewed_grammar(snap, &grammar)?;
+    Ok(TextDocumentContentResult {
+        text: ast_preview::ast_source(&*snap.db, file),
+    })
+}
+
+fn previewed_grammar(snap: &StateSnapshot, uri: &Url) -> Result<FileId> {
+    match snap.file(uri) {
+        Some(file) if !playground::is_sample(uri) => Ok(file),
+        _ => Err(Box::new(LspError {
+            code: ErrorCode::InvalidParams,
+            message: format!("{uri} is not a known grammar"),
+        })),
+    }
+}
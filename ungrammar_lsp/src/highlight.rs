@@ -0,0 +1,170 @@
+//! Semantic highlighting: what each token of a grammar means.
+use std::collections::HashSet;
+
+use rowan::{NodeOrToken, TextRange};
+
+use crate::{
+    db::{FileId, GrammarDatabase},
+    syntax::{ast, SyntaxKind::*, SyntaxToken},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum HlTag {
+    /// The name of a rule.
+    NodeDef,
+    NodeRef,
+    /// A reference to a node that no rule defines.
+    UndefinedRef,
+    TokenLit,
+    /// A token the lexer provides, like `'lex:ident'`.
+    LexToken,
+    Label,
+    Operator,
+    /// A `%` keyword.
+    Directive,
+    Comment,
+    DocComment,
+}
+
+/// The highlighted tokens of `file` that intersect `range`, or of the whole
+/// file, in order.
+pub(crate) fn highlight(
+    db: &dyn GrammarDatabase,
+    file: FileId,
+    range: Option<TextRange>,
+) -> Vec<(TextRange, HlTag)> {
+    let root = db.parse(file).syntax_node();
+    let grammar = db.grammar(file);
+    let undefined: HashSet<&str> = grammar.undefined.iter().map(|it| it.as_str()).collect();
+    let lex_prefix = db.lex_prefix();
+    let is_lex = |token: &SyntaxToken| {
+        // An empty prefix means there is no such convention.
+        !lex_prefix.is_empty() && token.text()[1..].starts_with(&*lex_prefix)
+    };
+    let docs: HashSet<SyntaxToken> = root
+        .children()
+        .filter_map(ast::Rule::cast)
+        .flat_map(|it| it.doc_comment_tokens())
+        .collect();
+
+    let range = match range {
+        Some(range) => match range.intersect(root.text_range()) {
+            Some(range) => Some(range),
+            None => return Vec::new(),
+        },
+        None => None,
+    };
+    let elements = match range {
+        Some(range) => match root.covering_element(range) {
+            NodeOrToken::Node(node) => node.descendants_with_tokens(),
+            NodeOrToken::Token(token) => token.parent().unwrap().descendants_with_tokens(),
+        },
+        None => root.descendants_with_tokens(),
+    };
+    let mut res = Vec::new();
+    for token in elements.filter_map(|it| it.into_token()) {
+        let outside = |range: TextRange| {
+            token.text_range().end() <= range.start() || range.end() <= token.text_range().start()
+        };
+        if range.is_some_and(outside) {
+            continue;
+        }
+        let parent = token.parent().map(|it| it.kind());
+        let tag = match token.kind() {
+            COMMENT if docs.contains(&token) => HlTag::DocComment,
+            COMMENT => HlTag::Comment,
+            IDENT => match parent {
+                Some(RULE) => HlTag::NodeDef,
+                Some(NODE_REF) if undefined.contains(token.text()) => HlTag::UndefinedRef,
+                Some(NODE_REF) => HlTag::NodeRef,
+                Some(LABELED_RULE) => HlTag::Label,
+                _ => continue,
+            },
+            TOKEN_LIT if is_lex(&token) => HlTag::LexToken,
+            TOKEN_LIT => HlTag::TokenLit,
+            EQ | STAR | QMARK | PIPE | COLON => HlTag::Operator,
+            DIRECTIVE => HlTag::Directive,
+            _ => continue,
+        };
+        res.push((token.text_range(), tag));
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::db::{RootDatabase, SourceDatabase};
+
+    fn check(text: &str, expected: &[(&str, HlTag)]) {
+        let mut db = RootDatabase::default();
+        let file = FileId(0);
+        db.set_file_text(file, Arc::new(text.to_string()));
+        let found: Vec<_> = highlight(&db, file, None)
+            .into_iter()
+            .map(|(range, tag)| (&text[range], tag))
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn tags_names_by_meaning() {
+        use HlTag::*;
+        check(
+            "// Header\n\n// Doc\nA = x:B* | 'lex:ident' C? // trailing\nB = '+'\n",
+            &[
+                ("// Header", Comment),
+                ("// Doc", DocComment),
+                ("A", NodeDef),
+                ("=", Operator),
+                ("x", Label),
+                (":", Operator),
+                ("B", NodeRef),
+                ("*", Operator),
+                ("|", Operator),
+                ("'lex:ident'", LexToken),
+                ("C", UndefinedRef),
+                ("?", Operator),
+                ("// trailing", Comment),
+                ("B", NodeDef),
+                ("=", Operator),
+                ("'+'", TokenLit),
+            ],
+        );
+    }
+
+    #[test]
+    fn highlights_precedence_declarations() {
+        use HlTag::*;
+        check(
+            "E = E '+' E\n%precedence E %left '+'\n",
+            &[
+                ("E", NodeDef),
+                ("=", Operator),
+                ("E", NodeRef),
+                ("'+'", TokenLit),
+                ("E", NodeRef),
+                ("%precedence", Directive),
+                ("E", NodeRef),
+                ("%left", Directive),
+                ("'+'", TokenLit),
+            ],
+        );
+    }
+
+    #[test]
+    fn follows_the_lex_prefix() {
+        use HlTag::*;
+        let mut db = RootDatabase::default();
+        db.set_lex_prefix(Arc::from("lexer:"));
+        db.set_file_text(FileId(0), Arc::new("A = 'lexer:x' 'lex:y'".to_string()));
+        let tags: Vec<_> = highlight(&db, FileId(0), None)
+            .into_iter()
+            .map(|(_, tag)| tag)
+            .filter(|it| matches!(it, LexToken | TokenLit))
+            .collect();
+        assert_eq!(tags, [LexToken, TokenLit]);
+    }
+}
@@ -0,0 +1,205 @@
+//! Hover documentation for nodes and tokens, as Markdown.
+use std::fmt::Write;
+
+use rowan::{TextRange, TextSize};
+
+use crate::{
+    db::{FileId, SourceDatabase},
+    navigation::{self, Symbol},
+    printer,
+    syntax::{ast, escape_token, SyntaxKind::*, SyntaxNode},
+};
+
+/// Returns the Markdown to show for the name at `offset`, and the range of
+/// that name.
+pub(crate) fn hover(
+    db: &dyn SourceDatabase,
+    file: FileId,
+    offset: TextSize,
+) -> Option<(TextRange, String)> {
+    let (symbol, range) = navigation::symbol_at(db, file, offset)?;
+    let root = db.parse(file).syntax_node();
+    let markdown = match &symbol {
+        Symbol::Node(name) => node_hover(db, file, &root, name, &symbol),
+        Symbol::Token(value) => token_hover(db, file, &root, value, &symbol),
+    };
+    Some((range, markdown))
+}
+
+fn node_hover(
+    db: &dyn SourceDatabase,
+    file: FileId,
+    root: &SyntaxNode,
+    name: &str,
+    symbol: &Symbol,
+) -> String {
+    let mut res = rule_docs(root, name)
+        .unwrap_or_else(|| format!("```ungrammar\n{name}\n```\n\nNo rule defines this node.\n"));
+
+    let references = navigation::references(db, file, symbol, false);
+    let mut labels: Vec<String> = Vec::new();
+    for range in &references {
+        let label = root
+            .covering_element(*range)
+            .ancestors()
+            .find(|it| !matches!(it.kind(), NODE_REF | OPT_RULE | REP_RULE))
+            .and_then(ast::LabeledRule::cast)
+            .and_then(|it| it.label());
+        if let Some(label) = label {
+            if !labels.iter().any(|it| it == label.text()) {
+                labels.push(label.text().to_string());
+            }
+        }
+    }
+    write!(res, "\n---\n\n{}", plural(references.len(), "reference")).unwrap();
+    if !labels.is_empty() {
+        let labels: Vec<_> = labels.iter().map(|it| format!("`{it}`")).collect();
+        write!(res, " · labels: {}", labels.join(", ")).unwrap();
+    }
+    res
+}
+
+/// The pretty-printed rule of `name` followed by its doc comment, if the
+/// node has a rule.
+pub(crate) fn rule_docs(root: &SyntaxNode, name: &str) -> Option<String> {
+    let rule = root
+        .children()
+        .filter_map(ast::Rule::cast)
+        .find(|it| it.name().is_some_and(|it| it.text() == name))?;
+    let mut res = format!("```ungrammar\n{}\n```\n", printer::print_rule(&rule));
+    if let Some(doc) = rule.doc_comment() {
+        writeln!(res, "\n---\n\n{doc}").unwrap();
+    }
+    Some(res)
+}
+
+fn token_hover(
+    db: &dyn SourceDatabase,
+    file: FileId,
+    root: &SyntaxNode,
+    value: &str,
+    symbol: &Symbol,
+) -> String {
+    let mut users: Vec<String> = Vec::new();
+    for range in navigation::references(db, file, symbol, false) {
+        let rule = root
+            .covering_element(range)
+            .ancestors()
+            .find_map(ast::Rule::cast)
+            .and_then(|it| it.name());
+        if let Some(name) = rule {
+            if !users.iter().any(|it| it == name.text()) {
+                users.push(name.text().to_string());
+            }
+        }
+    }
+    let mut res = String::new();
+    writeln!(res, "```ungrammar\n{}\n```\n\n---\n", escape_token(value)).unwrap();
+    if users.is_empty() {
+        res.push_str("Not used by any node");
+    } else {
+        let users: Vec<_> = users.iter().map(|it| format!("`{it}`")).collect();
+        write!(res, "Used by {}", users.join(", ")).unwrap();
+    }
+    res
+}
+
+/// The hover for clients that cannot render Markdown: code blocks and
+/// inline code lose their markers, and separators become blank lines.
+pub(crate) fn to_plain_text(markdown: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut in_code = false;
+    for line in markdown.lines() {
+        if line.starts_with("```") {
+            in_code = !in_code;
+            continue;
+        }
+        let line = match in_code {
+            true => line.to_string(),
+            false if line == "---" => String::new(),
+            false => line.replace('`', ""),
+        };
+        if line.is_empty() && lines.last().is_none_or(|it| it.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(|it| it.is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+fn plural(n: usize, what: &str) -> String {
+    match n {
+        1 => format!("1 {what}"),
+        _ => format!("{n} {what}s"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::db::RootDatabase;
+
+    fn check(text: &str, expected: &str) {
+        let offset = TextSize::from(text.find("$0").unwrap() as u32);
+        let text = text.replace("$0", "");
+        let mut db = RootDatabase::default();
+        let file = FileId(0);
+        db.set_file_text(file, Arc::new(text));
+        let (_, markdown) = hover(&db, file, offset).unwrap();
+        assert_eq!(markdown, expected);
+    }
+
+    #[test]
+    fn shows_rule_docs_and_usages() {
+        check(
+            "\
+BlockUrl = 'b'
+ResourceUrl = 'r' // not a doc comment
+// Internal or external path.
+//
+// [example](/etc/block#const:c5143b)
+HrefUrl =   BlockUrl | ResourceUrl
+
+HrefToken = '[' ref:HrefUrl$0 ']' | href:HrefUrl? | HrefUrl* | ref:HrefUrl
+",
+            "\
+```ungrammar
+HrefUrl =
+  BlockUrl
+| ResourceUrl
+```
+
+---
+
+Internal or external path.
+
+[example](/etc/block#const:c5143b)
+
+---
+
+4 references · labels: `ref`, `href`",
+        );
+    }
+
+    #[test]
+    fn renders_plain_text() {
+        let markdown = "```ungrammar\nA =\n  '`'\n| B\n```\n\n---\n\nDocs.\n\n---\n\n1 reference · labels: `a`";
+        assert_eq!(
+            to_plain_text(markdown),
+            "A =\n  '`'\n| B\n\nDocs.\n\n1 reference · labels: a"
+        );
+    }
+
+    #[test]
+    fn shows_users_of_tokens() {
+        check(
+            "A = '+'$0 B\nB = '+' | '-'\nC = '+'",
+            "```ungrammar\n'+'\n```\n\n---\n\nUsed by `A`, `B`, `C`",
+        );
+    }
+}
@@ -0,0 +1,124 @@
+//! Inlay hints with the type of the AST field each label or node reference
+//! turns into, as `codegen` infers it.
+use codegen::Lowered;
+use rowan::{TextRange, TextSize};
+
+use crate::{
+    db::{FileId, GrammarDatabase},
+    syntax::{ast, SyntaxKind::*, SyntaxNode},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InlayHint {
+    /// Where the hint goes: right after the label or reference.
+    pub(crate) offset: TextSize,
+    pub(crate) label: String,
+}
+
+/// The hints of `file` within `range`, or of the whole file.
+pub(crate) fn inlay_hints(
+    db: &dyn GrammarDatabase,
+    file: FileId,
+    range: Option<TextRange>,
+) -> Vec<InlayHint> {
+    let parse = db.parse(file);
+    let root = parse.syntax_node();
+    let grammar = db.grammar(file).best_effort.clone();
+    let mut seen = Vec::new();
+    let mut res = Vec::new();
+    for rule in root.children().filter_map(ast::Rule::cast) {
+        let Some(name) = rule.name() else { continue };
+        // Only the first rule of a node is in the grammar.
+        if seen.contains(&name.text().to_string()) {
+            continue;
+        }
+        seen.push(name.text().to_string());
+        let rule_range = rule.syntax.text_range();
+        if range.is_some_and(|range| range.intersect(rule_range).is_none()) {
+            continue;
+        }
+        if parse
+            .errors
+            .iter()
+            .any(|it| rule_range.contains_range(it.range))
+        {
+            continue;
+        }
+        let Some(node) = grammar.iter().find(|it| grammar[*it].name == name.text()) else {
+            continue;
+        };
+        let Lowered::Node(lowered) = codegen::lower_node(&grammar, node) else {
+            continue;
+        };
+        let mut occurrences = Vec::new();
+        if let Some(body) = rule.body() {
+            collect_occurrences(body.syntax(), &mut occurrences);
+        }
+        // A rule that failed to lower in full.
+        if occurrences.len() != lowered.occurrences.len() {
+            continue;
+        }
+        for (syntax, &field) in occurrences.iter().zip(&lowered.occurrences) {
+            res.push(InlayHint {
+                offset: syntax.text_range().end(),
+                label: format!(": {}", lowered.fields[field].ty()),
+            });
+        }
+    }
+    res
+}
+
+/// Labeled rules and unlabeled node references, in the order `codegen`
+/// visits them.
+fn collect_occurrences(syntax: &SyntaxNode, acc: &mut Vec<SyntaxNode>) {
+    match syntax.kind() {
+        LABELED_RULE | NODE_REF => acc.push(syntax.clone()),
+        _ => syntax
+            .children()
+            .for_each(|it| collect_occurrences(&it, acc)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::db::{RootDatabase, SourceDatabase};
+
+    /// Renders the hints into the text, like an editor would.
+    fn check(text: &str, expected: &str) {
+        let mut db = RootDatabase::default();
+        let file = FileId(0);
+        db.set_file_text(file, Arc::new(text.to_string()));
+        let mut res = text.to_string();
+        for hint in inlay_hints(&db, file, None).iter().rev() {
+            res.insert_str(usize::from(hint.offset), &format!("[{}]", hint.label));
+        }
+        assert_eq!(res, expected);
+    }
+
+    #[test]
+    fn shows_field_types() {
+        check(
+            "\
+ResourceUrl = (scheme:Scheme '://')? path:Path query:QueryParams
+QueryParams = '?' KvParam ('&' KvParam)*
+Path = AbsolutePath | RelativePath
+",
+            "\
+ResourceUrl = (scheme:Scheme[: Option<Scheme>] '://')? path:Path[: Path] query:QueryParams[: QueryParams]
+QueryParams = '?' KvParam[: AstChildren<KvParam>] ('&' KvParam[: AstChildren<KvParam>])*
+Path = AbsolutePath | RelativePath
+",
+        );
+    }
+
+    #[test]
+    fn skips_broken_and_duplicate_rules() {
+        check(
+            "A = B (C\nB = x:C op:('+' | '-')\nB = C\n",
+            "A = B (C\nB = x:C[: C] op:('+' | '-')[: SyntaxToken]\nB = C\n",
+        );
+    }
+}
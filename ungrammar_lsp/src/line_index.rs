@@ -0,0 +1,159 @@
+//! Conversion between byte offsets and LSP line/character positions.
+//!
+//! LSP positions count characters in UTF-16 code units, unless the client
+//! agreed to UTF-8, while the syntax tree works with UTF-8 byte offsets.
+//! [`LineIndex`] remembers where lines start and where the multi-byte
+//! characters are, so conversions do not need the text itself.
+use lsp_types::{Position, PositionEncodingKind, Range};
+use rowan::{TextRange, TextSize};
+
+/// What the `character` of a position counts.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum PositionEncoding {
+    /// Bytes, like the syntax tree.
+    Utf8,
+    /// What every client understands.
+    #[default]
+    Utf16,
+}
+
+impl PositionEncoding {
+    pub(crate) fn kind(self) -> PositionEncodingKind {
+        match self {
+            PositionEncoding::Utf8 => PositionEncodingKind::UTF8,
+            PositionEncoding::Utf16 => PositionEncodingKind::UTF16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct LineIndex {
+    /// Offset of the first byte of every line.
+    line_starts: Vec<TextSize>,
+    /// Non-ASCII characters of every line, as (column in bytes, UTF-8 length,
+    /// UTF-16 length).
+    wide_chars: Vec<Vec<(u32, u32, u32)>>,
+    len: TextSize,
+    encoding: PositionEncoding,
+}
+
+impl LineIndex {
+    pub(crate) fn new(text: &str, encoding: PositionEncoding) -> LineIndex {
+        let mut line_starts = vec![TextSize::from(0)];
+        let mut wide_chars = vec![Vec::new()];
+        let mut line_start = 0;
+        for (offset, c) in text.char_indices() {
+            if c == '\n' {
+                line_start = offset + 1;
+                line_starts.push(TextSize::from(line_start as u32));
+                wide_chars.push(Vec::new());
+            } else if !c.is_ascii() {
+                let column = (offset - line_start) as u32;
+                let entry = (column, c.len_utf8() as u32, c.len_utf16() as u32);
+                wide_chars.last_mut().unwrap().push(entry);
+            }
+        }
+        LineIndex {
+            line_starts,
+            wide_chars,
+            len: TextSize::of(text),
+            encoding,
+        }
+    }
+
+    /// How many units of the position encoding a wide character takes.
+    fn width(&self, utf8: u32, utf16: u32) -> u32 {
+        match self.encoding {
+            PositionEncoding::Utf8 => utf8,
+            PositionEncoding::Utf16 => utf16,
+        }
+    }
+
+    pub(crate) fn position(&self, offset: TextSize) -> Position {
+        let offset = offset.min(self.len);
+        let line = self.line_starts.partition_point(|&it| it <= offset) - 1;
+        let column = u32::from(offset - self.line_starts[line]);
+        let mut character = column;
+        for &(start, utf8, utf16) in &self.wide_chars[line] {
+            if start >= column {
+                break;
+            }
+            character = character - utf8 + self.width(utf8, utf16);
+        }
+        Position {
+            line: line as u32,
+            character,
+        }
+    }
+
+    /// The inverse of [`LineIndex::position`]. Positions past the end of a
+    /// line are clamped to its end, as the protocol asks.
+    pub(crate) fn offset(&self, position: Position) -> TextSize {
+        let line = position.line as usize;
+        let Some(&line_start) = self.line_starts.get(line) else {
+            return self.len;
+        };
+        let line_end = match self.line_starts.get(line + 1) {
+            Some(&next) => next - TextSize::from(1),
+            None => self.len,
+        };
+        // Bytes minus encoding units of the wide characters seen so far.
+        let mut excess = 0;
+        for &(start, utf8, utf16) in &self.wide_chars[line] {
+            let width = self.width(utf8, utf16);
+            let start_units = start - excess;
+            if position.character < start_units + width {
+                if position.character > start_units {
+                    // Inside a character: snap to its start.
+                    return line_start + TextSize::from(start);
+                }
+                break;
+            }
+            excess += utf8 - width;
+        }
+        (line_start + TextSize::from(position.character + excess)).min(line_end)
+    }
+
+    pub(crate) fn range(&self, range: TextRange) -> Range {
+        Range {
+            start: self.position(range.start()),
+            end: self.position(range.end()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_utf16_code_units() {
+        let text = "A = 'é'\n// 😀 x\nB";
+        let index = LineIndex::new(text, PositionEncoding::Utf16);
+        let x = TextSize::from(text.find('x').unwrap() as u32);
+        assert_eq!(index.position(x), Position::new(1, 6));
+        assert_eq!(index.offset(Position::new(1, 6)), x);
+    }
+
+    #[test]
+    fn counts_bytes_in_utf8() {
+        let text = "A = 'é'\n// 😀 x\nB";
+        let index = LineIndex::new(text, PositionEncoding::Utf8);
+        let x = TextSize::from(text.find('x').unwrap() as u32);
+        assert_eq!(index.position(x), Position::new(1, 8));
+        assert_eq!(index.offset(Position::new(1, 8)), x);
+        // The middle of the emoji.
+        let emoji = TextSize::from(text.find('😀').unwrap() as u32);
+        assert_eq!(index.offset(Position::new(1, 5)), emoji);
+    }
+
+    #[test]
+    fn offsets_clamp_to_line_end() {
+        let text = "A = 'é'\nB";
+        let index = LineIndex::new(text, PositionEncoding::Utf16);
+        assert_eq!(index.offset(Position::new(0, 6)), TextSize::from(7));
+        assert_eq!(index.offset(Position::new(0, 99)), TextSize::from(8));
+        assert_eq!(index.offset(Position::new(1, 99)), TextSize::of(text));
+        assert_eq!(index.offset(Position::new(7, 0)), TextSize::of(text));
+    }
+}
@@ -0,0 +1,212 @@
+//! Semantic checks on a resolved grammar, reported with source ranges.
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use rowan::TextRange;
+
+use ungrammar_fork::PrecedenceErrorKind;
+
+use crate::{
+    db::{FileId, GrammarDatabase},
+    grammar::PrecedenceProblem,
+    syntax::ast,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum LintKind {
+    UndefinedNode,
+    DuplicateRule,
+    Precedence,
+    UnreachableNode,
+}
+
+impl LintKind {
+    pub(crate) fn code(self) -> &'static str {
+        match self {
+            LintKind::UndefinedNode => "undefined-node",
+            LintKind::DuplicateRule => "duplicate-rule",
+            LintKind::Precedence => "precedence",
+            LintKind::UnreachableNode => "unreachable-node",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Lint {
+    pub(crate) kind: LintKind,
+    pub(crate) range: TextRange,
+    pub(crate) message: String,
+    /// Other places that explain the problem, like the first definition of a
+    /// duplicated rule.
+    pub(crate) related: Vec<(TextRange, String)>,
+}
+
+pub(crate) fn lints(db: &dyn GrammarDatabase, file: FileId) -> Arc<Vec<Lint>> {
+    let resolved = db.grammar(file);
+    let Some(source_file) = ast::SourceFile::cast(db.parse(file).syntax_node()) else {
+        return Arc::default();
+    };
+
+    let mut res = Vec::new();
+    let mut first_definitions: HashMap<String, TextRange> = HashMap::new();
+    for rule in source_file.rules() {
+        let Some(name) = rule.name() else { continue };
+        if !resolved.duplicates.iter().any(|it| it == name.text()) {
+            continue;
+        }
+        match first_definitions.get(name.text()) {
+            Some(&first) => res.push(Lint {
+                kind: LintKind::DuplicateRule,
+                range: name.text_range(),
+                message: format!("duplicate rule: `{}`", name.text()),
+                related: vec![(first, "first defined here".to_string())],
+            }),
+            None => {
+                first_definitions.insert(name.text().to_string(), name.text_range());
+            }
+        }
+    }
+
+    for node_ref in source_file
+        .syntax
+        .descendants()
+        .filter_map(ast::NodeRef::cast)
+    {
+        let Some(name) = node_ref.name() else {
+            continue;
+        };
+        if resolved.undefined.iter().any(|it| it == name.text()) {
+            res.push(Lint {
+                kind: LintKind::UndefinedNode,
+                range: name.text_range(),
+                message: format!("undefined node: `{}`", name.text()),
+                related: Vec::new(),
+            });
+        }
+    }
+    precedence_lints(&source_file, &resolved.precedence_errors, &mut res);
+    unreachable_lints(&source_file, &db.root_nodes(), &mut res);
+    res.sort_by_key(|it| it.range.start());
+    Arc::new(res)
+}
+
+/// Flags the rules that cannot be reached from any of `roots`. Files that
+/// define none of the roots are left alone, as they are not the grammar the
+/// roots belong to.
+fn unreachable_lints(source_file: &ast::SourceFile, roots: &[String], acc: &mut Vec<Lint>) {
+    let rules: Vec<_> = source_file
+        .rules()
+        .filter_map(|rule| Some((rule.name()?, rule)))
+        .collect();
+    let mut reachable: HashSet<&str> = HashSet::new();
+    let mut stack: Vec<_> = roots
+        .iter()
+        .map(String::as_str)
+        .filter(|root| rules.iter().any(|(name, _)| name.text() == *root))
+        .collect();
+    if stack.is_empty() {
+        return;
+    }
+    while let Some(node) = stack.pop() {
+        if !reachable.insert(node) {
+            continue;
+        }
+        for (_, rule) in rules.iter().filter(|(name, _)| name.text() == node) {
+            let refs = rule.syntax.descendants().filter_map(ast::NodeRef::cast);
+            stack.extend(refs.filter_map(|it| {
+                let name = it.name()?;
+                let (defined, _) = rules.iter().find(|(it, _)| it.text() == name.text())?;
+                Some(defined.text())
+            }));
+        }
+    }
+    let mut reported = HashSet::new();
+    for (name, _) in &rules {
+        if reachable.contains(name.text()) || !reported.insert(name.text()) {
+            continue;
+        }
+        acc.push(Lint {
+            kind: LintKind::UnreachableNode,
+            range: name.text_range(),
+            message: format!("unreachable node: `{}`", name.text()),
+            related: Vec::new(),
+        });
+    }
+}
+
+fn precedence_lints(
+    source_file: &ast::SourceFile,
+    problems: &[PrecedenceProblem],
+    acc: &mut Vec<Lint>,
+) {
+    let decls: Vec<_> = source_file
+        .precedences()
+        .filter_map(|decl| {
+            let name = decl.node()?.name()?;
+            Some((name, decl))
+        })
+        .collect();
+    let decls_of = |node: &str| -> Vec<_> {
+        decls
+            .iter()
+            .filter(|(name, _)| name.text() == node)
+            .collect()
+    };
+    // Tokens listed more than once have a problem for every listing, and
+    // each one is reported for all of them at once.
+    let mut reported = HashSet::new();
+    for problem in problems {
+        match problem {
+            PrecedenceProblem::Duplicate(node) => {
+                let decls = decls_of(node);
+                let Some((first, _)) = decls.first() else {
+                    continue;
+                };
+                for (name, _) in &decls[1..] {
+                    acc.push(Lint {
+                        kind: LintKind::Precedence,
+                        range: name.text_range(),
+                        message: format!("duplicate precedence declaration: `{node}`"),
+                        related: vec![(first.text_range(), "first declared here".to_string())],
+                    });
+                }
+            }
+            PrecedenceProblem::Operator {
+                node,
+                token,
+                kind,
+                message,
+            } => {
+                if !reported.insert((node, token, *kind)) {
+                    continue;
+                }
+                let Some((name, decl)) = decls_of(node).first().copied() else {
+                    continue;
+                };
+                let mentions: Vec<_> = decl
+                    .levels()
+                    .flat_map(|it| it.tokens())
+                    .filter(|it| it.value().as_deref() == Some(token.as_str()))
+                    .filter_map(|it| it.token())
+                    .collect();
+                let ranges: Vec<_> = match kind {
+                    PrecedenceErrorKind::MissingLevel => vec![name.text_range()],
+                    PrecedenceErrorKind::NotAnOperator => {
+                        mentions.iter().map(|it| it.text_range()).collect()
+                    }
+                    PrecedenceErrorKind::DuplicateLevel => {
+                        mentions.iter().skip(1).map(|it| it.text_range()).collect()
+                    }
+                };
+                acc.extend(ranges.into_iter().map(|range| Lint {
+                    kind: LintKind::Precedence,
+                    range,
+                    message: message.clone(),
+                    related: Vec::new(),
+                }));
+            }
+        }
+    }
+}
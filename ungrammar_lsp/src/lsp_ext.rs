@@ -0,0 +1,176 @@
+//! Protocol the `lsp-types` version in use does not cover yet, the pull
+//! model for diagnostics from LSP 3.17, and the requests only this server
+//! knows about.
+use lsp_types::{
+    request::Request, Diagnostic, PartialResultParams, TextDocumentIdentifier, Url,
+    WorkDoneProgressParams,
+};
+use serde::{Deserialize, Serialize};
+
+pub(crate) enum DocumentDiagnosticRequest {}
+
+impl Request for DocumentDiagnosticRequest {
+    type Params = DocumentDiagnosticParams;
+    type Result = DocumentDiagnosticReport;
+    const METHOD: &'static str = "textDocument/diagnostic";
+}
+
+pub(crate) enum WorkspaceDiagnosticRequest {}
+
+impl Request for WorkspaceDiagnosticRequest {
+    type Params = WorkspaceDiagnosticParams;
+    type Result = WorkspaceDiagnosticReport;
+    const METHOD: &'static str = "workspace/diagnostic";
+}
+
+/// Asks the client to pull diagnostics again, for changes it cannot know
+/// about, like settings or files changed on disk.
+pub(crate) enum WorkspaceDiagnosticRefresh {}
+
+impl Request for WorkspaceDiagnosticRefresh {
+    type Params = ();
+    type Result = ();
+    const METHOD: &'static str = "workspace/diagnostic/refresh";
+}
+
+/// The `diagnosticProvider` server capability.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DiagnosticOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) identifier: Option<String>,
+    /// Whether a change to one document can change the diagnostics of
+    /// another.
+    pub(crate) inter_file_dependencies: bool,
+    pub(crate) workspace_diagnostics: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DocumentDiagnosticParams {
+    pub(crate) text_document: TextDocumentIdentifier,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) identifier: Option<String>,
+    /// The `resultId` of the report the client has.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) previous_result_id: Option<String>,
+    #[serde(flatten)]
+    pub(crate) work_done_progress_params: WorkDoneProgressParams,
+    #[serde(flatten)]
+    pub(crate) partial_result_params: PartialResultParams,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub(crate) enum DocumentDiagnosticReport {
+    Full(FullDocumentDiagnosticReport),
+    /// The diagnostics did not change since the report with this id.
+    Unchanged(UnchangedDocumentDiagnosticReport),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct FullDocumentDiagnosticReport {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) result_id: Option<String>,
+    pub(crate) items: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct UnchangedDocumentDiagnosticReport {
+    pub(crate) result_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct WorkspaceDiagnosticParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) identifier: Option<String>,
+    /// The reports the client already has.
+    pub(crate) previous_result_ids: Vec<PreviousResultId>,
+    #[serde(flatten)]
+    pub(crate) work_done_progress_params: WorkDoneProgressParams,
+    #[serde(flatten)]
+    pub(crate) partial_result_params: PartialResultParams,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct PreviousResultId {
+    pub(crate) uri: Url,
+    pub(crate) value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct WorkspaceDiagnosticReport {
+    pub(crate) items: Vec<WorkspaceDocumentDiagnosticReport>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct WorkspaceDocumentDiagnosticReport {
+    pub(crate) uri: Url,
+    /// The version of the open document the report is for, `None` for
+    /// files that are not open.
+    pub(crate) version: Option<i32>,
+    #[serde(flatten)]
+    pub(crate) report: DocumentDiagnosticReport,
+}
+
+/// The tree the grammar playground reads a sample as, as text, see
+/// [`crate::playground`].
+pub(crate) enum SampleTree {}
+
+impl Request for SampleTree {
+    type Params = SampleTreeParams;
+    type Result = String;
+    const METHOD: &'static str = "ungrammar/sampleTree";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SampleTreeParams {
+    pub(crate) text_document: TextDocumentIdentifier,
+}
+
+/// The text of a document the server makes up, like the preview of the
+/// AST of a grammar. From LSP 3.18.
+pub(crate) enum TextDocumentContentRequest {}
+
+impl Request for TextDocumentContentRequest {
+    type Params = TextDocumentContentParams;
+    type Result = TextDocumentContentResult;
+    const METHOD: &'static str = "workspace/textDocumentContent";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct TextDocumentContentParams {
+    pub(crate) uri: Url,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct TextDocumentContentResult {
+    pub(crate) text: String,
+}
+
+/// Asks the client to get the text of a made-up document again.
+pub(crate) enum TextDocumentContentRefresh {}
+
+impl Request for TextDocumentContentRefresh {
+    type Params = TextDocumentContentParams;
+    type Result = ();
+    const METHOD: &'static str = "workspace/textDocumentContent/refresh";
+}
+
+/// The `textDocumentContent` server capability.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct TextDocumentContentOptions {
+    pub(crate) schemes: Vec<String>,
+}
+
+/// What `ungrammar.previewAst` returns: where the preview lives, and its
+/// text for clients that cannot ask for it.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct AstPreview {
+    pub(crate) uri: Url,
+    pub(crate) text: String,
+}
@@ -1,80 +1,531 @@
-use std::{error::Error, io::Read};
+mod assists;
+mod ast_preview;
+mod capabilities;
+mod check;
+mod completion;
+mod config;
+mod db;
+mod dependencies;
+mod diagnostics;
+mod dispatch;
+mod document;
+mod folding;
+mod formatting;
+mod grammar;
+mod handlers;
+mod highlight;
+mod hover;
+mod inlay_hints;
+mod line_index;
+mod lints;
+mod lsp_ext;
+mod navigation;
+mod playground;
+mod printer;
+mod rename;
+mod selection;
+mod symbols;
+mod syntax;
+mod task_pool;
+mod transport;
+mod workspace;
 
-use lsp_server::{Connection, Message, Notification as NotificationData};
+use std::{collections::{BTreeSet, HashMap}, error::Error, fs, path::PathBuf, process::ExitCode, sync::Arc, thread};
+
+use lsp_server::{
+    Connection, ErrorCode, Message, Notification as NotificationData, Request as RequestData,
+    RequestId, Response,
+};
+use salsa::ParallelDatabase;
 use lsp_types::{
-    InitializeParams, ClientCapabilities, ServerCapabilities, 
-    TextDocumentSyncCapability, TextDocumentSyncKind, 
-    notification::{DidChangeTextDocument, Notification, LogMessage, PublishDiagnostics}, 
-    DidChangeTextDocumentParams, VersionedTextDocumentIdentifier, 
-    LogMessageParams, MessageType, PublishDiagnosticsParams, Diagnostic, 
-    DiagnosticSeverity, Range,  Position
+    InitializeParams, ClientCapabilities, RegistrationParams,
+    notification::{
+        Cancel, DidChangeConfiguration, DidChangeTextDocument, DidChangeWatchedFiles,
+        DidCloseTextDocument, DidOpenTextDocument, Exit, Notification, Progress,
+        PublishDiagnostics,
+    },
+    request::{CallHierarchyIncomingCalls, CallHierarchyOutgoingCalls, CallHierarchyPrepare, CodeActionRequest, CodeLensRequest, Completion, DocumentHighlightRequest, ExecuteCommand, FoldingRangeRequest, SelectionRangeRequest, DocumentSymbolRequest, Formatting, RangeFormatting, GotoDefinition, HoverRequest, InlayHintRequest, ResolveCompletionItem, PrepareRenameRequest, References, RegisterCapability, Rename, Request, SemanticTokensFullRequest, Shutdown, SemanticTokensRangeRequest, WorkDoneProgressCreate, WorkspaceConfiguration, WorkspaceSymbolRequest},
+    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    TextDocumentItem, VersionedTextDocumentIdentifier, DidChangeConfigurationParams,
+    ConfigurationItem, ConfigurationParams, ExecuteCommandParams, DidChangeWatchedFilesParams, FileChangeType,
+    ProgressParams, ProgressParamsValue, ProgressToken, WorkDoneProgress, WorkDoneProgressBegin,
+    WorkDoneProgressCreateParams, WorkDoneProgressEnd, WorkDoneProgressReport, CancelParams, Diagnostic, NumberOrString, PublishDiagnosticsParams, Url,
+};
+
+use capabilities::ClientFeatures;
+use config::Config;
+use db::{FileId, GrammarDatabase, RootDatabase, SourceDatabase};
+use dispatch::RequestDispatcher;
+use lsp_ext::{
+    DocumentDiagnosticRequest, SampleTree, TextDocumentContentParams, TextDocumentContentRefresh,
+    TextDocumentContentRequest, WorkspaceDiagnosticRefresh, WorkspaceDiagnosticRequest,
 };
+use document::DocumentManager;
+use task_pool::TaskPool;
+
+/// Everything the server knows about the grammar files it was told about.
+#[derive(Default)]
+struct State {
+    db: RootDatabase,
+    documents: DocumentManager,
+    files: HashMap<Url, FileId>,
+    /// Where to look for grammar files that are not open.
+    workspace_roots: Vec<PathBuf>,
+    /// The grammar files under the workspace roots, open or not. Together
+    /// with the open documents, they make up the index that requests about
+    /// the whole workspace look at.
+    workspace_files: BTreeSet<Url>,
+    /// Bumped on every change to the database.
+    revision: u64,
+    /// Requests running on the task pool, with the revision they started at.
+    pending: HashMap<RequestId, u64>,
+    /// What the client agreed to in `initialize`.
+    features: Arc<ClientFeatures>,
+    /// The id of the last request the server sent to the client.
+    last_request_id: i32,
+    /// Requests sent to the client that it has not answered yet, by method.
+    sent_requests: HashMap<RequestId, &'static str>,
+    config: Arc<Config>,
+    /// The settings from `initializationOptions`, which the ones from the
+    /// client's settings apply to.
+    initial_config: Arc<Config>,
+    /// The grammars whose AST preview the client asked for, to tell it when
+    /// they change.
+    ast_previews: BTreeSet<Url>,
+}
+
+/// What handlers on the task pool get to see of the state.
+struct StateSnapshot {
+    db: salsa::Snapshot<RootDatabase>,
+    files: HashMap<Url, FileId>,
+    features: Arc<ClientFeatures>,
+    config: Arc<Config>,
+    /// The versions of the open documents.
+    versions: HashMap<Url, i32>,
+}
+
+impl StateSnapshot {
+    fn file(&self, uri: &Url) -> Option<FileId> {
+        self.files.get(uri).copied()
+    }
+}
+
+impl State {
+    fn file_id(&mut self, uri: &Url) -> FileId {
+        let next = FileId(self.files.len() as u32);
+        *self.files.entry(uri.clone()).or_insert(next)
+    }
+
+    fn file(&self, uri: &Url) -> Option<FileId> {
+        self.files.get(uri).copied()
+    }
+
+    /// Whether `uri` is open or a grammar under the workspace roots.
+    fn is_indexed(&self, uri: &Url) -> bool {
+        self.documents.get(uri).is_some() || self.workspace_files.contains(uri)
+    }
+
+    /// Sets the text of `uri`, leaving the database alone if it did not
+    /// change so that nothing gets recomputed.
+    fn set_file_text(&mut self, uri: &Url, text: String) -> FileId {
+        let known = self.files.contains_key(uri);
+        let file = self.file_id(uri);
+        if !known || *self.db.file_text(file) != text {
+            self.db.set_file_text(file, Arc::new(text));
+            self.revision += 1;
+        }
+        file
+    }
+
+    /// The files of the index. Closed documents and deleted files keep
+    /// their ids, but requests should not find them anymore.
+    fn indexed_files(&self) -> HashMap<Url, FileId> {
+        self.files.iter()
+            .filter(|(uri, _)| self.is_indexed(uri))
+            .map(|(uri, &file)| (uri.clone(), file))
+            .collect()
+    }
+
+    fn snapshot(&self) -> StateSnapshot {
+        StateSnapshot {
+            db: self.db.snapshot(),
+            files: self.indexed_files(),
+            features: self.features.clone(),
+            config: self.config.clone(),
+            versions: self.documents.versions().map(|(uri, version)| (uri.clone(), version)).collect(),
+        }
+    }
+
+    fn next_request_id(&mut self) -> RequestId {
+        self.last_request_id += 1;
+        RequestId::from(format!("ungrammar_lsp/{}", self.last_request_id))
+    }
+
+    /// Sends a request to the client. Its response comes back through the
+    /// main loop.
+    fn send_request<R: Request>(
+        &mut self,
+        params: R::Params,
+        lsp: &Connection
+    ) -> Result<(), Box<dyn Error + Sync + Send>> {
+        let id = self.next_request_id();
+        self.sent_requests.insert(id.clone(), R::METHOD);
+        let req = RequestData::new(id, R::METHOD.to_string(), params);
+        lsp.sender.send(req.into())?;
+        Ok(())
+    }
+
+    /// Asks the client for the `ungrammar` section of its settings.
+    fn request_config(&mut self, lsp: &Connection) -> Result<(), Box<dyn Error + Sync + Send>> {
+        let params = ConfigurationParams {
+            items: vec![ConfigurationItem {
+                scope_uri: None,
+                section: Some(config::SECTION.to_string()),
+            }],
+        };
+        self.send_request::<WorkspaceConfiguration>(params, lsp)
+    }
+
+    /// Replaces the settings with the ones the client sent, and re-checks
+    /// the open documents if anything changed.
+    fn update_config(
+        &mut self,
+        value: &serde_json::Value,
+        lsp: &Connection
+    ) -> Result<(), Box<dyn Error + Sync + Send>> {
+        let mut config = (*self.initial_config).clone();
+        for err in config.update(value) {
+            log::warn!("invalid setting {err}");
+        }
+        if config == *self.config {
+            return Ok(());
+        }
+        if *self.db.lex_prefix() != *config.lex_prefix {
+            self.db.set_lex_prefix(Arc::from(config.lex_prefix.as_str()));
+        }
+        if *self.db.root_nodes() != config.root_nodes {
+            self.db.set_root_nodes(Arc::new(config.root_nodes.clone()));
+        }
+        // Formatting and severities change even if the database did not.
+        self.revision += 1;
+        self.config = Arc::new(config);
+        let uris: Vec<_> = self.files.keys().filter(|it| self.is_indexed(it)).cloned().collect();
+        for uri in uris {
+            publish_file_diagnostics(self, &uri, lsp)?;
+        }
+        refresh_diagnostics(self, lsp)
+    }
+}
 
-use ungrammar_fork::Grammar;
+/// Feeds the current text of an open document to the database and reports
+/// what is wrong with it, and with the samples if it is a grammar.
+fn update_document(
+    state: &mut State,
+    uri: &Url,
+    lsp: &Connection
+) -> Result<(), Box<dyn Error + Sync + Send>> {
+    let Some(document) = state.documents.get(uri) else {
+        return Ok(());
+    };
+    let text = document.text.clone();
+    match playground::is_sample(uri) {
+        true => load_sample_grammar(state, uri, &text, lsp)?,
+        false => {
+            state.set_file_text(uri, text);
+            publish_sample_diagnostics(state, lsp)?;
+            refresh_ast_preview(state, uri, lsp)?;
+        }
+    }
+    publish_file_diagnostics(state, uri, lsp)
+}
+
+/// Adds the grammar a sample names to the index, if it is not in it yet.
+fn load_sample_grammar(
+    state: &mut State,
+    uri: &Url,
+    text: &str,
+    lsp: &Connection
+) -> Result<(), Box<dyn Error + Sync + Send>> {
+    state.set_file_text(uri, text.to_string());
+    let grammar = playground::parse_header(text)
+        .ok()
+        .and_then(|header| playground::grammar_uri(uri, &header));
+    let Some(grammar) = grammar else {
+        return Ok(());
+    };
+    let exists = grammar.to_file_path().is_ok_and(|it| it.is_file());
+    if state.is_indexed(&grammar) || playground::is_sample(&grammar) || !exists {
+        return Ok(());
+    }
+    state.workspace_files.insert(grammar.clone());
+    load_from_disk(state, &grammar);
+    publish_file_diagnostics(state, &grammar, lsp)
+}
+
+/// Checks the open samples again, after a grammar changed.
+fn publish_sample_diagnostics(state: &State, lsp: &Connection) -> Result<(), Box<dyn Error + Sync + Send>> {
+    let samples = state.documents.versions().map(|(uri, _)| uri).filter(|it| playground::is_sample(it));
+    for uri in samples {
+        publish_file_diagnostics(state, uri, lsp)?;
+    }
+    Ok(())
+}
+
+/// Asks the client to get the AST preview of `uri` again, if it has one.
+fn refresh_ast_preview(state: &mut State, uri: &Url, lsp: &Connection) -> Result<(), Box<dyn Error + Sync + Send>> {
+    if !state.features.text_document_content || !state.ast_previews.contains(uri) {
+        return Ok(());
+    }
+    let params = TextDocumentContentParams { uri: ast_preview::preview_uri(uri) };
+    state.send_request::<TextDocumentContentRefresh>(params, lsp)
+}
+
+/// Remembers the grammars the client previews the AST of.
+fn track_ast_preview(req: &RequestData, state: &mut State) {
+    let grammar = match req.method.as_str() {
+        ExecuteCommand::METHOD => serde_json::from_value::<ExecuteCommandParams>(req.params.clone())
+            .ok()
+            .filter(|it| it.command == ast_preview::COMMAND)
+            .and_then(|it| serde_json::from_value(it.arguments.first()?.clone()).ok()),
+        TextDocumentContentRequest::METHOD => serde_json::from_value::<TextDocumentContentParams>(req.params.clone())
+            .ok()
+            .and_then(|it| ast_preview::grammar_uri(&it.uri)),
+        _ => None,
+    };
+    if let Some(grammar) = grammar {
+        state.ast_previews.insert(grammar);
+    }
+}
+
+/// Feeds the saved text of a workspace grammar to the database. Files that
+/// cannot be read are dropped from the index.
+fn load_from_disk(state: &mut State, uri: &Url) {
+    let text = match uri.to_file_path() {
+        Ok(path) => fs::read_to_string(path),
+        Err(()) => return,
+    };
+    match text {
+        Ok(text) => {
+            state.set_file_text(uri, text);
+        }
+        Err(err) => {
+            log::warn!("cannot read {uri}: {err}");
+            state.workspace_files.remove(uri);
+        }
+    }
+}
+
+/// Reports what is wrong with `uri` as it is open, or as it is saved if it
+/// is only in the index. Files out of the index have nothing wrong. Clients
+/// that pull diagnostics ask for them instead.
+fn publish_file_diagnostics(
+    state: &State,
+    uri: &Url,
+    lsp: &Connection
+) -> Result<(), Box<dyn Error + Sync + Send>> {
+    if state.features.pull_diagnostics {
+        return Ok(());
+    }
+    let diagnostics = match state.file(uri) {
+        Some(file) if state.is_indexed(uri) => {
+            diagnostics::file_diagnostics(&state.db, &state.config, uri, file, &state.indexed_files())
+        }
+        _ => Vec::new(),
+    };
+    // Always send the whole set, even when empty: it replaces whatever the
+    // client is showing for this document.
+    log::debug!("{} diagnostics for {uri}", diagnostics.len());
+    let version = state.documents.get(uri).map(|it| it.version);
+    publish_diagnostics(lsp, uri.clone(), diagnostics, version)
+}
+
+/// Tells clients that pull diagnostics that they changed for reasons they
+/// cannot see, like the settings or files on disk.
+fn refresh_diagnostics(state: &mut State, lsp: &Connection) -> Result<(), Box<dyn Error + Sync + Send>> {
+    if state.features.pull_diagnostics && state.features.diagnostic_refresh {
+        state.send_request::<WorkspaceDiagnosticRefresh>((), lsp)?;
+    }
+    Ok(())
+}
+
+/// Loads every grammar under the workspace roots, and reports the ones that
+/// have problems. Clients that can show progress see how far it got.
+fn index_workspace(state: &mut State, lsp: &Connection) -> Result<(), Box<dyn Error + Sync + Send>> {
+    let paths: Vec<_> = state.workspace_roots.iter().flat_map(|it| workspace::grammar_files(it)).collect();
+    let token = ProgressToken::String("ungrammar_lsp/indexing".to_string());
+    let progress = state.features.work_done_progress;
+    if progress {
+        let params = WorkDoneProgressCreateParams { token: token.clone() };
+        state.send_request::<WorkDoneProgressCreate>(params, lsp)?;
+        send_progress(lsp, &token, WorkDoneProgress::Begin(WorkDoneProgressBegin {
+            title: "Indexing grammars".to_string(),
+            percentage: Some(0),
+            ..Default::default()
+        }))?;
+    }
+    for (idx, path) in paths.iter().enumerate() {
+        let Ok(uri) = Url::from_file_path(path) else {
+            continue;
+        };
+        if progress {
+            send_progress(lsp, &token, WorkDoneProgress::Report(WorkDoneProgressReport {
+                message: Some(format!("{}/{}", idx + 1, paths.len())),
+                percentage: Some((idx * 100 / paths.len()) as u32),
+                ..Default::default()
+            }))?;
+        }
+        state.workspace_files.insert(uri.clone());
+        load_from_disk(state, &uri);
+        let Some(file) = state.file(&uri).filter(|_| state.is_indexed(&uri)) else {
+            continue;
+        };
+        if state.features.pull_diagnostics {
+            continue;
+        }
+        let diagnostics = diagnostics::file_diagnostics(&state.db, &state.config, &uri, file, &state.indexed_files());
+        if !diagnostics.is_empty() {
+            publish_diagnostics(lsp, uri, diagnostics, None)?;
+        }
+    }
+    log::info!("indexed {} grammars", state.workspace_files.len());
+    if progress {
+        let message = Some(format!("{} grammars", state.workspace_files.len()));
+        send_progress(lsp, &token, WorkDoneProgress::End(WorkDoneProgressEnd { message }))?;
+    }
+    Ok(())
+}
+
+fn send_progress(
+    lsp: &Connection,
+    token: &ProgressToken,
+    value: WorkDoneProgress,
+) -> Result<(), Box<dyn Error + Sync + Send>> {
+    let params = ProgressParams { token: token.clone(), value: ProgressParamsValue::WorkDone(value) };
+    let notif = NotificationData::new(Progress::METHOD.to_string(), params);
+    lsp.sender.send(notif.into())?;
+    Ok(())
+}
+
+fn publish_diagnostics(
+    lsp: &Connection,
+    uri: Url,
+    diagnostics: Vec<Diagnostic>,
+    version: Option<i32>,
+) -> Result<(), Box<dyn Error + Sync + Send>> {
+    let diag = PublishDiagnosticsParams {
+        uri,
+        diagnostics,
+        version,
+    };
+    lsp.sender.send(Message::Notification(NotificationData {
+        method: PublishDiagnostics::METHOD.into(),
+        params: serde_json::to_value(diag)?,
+    }))?;
+    Ok(())
+}
 
 fn handle_notification(
     notif: NotificationData,
+    state: &mut State,
     lsp: &Connection
 ) -> Result<(), Box<dyn Error + Sync + Send>> {
     let method: &str = &notif.method;
     match method {
+        DidOpenTextDocument::METHOD => {
+            let params: DidOpenTextDocumentParams = notif.extract(
+                DidOpenTextDocument::METHOD
+            )?;
+
+            let TextDocumentItem{
+                uri, version, text, ..
+            } = params.text_document;
+            state.documents.open(uri.clone(), version, text)?;
+            update_document(state, &uri, lsp)?;
+        },
         DidChangeTextDocument::METHOD => {
             let params: DidChangeTextDocumentParams = notif.extract(
                 DidChangeTextDocument::METHOD
-            ).unwrap();
+            )?;
 
             let VersionedTextDocumentIdentifier{
-                version: _, uri
+                version, uri
             } = params.text_document;
-
-            if uri.scheme() != "file" {
-                let scheme = uri.scheme();
-                log::warn!("Got {uri} not supported uri scheme {scheme}");
-                let log_msg = LogMessageParams{
-                    typ: MessageType::WARNING,
-                    message: format!("Only support file:// schema, got {uri}"),
-                };
-                lsp.sender.send(Message::Notification(NotificationData {
-                    method: LogMessage::METHOD.into(),
-                    params: serde_json::to_value(log_msg)?,
-                }))?;
+            state.documents.change(&uri, version, params.content_changes)?;
+            update_document(state, &uri, lsp)?;
+        },
+        DidCloseTextDocument::METHOD => {
+            let params: DidCloseTextDocumentParams = notif.extract(
+                DidCloseTextDocument::METHOD
+            )?;
+            let uri = params.text_document.uri;
+            state.documents.close(&uri)?;
+            // Workspace grammars stay in the index as saved.
+            if state.workspace_files.contains(&uri) {
+                load_from_disk(state, &uri);
             }
-
-            let mut file = std::fs::File::open(uri.path())?;
-            let mut ungrammar_str = String::new();
-            file.read_to_string(&mut ungrammar_str)?;
-
-            let parse_err = ungrammar_str.parse::<Grammar>();
-            match parse_err {
-                Ok(grammar) => {
-                    let log_str = format!("Successfully parsed grammar {grammar:?}");
-                    log::debug!("{log_str}");
-                    let log_msg = LogMessageParams {
-                        typ: MessageType::LOG,
-                        message: log_str,
-                    };
-                    lsp.sender.send(Message::Notification(NotificationData {
-                        method: LogMessage::METHOD.into(),
-                        params: serde_json::to_value(log_msg)?,
-                    }))?;
-                },
-                Err(err) => {
-                    let diag = PublishDiagnosticsParams {
-                        uri,
-                        diagnostics: vec![
-                            err.into_lsp_diagnostic(
-                                Some(DiagnosticSeverity::ERROR),
-                                Some("ungrammar_lsp".into()),
-                            )
-                        ],
-                        version: None,
-                    };
-
-                    lsp.sender.send(Message::Notification(NotificationData {
-                        method: PublishDiagnostics::METHOD.into(),
-                        params: serde_json::to_value(diag)?,
-                    }))?;
-                },
+            publish_file_diagnostics(state, &uri, lsp)?;
+            if !playground::is_sample(&uri) {
+                publish_sample_diagnostics(state, lsp)?;
+                refresh_ast_preview(state, &uri, lsp)?;
+            }
+        },
+        DidChangeWatchedFiles::METHOD => {
+            let params: DidChangeWatchedFilesParams = notif.extract(
+                DidChangeWatchedFiles::METHOD
+            )?;
+            for change in params.changes {
+                let uri = change.uri;
+                let is_grammar = uri.to_file_path().is_ok_and(|path| {
+                    workspace::is_grammar_file(&path, &state.workspace_roots)
+                });
+                if !is_grammar {
+                    continue;
+                }
+                if change.typ == FileChangeType::DELETED {
+                    state.workspace_files.remove(&uri);
+                } else {
+                    state.workspace_files.insert(uri.clone());
+                }
+                // Open documents are checked as edited, not as saved.
+                if state.documents.get(&uri).is_none() {
+                    if state.workspace_files.contains(&uri) {
+                        load_from_disk(state, &uri);
+                    }
+                    publish_file_diagnostics(state, &uri, lsp)?;
+                    refresh_ast_preview(state, &uri, lsp)?;
+                }
+            }
+            publish_sample_diagnostics(state, lsp)?;
+            refresh_diagnostics(state, lsp)?;
+        },
+        DidChangeConfiguration::METHOD => {
+            let params: DidChangeConfigurationParams = notif.extract(
+                DidChangeConfiguration::METHOD
+            )?;
+            // Clients that can be asked for settings may leave them out of
+            // the notification.
+            match state.features.configuration {
+                true => state.request_config(lsp)?,
+                false => {
+                    let settings = params.settings.get(config::SECTION);
+                    state.update_config(settings.unwrap_or(&serde_json::Value::Null), lsp)?;
+                }
+            }
+        },
+        Cancel::METHOD => {
+            let params: CancelParams = notif.extract(Cancel::METHOD)?;
+            let id: RequestId = match params.id {
+                NumberOrString::Number(id) => id.into(),
+                NumberOrString::String(id) => id.into(),
+            };
+            // Requests that were already answered are no longer pending.
+            if state.pending.remove(&id).is_some() {
+                let response = Response::new_err(
+                    id,
+                    ErrorCode::RequestCanceled as i32,
+                    "canceled by the client".to_string(),
+                );
+                lsp.sender.send(response.into())?;
             }
         },
         ignored => {
@@ -86,111 +537,281 @@ fn handle_notification(
     Ok(())
 }
 
-fn main() -> Result<(), Box<dyn Error + Sync + Send>> {
+fn handle_request(
+    req: RequestData,
+    state: &mut State,
+    pool: &TaskPool<Response>,
+    lsp: &Connection
+) -> Result<(), Box<dyn Error + Sync + Send>> {
+    track_ast_preview(&req, state);
+    let mut dispatcher = RequestDispatcher::new(req, state, pool);
+    #[cfg(test)]
+    dispatcher.on::<tests::Sleep>(tests::handle_sleep);
+    let response = dispatcher
+        .on::<GotoDefinition>(handlers::handle_goto_definition)
+        .on::<References>(handlers::handle_references)
+        .on::<HoverRequest>(handlers::handle_hover)
+        .on::<Completion>(handlers::handle_completion)
+        .on::<ResolveCompletionItem>(handlers::handle_completion_resolve)
+        .on::<PrepareRenameRequest>(handlers::handle_prepare_rename)
+        .on::<Rename>(handlers::handle_rename)
+        .on::<DocumentSymbolRequest>(handlers::handle_document_symbol)
+        .on::<SemanticTokensFullRequest>(handlers::handle_semantic_tokens_full)
+        .on::<SemanticTokensRangeRequest>(handlers::handle_semantic_tokens_range)
+        .on::<CodeActionRequest>(handlers::handle_code_action)
+        .on::<InlayHintRequest>(handlers::handle_inlay_hint)
+        .on::<DocumentHighlightRequest>(handlers::handle_document_highlight)
+        .on::<FoldingRangeRequest>(handlers::handle_folding_range)
+        .on::<SelectionRangeRequest>(handlers::handle_selection_range)
+        .on::<CodeLensRequest>(handlers::handle_code_lens)
+        .on::<CallHierarchyPrepare>(handlers::handle_call_hierarchy_prepare)
+        .on::<CallHierarchyIncomingCalls>(handlers::handle_call_hierarchy_incoming)
+        .on::<CallHierarchyOutgoingCalls>(handlers::handle_call_hierarchy_outgoing)
+        .on::<Formatting>(handlers::handle_formatting)
+        .on::<RangeFormatting>(handlers::handle_range_formatting)
+        .on::<WorkspaceSymbolRequest>(handlers::handle_workspace_symbol)
+        .on::<DocumentDiagnosticRequest>(handlers::handle_document_diagnostic)
+        .on::<WorkspaceDiagnosticRequest>(handlers::handle_workspace_diagnostic)
+        .on::<SampleTree>(handlers::handle_sample_tree)
+        .on::<ExecuteCommand>(handlers::handle_execute_command)
+        .on::<TextDocumentContentRequest>(handlers::handle_text_document_content)
+        .finish();
+    if let Some(response) = response {
+        lsp.sender.send(response.into())?;
+    }
+    Ok(())
+}
+
+/// Handles the client's answer to a request the server sent.
+fn handle_response(
+    response: Response,
+    state: &mut State,
+    lsp: &Connection
+) -> Result<(), Box<dyn Error + Sync + Send>> {
+    let Some(method) = state.sent_requests.remove(&response.id) else {
+        log::warn!("response to unknown request {:?}", response.id);
+        return Ok(());
+    };
+    if let Some(err) = response.error {
+        log::warn!("{method} failed: {err:?}");
+        return Ok(());
+    }
+    if method == WorkspaceConfiguration::METHOD {
+        let result: Vec<serde_json::Value> = serde_json::from_value(response.result.unwrap_or_default())?;
+        if let Some(settings) = result.first() {
+            state.update_config(settings, lsp)?;
+        }
+    }
+    Ok(())
+}
+
+/// Sends the response of a request that ran on the task pool, unless the
+/// client canceled it meanwhile.
+fn complete_request(
+    response: Response,
+    state: &mut State,
+    lsp: &Connection
+) -> Result<(), Box<dyn Error + Sync + Send>> {
+    let Some(revision) = state.pending.remove(&response.id) else {
+        return Ok(());
+    };
+    let response = match revision == state.revision {
+        true => response,
+        false => dispatch::content_modified(response.id),
+    };
+    lsp.sender.send(response.into())?;
+    Ok(())
+}
+
+const USAGE: &str = "\
+usage: ungrammar_lsp [--listen tcp:<host>:<port> | --listen unix:<path>]
+
+Serves the language server over stdio, or to every client that connects to
+the socket given to `--listen` until it gets SIGINT or SIGTERM.";
+
+fn main() -> Result<ExitCode, Box<dyn Error + Sync + Send>> {
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("check") => return Ok(check::run(args)),
+        Some("--listen") => return Ok(listen(args.next())),
+        Some(arg) => {
+            eprintln!("error: unknown argument `{arg}`\n\n{USAGE}\n\n{}", check::USAGE);
+            return Ok(ExitCode::from(4));
+        }
+        None => (),
+    }
     let (connection, io_threads) = Connection::stdio();
+    let code = run_server(&connection)?;
+    // The writer thread stops once nothing can send to it anymore.
+    drop(connection);
+    io_threads.join()?;
+    Ok(code)
+}
+
+/// Serves every client that connects to `address`.
+fn listen(address: Option<String>) -> ExitCode {
+    let address = match address.map(|it| it.parse::<transport::Address>()) {
+        Some(Ok(address)) => address,
+        Some(Err(err)) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            return ExitCode::from(4);
+        }
+        None => {
+            eprintln!("error: expected an address after `--listen`\n\n{USAGE}");
+            return ExitCode::from(4);
+        }
+    };
+    // SIGINT and SIGTERM, or Ctrl-C on Windows, stop the server.
+    let (stop, stopped) = crossbeam_channel::bounded(1);
+    if let Err(err) = ctrlc::set_handler(move || { let _ = stop.try_send(()); }) {
+        eprintln!("error: cannot handle signals: {err}");
+        return ExitCode::FAILURE;
+    }
+    let server = match transport::listen(&address, serve_session) {
+        Ok(it) => it,
+        Err(err) => {
+            eprintln!("error: cannot listen on {address}: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    eprintln!("listening on {}", server.address());
+    let _ = stopped.recv();
+    server.stop();
+    ExitCode::SUCCESS
+}
+
+/// Serves one client that connected to `--listen`.
+fn serve_session(connection: &Connection) {
+    match run_server(connection) {
+        Ok(code) if code == ExitCode::SUCCESS => log::info!("session ended"),
+        Ok(_) => log::warn!("session ended without shutdown"),
+        Err(err) => log::error!("session failed: {err}"),
+    }
+}
+
+/// The directories the client opened, as local paths.
+fn workspace_roots(params: &InitializeParams) -> Vec<PathBuf> {
+    #[allow(deprecated)]
+    let uris = match &params.workspace_folders {
+        Some(folders) => folders.iter().map(|it| it.uri.clone()).collect(),
+        None => params.root_uri.iter().cloned().collect::<Vec<_>>(),
+    };
+    uris.iter().filter_map(|it| it.to_file_path().ok()).collect()
+}
 
-    // Run the server
+/// Serves one client until it sends `exit` or disconnects. The exit code
+/// tells whether it asked for a `shutdown` first.
+fn run_server(connection: &Connection) -> Result<ExitCode, Box<dyn Error + Sync + Send>> {
     let (id, params) = connection.initialize_start()?;
 
+    let raw_capabilities = params["capabilities"].clone();
     let init_params: InitializeParams = serde_json::from_value(params).unwrap();
+    let workspace_roots = workspace_roots(&init_params);
     let client_capabilities: ClientCapabilities = init_params.capabilities;
     log::info! {"Client cap: {client_capabilities:?}"};
-    let server_capabilities = ServerCapabilities {
-        text_document_sync: Some(
-            TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)
-        ),
-        ..Default::default()
-    };
-
-
-
+    let features = capabilities::client_features(&client_capabilities, &raw_capabilities);
+    let server_capabilities = capabilities::server_capabilities(&features);
     log::info! {"Server cap: {server_capabilities:?}"};
-    // TODO: This is fine because negotiated capabilities will always be
-    // subset of client-submitted capabilities at early dev
-    let negotiated_capabilities = server_capabilities;
-    let initialize_data = serde_json::json!({
-        "capabilities": negotiated_capabilities,
+    let mut initialize_data = serde_json::json!({
+        "capabilities": server_capabilities,
         "serverInfo": {
             "name": "ungrammar_lsp",
             "version": "dev"
         }
     });
+    if let Some(provider) = capabilities::diagnostic_provider(&features) {
+        initialize_data["capabilities"]["diagnosticProvider"] = serde_json::to_value(provider)?;
+    }
+    if let Some(options) = capabilities::text_document_content(&features) {
+        initialize_data["capabilities"]["workspace"]["textDocumentContent"] = serde_json::to_value(options)?;
+    }
 
     connection.initialize_finish(id, initialize_data)?;
-    // Main loop where the LSP server listens for client messages.
-    for message in &connection.receiver {
+    let mut state = State {
+        documents: DocumentManager::new(features.position_encoding),
+        workspace_roots,
+        ..State::default()
+    };
+    state.db.set_position_encoding(features.position_encoding);
+    state.features = Arc::new(features);
+    if !state.features.registrations.is_empty() {
+        let params = RegistrationParams { registrations: state.features.registrations.clone() };
+        state.send_request::<RegisterCapability>(params, connection)?;
+    }
+    if let Some(options) = &init_params.initialization_options {
+        state.update_config(options, connection)?;
+        state.initial_config = state.config.clone();
+    }
+    if state.features.configuration {
+        state.request_config(connection)?;
+    }
+    index_workspace(&mut state, connection)?;
+    let threads = thread::available_parallelism().map_or(1, usize::from);
+    let pool = TaskPool::new(threads);
+    let mut shutdown = false;
+    // Main loop where the LSP server listens for client messages, and for
+    // the results of requests running on the task pool.
+    loop {
+        let message = crossbeam_channel::select! {
+            recv(connection.receiver) -> message => match message {
+                Ok(message) => message,
+                Err(_) => break,
+            },
+            recv(pool.receiver) -> response => {
+                let response = response.expect("the task pool outlives the loop");
+                if let Err(err) = complete_request(response, &mut state, connection) {
+                    log::error!("Error sending response: {err}")
+                }
+                continue;
+            },
+        };
         log::debug!{"received {message:?}"}
         match message {
-            Message::Request(req) if req.method == "shutdown" => {
+            Message::Request(req) if shutdown => {
+                let response = Response::new_err(
+                    req.id,
+                    ErrorCode::InvalidRequest as i32,
+                    "the server is shutting down".to_string(),
+                );
+                connection.sender.send(response.into())?;
+            }
+            Message::Request(req) if req.method == Shutdown::METHOD => {
                 log::info!{"shutdown initiated"};
-                // Handle the LSP shutdown request.
-                connection.sender.send(req.into()).unwrap();
+                shutdown = true;
+                connection.sender.send(Response::new_ok(req.id, ()).into())?;
             }
             Message::Request(req) => {
-                log::warn!(
-                    "client sends req {req:?}, not sure how to handle. \
-                    There might be a server-client capabilities misunderstanding."
-                );
+                let req_dbg = format!("{req:?}");
+                if let Err(err) = handle_request(req, &mut state, &pool, connection) {
+                    log::error!("Error handling req {req_dbg}: {err}")
+                }
+            }
+            Message::Notification(notif) if notif.method == Exit::METHOD => {
+                log::info!{"exiting"};
+                break;
+            }
+            Message::Notification(notif) if shutdown => {
+                log::info!{"ignoring {notif:?} after shutdown"};
             }
             Message::Notification(notification) => {
                 let notif = notification.clone();
                 let notif_dbg = format!("{notif:?}");
-                if let Err(err) = handle_notification(notification, &connection) {
+                if let Err(err) = handle_notification(notification, &mut state, connection) {
                     log::error!("Error handling notif {notif_dbg}: {err}")
                 }
             }
-            ignore => {
-                log::info!{"ignoring {ignore:?}"};
+            Message::Response(response) => {
+                if let Err(err) = handle_response(response, &mut state, connection) {
+                    log::error!("Error handling response: {err}")
+                }
             }
         }
     }
-    io_threads.join().map_err(Into::into)
+    Ok(match shutdown {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    })
 }
-pub(crate) trait DiagnosticExt {
-    fn range(&self) -> Range;
-    fn msg(&self) -> String;
-    fn into_lsp_diagnostic(
-        self, 
-        severity: Option<DiagnosticSeverity>,
-        source: Option<String>,
-    ) -> Diagnostic 
-    where Self: Sized {
-        Diagnostic { 
-            range: self.range(),
-            message: self.msg(),
-            severity,
-            source,
 
-            code: Default::default(),
-            code_description: Default::default(),
-            related_information: Default::default(),
-            tags: Default::default(),
-            data: Default::default(),
-        }
-    }
-}
-
-
-
-
-impl DiagnosticExt for ungrammar_fork::Error {
-    fn range(&self) -> Range {
-        match self.location {
-            Some(loc) => {
-                let pos = Position {
-                        line: (loc.line - 1).try_into().unwrap(),
-                        character: (loc.column - 1).try_into().unwrap(),
-                    };
-                Range {
-                    start: pos, 
-                    end: pos,
-                }
-            },
-            None => Range::default()
-        }
-    }
-
-    fn msg(&self) -> String {
-        self.message.clone()
-    }
-}
+#[cfg(test)]
+mod tests;
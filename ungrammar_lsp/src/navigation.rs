@@ -0,0 +1,136 @@
+//! What the name under the cursor refers to, and where else it appears.
+use rowan::{TextRange, TextSize};
+
+use crate::{
+    db::{FileId, SourceDatabase},
+    syntax::{ast, SyntaxKind::*, SyntaxNode, SyntaxToken},
+};
+
+/// Something that can be referred to by name in a grammar.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Symbol {
+    Node(String),
+    /// A token, by value: `'+'` is `Token("+")`.
+    Token(String),
+}
+
+/// Returns the symbol at `offset` and the range of its name there.
+pub(crate) fn symbol_at(
+    db: &dyn SourceDatabase,
+    file: FileId,
+    offset: TextSize,
+) -> Option<(Symbol, TextRange)> {
+    let token = name_token_at(&db.parse(file).syntax_node(), offset)?;
+    let symbol = classify(&token)?;
+    Some((symbol, token.text_range()))
+}
+
+/// The identifier or token literal touching `offset`, if any.
+pub(crate) fn name_token_at(root: &SyntaxNode, offset: TextSize) -> Option<SyntaxToken> {
+    root.token_at_offset(offset)
+        .filter(|it| matches!(it.kind(), IDENT | TOKEN_LIT))
+        .last()
+}
+
+/// What a name token means. Labels are not symbols: they are scoped to their
+/// rule.
+pub(crate) fn classify(token: &SyntaxToken) -> Option<Symbol> {
+    let parent = token.parent()?;
+    match (token.kind(), parent.kind()) {
+        (IDENT, NODE_REF | RULE) => Some(Symbol::Node(token.text().to_string())),
+        (TOKEN_LIT, TOKEN_REF) => ast::TokenRef::cast(parent)?.value().map(Symbol::Token),
+        _ => None,
+    }
+}
+
+/// The names of the rules defining `symbol`. Tokens have none.
+pub(crate) fn definitions(
+    db: &dyn SourceDatabase,
+    file: FileId,
+    symbol: &Symbol,
+) -> Vec<TextRange> {
+    let Symbol::Node(name) = symbol else {
+        return Vec::new();
+    };
+    let Some(source_file) = ast::SourceFile::cast(db.parse(file).syntax_node()) else {
+        return Vec::new();
+    };
+    source_file
+        .rules()
+        .filter_map(|it| it.name())
+        .filter(|it| it.text() == name)
+        .map(|it| it.text_range())
+        .collect()
+}
+
+/// Every mention of `symbol`, in order. For nodes, the names of their rules
+/// are included only if `include_declaration` is set.
+pub(crate) fn references(
+    db: &dyn SourceDatabase,
+    file: FileId,
+    symbol: &Symbol,
+    include_declaration: bool,
+) -> Vec<TextRange> {
+    let root = db.parse(file).syntax_node();
+    root.descendants_with_tokens()
+        .filter_map(|it| it.into_token())
+        .filter(|it| matches!(it.kind(), IDENT | TOKEN_LIT))
+        .filter(|it| include_declaration || it.parent().map(|it| it.kind()) != Some(RULE))
+        .filter(|it| classify(it).as_ref() == Some(symbol))
+        .map(|it| it.text_range())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::db::RootDatabase;
+
+    /// Sets up a file from `text`, where `$0` marks the cursor.
+    fn with_cursor(text: &str) -> (RootDatabase, FileId, TextSize, String) {
+        let offset = text.find("$0").unwrap();
+        let text = text.replace("$0", "");
+        let mut db = RootDatabase::default();
+        let file = FileId(0);
+        db.set_file_text(file, Arc::new(text.clone()));
+        (db, file, TextSize::from(offset as u32), text)
+    }
+
+    fn texts<'a>(text: &'a str, ranges: &[TextRange]) -> Vec<(usize, &'a str)> {
+        ranges
+            .iter()
+            .map(|&it| (usize::from(it.start()), &text[it]))
+            .collect()
+    }
+
+    #[test]
+    fn finds_nodes_from_references_and_definitions() {
+        let (db, file, offset, text) = with_cursor("A = B$0 'b' B\nB = label:A 'b'\n");
+        let (symbol, range) = symbol_at(&db, file, offset).unwrap();
+        assert_eq!((&symbol, &text[range]), (&Symbol::Node("B".into()), "B"));
+        assert_eq!(texts(&text, &definitions(&db, file, &symbol)), [(12, "B")]);
+        assert_eq!(
+            texts(&text, &references(&db, file, &symbol, false)),
+            [(4, "B"), (10, "B")]
+        );
+        assert_eq!(references(&db, file, &symbol, true).len(), 3);
+
+        // Labels are not nodes.
+        let (db, file, offset, _) = with_cursor("A = lab$0el:A");
+        assert_eq!(symbol_at(&db, file, offset), None);
+    }
+
+    #[test]
+    fn finds_tokens_by_value() {
+        let (db, file, offset, text) = with_cursor("A = '\\''$0 B\nB = '\\'' '+'\n");
+        let (symbol, _) = symbol_at(&db, file, offset).unwrap();
+        assert_eq!(symbol, Symbol::Token("'".into()));
+        assert_eq!(definitions(&db, file, &symbol), []);
+        assert_eq!(
+            texts(&text, &references(&db, file, &symbol, true)),
+            [(4, "'\\''"), (15, "'\\''")]
+        );
+    }
+}
@@ -0,0 +1,737 @@
+//! The grammar playground: checks sample input against a grammar.
+//!
+//! A sample is a `.sample` file that starts with a header naming the grammar,
+//! relative to the sample, and the node the whole sample should be:
+//!
+//! ```text
+//! // grammar: zork_keg.ungram
+//! // root: HrefToken
+//! [example](./notes?ft=md#const:c5143b)
+//! ```
+//!
+//! The rest of the file is parsed by interpreting the rules of the grammar,
+//! trying every way they can match. Whitespace between tokens is skipped. A
+//! literal token like `'+'` matches its text; one that ends in a letter,
+//! digit or `_` does not match at the start of a longer word. A lexer token
+//! like `'lex:ident'` matches what its name after the prefix says: `ident`,
+//! `int_number`, `float_number` and `string` are what they are in most
+//! languages, and any other name matches a run of letters, digits, `_` and
+//! `-`.
+//!
+//! Left-recursive nodes are grown from their shortest match. When the
+//! sample can be read in more than one way, the reading that respects the
+//! `%precedence` declarations wins.
+use std::{collections::HashMap, fmt, rc::Rc, sync::Arc};
+
+use lsp_types::Url;
+use rowan::{TextRange, TextSize};
+use ungrammar_fork::{Assoc, Grammar, Node, Rule, Token};
+
+use crate::db::{check_canceled, FileId, GrammarDatabase};
+
+/// Whether `uri` is a sample rather than a grammar.
+pub(crate) fn is_sample(uri: &Url) -> bool {
+    uri.path().ends_with(".sample")
+}
+
+/// The header of a sample.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SampleHeader {
+    /// The path of the grammar, relative to the sample.
+    pub(crate) grammar: String,
+    pub(crate) grammar_range: TextRange,
+    pub(crate) root: String,
+    pub(crate) root_range: TextRange,
+    /// Where the input starts.
+    pub(crate) end: TextSize,
+}
+
+/// Reads the `// key: value` lines at the start of a sample.
+pub(crate) fn parse_header(text: &str) -> Result<SampleHeader, SampleError> {
+    let mut grammar = None;
+    let mut root = None;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let Some(comment) = line.strip_prefix("//") else {
+            break;
+        };
+        if let Some((key, value)) = comment.split_once(':') {
+            let value_start = offset + line.len() - value.len();
+            let trimmed = value.trim();
+            let start = value_start + (value.len() - value.trim_start().len());
+            let range = TextRange::at(TextSize::from(start as u32), TextSize::of(trimmed));
+            match key.trim() {
+                "grammar" => grammar = Some((trimmed.to_string(), range)),
+                "root" => root = Some((trimmed.to_string(), range)),
+                _ => (),
+            }
+        }
+        offset += line.len();
+    }
+    let end = TextSize::from(offset as u32);
+    match (grammar, root) {
+        (Some((grammar, grammar_range)), Some((root, root_range))) => Ok(SampleHeader {
+            grammar,
+            grammar_range,
+            root,
+            root_range,
+            end,
+        }),
+        _ => Err(SampleError {
+            range: TextRange::empty(0.into()),
+            message: "a sample starts with `// grammar: <path>` and `// root: <node>` lines"
+                .to_string(),
+        }),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SampleError {
+    pub(crate) range: TextRange,
+    pub(crate) message: String,
+}
+
+/// A node of the grammar, as found in a sample.
+#[derive(Debug)]
+pub(crate) struct SampleNode {
+    pub(crate) node: Node,
+    pub(crate) range: TextRange,
+    pub(crate) children: Vec<SampleChild>,
+    /// How many operators in this subtree bind against their precedence.
+    conflicts: usize,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct SampleChild {
+    pub(crate) label: Option<Rc<str>>,
+    pub(crate) element: SampleElement,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum SampleElement {
+    Node(Rc<SampleNode>),
+    Token(Token, TextRange),
+}
+
+/// What came out of checking a sample.
+#[derive(Debug)]
+pub(crate) struct SampleParse {
+    /// The tree of the whole input or, if it does not match, of the longest
+    /// part of it that does.
+    pub(crate) tree: Option<(Arc<Grammar>, Rc<SampleNode>)>,
+    pub(crate) errors: Vec<SampleError>,
+}
+
+impl fmt::Display for SampleParse {
+    /// The tree, one element per line, followed by the errors.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((grammar, tree)) = &self.tree {
+            write_node(f, grammar, None, tree, 0)?;
+        }
+        for err in &self.errors {
+            writeln!(f, "error@{:?}: {}", err.range, err.message)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_node(
+    f: &mut fmt::Formatter<'_>,
+    grammar: &Grammar,
+    label: Option<&str>,
+    node: &SampleNode,
+    depth: usize,
+) -> fmt::Result {
+    let indent = "  ".repeat(depth);
+    let label = label.map(|it| format!("{it}: ")).unwrap_or_default();
+    writeln!(
+        f,
+        "{indent}{label}{}@{:?}",
+        grammar[node.node].name, node.range
+    )?;
+    for child in &node.children {
+        let label = child.label.as_deref();
+        match &child.element {
+            SampleElement::Node(node) => write_node(f, grammar, label, node, depth + 1)?,
+            SampleElement::Token(token, range) => {
+                let label = label.map(|it| format!("{it}: ")).unwrap_or_default();
+                writeln!(f, "{indent}  {label}'{}'@{range:?}", grammar[*token].name)?
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks the sample `file` at `uri` against the grammar its header names,
+/// which is looked up in `files`.
+pub(crate) fn check_sample(
+    db: &dyn GrammarDatabase,
+    uri: &Url,
+    file: FileId,
+    files: &HashMap<Url, FileId>,
+) -> SampleParse {
+    let failed = |range, message| SampleParse {
+        tree: None,
+        errors: vec![SampleError { range, message }],
+    };
+    let text = db.file_text(file);
+    let header = match parse_header(&text) {
+        Ok(it) => it,
+        Err(err) => {
+            return SampleParse {
+                tree: None,
+                errors: vec![err],
+            }
+        }
+    };
+    let grammar_file = grammar_uri(uri, &header).and_then(|it| files.get(&it).copied());
+    let Some(grammar_file) = grammar_file else {
+        let message = format!("cannot find the grammar `{}`", header.grammar);
+        return failed(header.grammar_range, message);
+    };
+    let grammar = db.grammar(grammar_file).best_effort.clone();
+    let Some(root) = grammar.iter().find(|&it| grammar[it].name == header.root) else {
+        let message = format!("`{}` is not a node of `{}`", header.root, header.grammar);
+        return failed(header.root_range, message);
+    };
+    let mut interpreter = Interpreter {
+        db,
+        grammar: &grammar,
+        text: &text,
+        lex_prefix: &db.lex_prefix(),
+        memo: HashMap::new(),
+        active: Vec::new(),
+        lowest_active: usize::MAX,
+        furthest: header.end,
+        expected: Vec::new(),
+    };
+    let matches = interpreter.node(root, header.end);
+    let eof = TextSize::of(text.as_str());
+    let complete = matches
+        .iter()
+        .find(|(end, _)| interpreter.skip_trivia(*end) == eof);
+    let longest = matches.iter().max_by_key(|(end, _)| *end);
+    let (tree, errors) = match (complete, longest) {
+        (Some((_, tree)), _) => (Some(tree.clone()), Vec::new()),
+        (None, longest) => {
+            let end = longest.map_or(header.end, |(end, _)| *end);
+            let err = match end > interpreter.furthest {
+                true => interpreter.error_at(interpreter.skip_trivia(end), "the end of the sample"),
+                false => interpreter.expected_error(),
+            };
+            (longest.map(|(_, tree)| tree.clone()), vec![err])
+        }
+    };
+    SampleParse {
+        tree: tree.map(|it| (grammar.clone(), it)),
+        errors,
+    }
+}
+
+/// Where the grammar of a sample at `uri` is.
+pub(crate) fn grammar_uri(uri: &Url, header: &SampleHeader) -> Option<Url> {
+    uri.join(&header.grammar).ok()
+}
+
+/// The ways a node can match at a position, by where they end.
+type Matches = Rc<Vec<(TextSize, Rc<SampleNode>)>>;
+
+/// The ways part of a rule can match, by where they end.
+type Partial = Vec<(TextSize, Vec<SampleChild>)>;
+
+struct Interpreter<'a> {
+    db: &'a dyn GrammarDatabase,
+    grammar: &'a Grammar,
+    text: &'a str,
+    lex_prefix: &'a str,
+    memo: HashMap<(Node, TextSize), Matches>,
+    /// The nodes being matched, with what they matched so far.
+    active: Vec<(Node, TextSize, Matches)>,
+    /// The lowest entry of `active` the current match depends on.
+    lowest_active: usize,
+    /// The furthest position a token was expected at, and which tokens.
+    furthest: TextSize,
+    expected: Vec<Token>,
+}
+
+impl Interpreter<'_> {
+    fn node(&mut self, node: Node, pos: TextSize) -> Matches {
+        if let Some(matches) = self.memo.get(&(node, pos)) {
+            return matches.clone();
+        }
+        // Left recursion: go on with what the node matched so far.
+        if let Some(idx) = self
+            .active
+            .iter()
+            .position(|(it, start, _)| *it == node && *start == pos)
+        {
+            self.lowest_active = self.lowest_active.min(idx);
+            return self.active[idx].2.clone();
+        }
+        check_canceled(self.db);
+        let depth = self.active.len();
+        self.active.push((node, pos, Rc::new(Vec::new())));
+        let outer = self.lowest_active;
+        let mut lowest;
+        loop {
+            self.lowest_active = usize::MAX;
+            let found = self.rule(node, &self.grammar[node].rule, pos, None);
+            lowest = self.lowest_active;
+            let mut matches = (*self.active[depth].2).clone();
+            let mut changed = false;
+            for (end, children) in found {
+                let tree = self.make_node(node, pos, end, children);
+                match matches.iter_mut().find(|(it, _)| *it == end) {
+                    Some(old) if old.1.conflicts > tree.conflicts => {
+                        old.1 = tree;
+                        changed = true;
+                    }
+                    Some(_) => (),
+                    None => {
+                        matches.push((end, tree));
+                        changed = true;
+                    }
+                }
+            }
+            self.active[depth].2 = Rc::new(matches);
+            // Only a node that used its own matches can match more now.
+            if !changed || lowest != depth {
+                break;
+            }
+        }
+        let (_, _, matches) = self.active.pop().unwrap();
+        // What depends on nodes further up may change with them.
+        if lowest >= depth {
+            self.memo.insert((node, pos), matches.clone());
+            self.lowest_active = outer;
+        } else {
+            self.lowest_active = outer.min(lowest);
+        }
+        matches
+    }
+
+    fn make_node(
+        &self,
+        node: Node,
+        start: TextSize,
+        end: TextSize,
+        children: Vec<SampleChild>,
+    ) -> Rc<SampleNode> {
+        let start = children
+            .first()
+            .map_or(start, |it| element_range(&it.element).start());
+        let conflicts = conflicts(&children) + self.precedence_conflicts(node, &children);
+        Rc::new(SampleNode {
+            node,
+            range: TextRange::new(start, end.max(start)),
+            children,
+            conflicts,
+        })
+    }
+
+    /// How many operands of `node` are operations of `node` that should
+    /// have taken this one as their operand instead.
+    fn precedence_conflicts(&self, node: Node, children: &[SampleChild]) -> usize {
+        let Some(precedence) = self.grammar.precedence(node) else {
+            return 0;
+        };
+        let operator = |children: &[SampleChild]| {
+            children
+                .iter()
+                .enumerate()
+                .find_map(|(idx, it)| match it.element {
+                    SampleElement::Token(token, _) => Some((idx, precedence.level(token)?)),
+                    SampleElement::Node(_) => None,
+                })
+        };
+        let Some((op_idx, (level, assoc))) = operator(children) else {
+            return 0;
+        };
+        let mut conflicts = 0;
+        for (idx, child) in children.iter().enumerate() {
+            let SampleElement::Node(operand) = &child.element else {
+                continue;
+            };
+            // Look through nodes that only wrap another, like `Expr`.
+            let mut operand = operand;
+            while operand.node != node {
+                match operand.children.as_slice() {
+                    [SampleChild {
+                        element: SampleElement::Node(inner),
+                        ..
+                    }] => operand = inner,
+                    _ => break,
+                }
+            }
+            if operand.node != node {
+                continue;
+            }
+            let Some((_, (inner_level, _))) = operator(&operand.children) else {
+                continue;
+            };
+            let same_side_ok = match idx < op_idx {
+                true => assoc == Assoc::Left,
+                false => assoc == Assoc::Right,
+            };
+            if inner_level < level || (inner_level == level && !same_side_ok) {
+                conflicts += 1;
+            }
+        }
+        conflicts
+    }
+
+    /// Matches `rule`, which is part of the rule of `node`, at `pos`.
+    fn rule(&mut self, node: Node, rule: &Rule, pos: TextSize, label: Option<&Rc<str>>) -> Partial {
+        match rule {
+            Rule::Labeled { label, rule } => {
+                self.rule(node, rule, pos, Some(&Rc::from(label.as_str())))
+            }
+            Rule::Node(inner) => self
+                .node(*inner, pos)
+                .iter()
+                .map(|(end, tree)| {
+                    let child = SampleChild {
+                        label: label.cloned(),
+                        element: SampleElement::Node(tree.clone()),
+                    };
+                    (*end, vec![child])
+                })
+                .collect(),
+            Rule::Token(token) => match self.token(*token, pos) {
+                Some(range) => {
+                    let child = SampleChild {
+                        label: label.cloned(),
+                        element: SampleElement::Token(*token, range),
+                    };
+                    vec![(range.end(), vec![child])]
+                }
+                None => Vec::new(),
+            },
+            Rule::Seq(rules) => {
+                let mut partial = vec![(pos, Vec::new())];
+                for rule in rules {
+                    let mut next = Vec::new();
+                    for (pos, children) in &partial {
+                        for (end, more) in self.rule(node, rule, *pos, label) {
+                            self.add_partial(
+                                node,
+                                &mut next,
+                                end,
+                                [children.clone(), more].concat(),
+                            );
+                        }
+                    }
+                    partial = next;
+                    if partial.is_empty() {
+                        break;
+                    }
+                }
+                partial
+            }
+            Rule::Alt(rules) => {
+                let mut partial = Vec::new();
+                for rule in rules {
+                    for (end, children) in self.rule(node, rule, pos, label) {
+                        self.add_partial(node, &mut partial, end, children);
+                    }
+                }
+                partial
+            }
+            Rule::Opt(rule) => {
+                let mut partial = vec![(pos, Vec::new())];
+                for (end, children) in self.rule(node, rule, pos, label) {
+                    self.add_partial(node, &mut partial, end, children);
+                }
+                partial
+            }
+            Rule::Rep(rule) => {
+                let mut partial = vec![(pos, Vec::new())];
+                let mut frontier = partial.clone();
+                while !frontier.is_empty() {
+                    let mut next = Vec::new();
+                    for (pos, children) in &frontier {
+                        for (end, more) in self.rule(node, rule, *pos, label) {
+                            // Repeating something empty gets nowhere.
+                            if end > *pos && !partial.iter().any(|(it, _)| *it == end) {
+                                self.add_partial(
+                                    node,
+                                    &mut next,
+                                    end,
+                                    [children.clone(), more].concat(),
+                                );
+                            }
+                        }
+                    }
+                    partial.extend(next.iter().cloned());
+                    frontier = next;
+                }
+                partial
+            }
+        }
+    }
+
+    /// Adds a way for part of the rule of `node` to match up to `end`,
+    /// unless there already is one that is as good.
+    fn add_partial(
+        &self,
+        node: Node,
+        partial: &mut Partial,
+        end: TextSize,
+        children: Vec<SampleChild>,
+    ) {
+        let score = |children: &[SampleChild]| {
+            conflicts(children) + self.precedence_conflicts(node, children)
+        };
+        match partial.iter_mut().find(|(it, _)| *it == end) {
+            Some(old) if score(&old.1) > score(&children) => old.1 = children,
+            Some(_) => (),
+            None => partial.push((end, children)),
+        }
+    }
+
+    /// Matches `token` after the whitespace at `pos`.
+    fn token(&mut self, token: Token, pos: TextSize) -> Option<TextRange> {
+        let start = self.skip_trivia(pos);
+        let rest = &self.text[usize::from(start)..];
+        let name = &self.grammar[token].name;
+        let len = match name.strip_prefix(self.lex_prefix) {
+            Some(class) if !self.lex_prefix.is_empty() => lex(class, rest),
+            _ => rest
+                .strip_prefix(name.as_str())
+                .filter(|after| !name.ends_with(is_word_char) || !after.starts_with(is_word_char))
+                .map(|_| name.len()),
+        };
+        match len.filter(|it| *it > 0) {
+            Some(len) => Some(TextRange::at(start, TextSize::from(len as u32))),
+            None => {
+                if start > self.furthest {
+                    self.furthest = start;
+                    self.expected.clear();
+                }
+                if start == self.furthest && !self.expected.contains(&token) {
+                    self.expected.push(token);
+                }
+                None
+            }
+        }
+    }
+
+    fn skip_trivia(&self, pos: TextSize) -> TextSize {
+        let rest = &self.text[usize::from(pos)..];
+        pos + TextSize::of(&rest[..rest.len() - rest.trim_start().len()])
+    }
+
+    fn expected_error(&self) -> SampleError {
+        let mut expected: Vec<_> = self
+            .expected
+            .iter()
+            .map(|&it| format!("'{}'", self.grammar[it].name))
+            .collect();
+        expected.sort();
+        let expected = match expected.split_last() {
+            Some((last, [])) => last.clone(),
+            Some((last, rest)) => format!("{} or {last}", rest.join(", ")),
+            None => "nothing".to_string(),
+        };
+        self.error_at(self.furthest, &expected)
+    }
+
+    fn error_at(&self, pos: TextSize, expected: &str) -> SampleError {
+        let rest = &self.text[usize::from(pos)..];
+        let len = match rest.chars().next() {
+            None => 0,
+            Some(c) if is_word_char(c) => rest.find(|c| !is_word_char(c)).unwrap_or(rest.len()),
+            Some(c) => c.len_utf8(),
+        };
+        let range = TextRange::at(pos, TextSize::from(len as u32));
+        let found = match len {
+            0 => "the end of the sample".to_string(),
+            _ => format!("`{}`", &rest[..len]),
+        };
+        SampleError {
+            range,
+            message: format!("expected {expected}, found {found}"),
+        }
+    }
+}
+
+fn conflicts(children: &[SampleChild]) -> usize {
+    children
+        .iter()
+        .map(|it| match &it.element {
+            SampleElement::Node(node) => node.conflicts,
+            SampleElement::Token(..) => 0,
+        })
+        .sum()
+}
+
+fn element_range(element: &SampleElement) -> TextRange {
+    match element {
+        SampleElement::Node(node) => node.range,
+        SampleElement::Token(_, range) => *range,
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The length of the lexer token of class `class` at the start of `text`.
+fn lex(class: &str, text: &str) -> Option<usize> {
+    let digits = |text: &str| {
+        text.find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(text.len())
+    };
+    let len = match class {
+        "ident" => {
+            if !text.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+                return None;
+            }
+            text.find(|c| !is_word_char(c)).unwrap_or(text.len())
+        }
+        "int_number" => digits(text),
+        "float_number" => {
+            let int = digits(text);
+            let rest = text[int..].strip_prefix('.')?;
+            match digits(rest) {
+                0 => return None,
+                frac => int + 1 + frac,
+            }
+        }
+        "string" => {
+            let rest = text.strip_prefix('"')?;
+            let mut escaped = false;
+            let end = rest.find(|c| {
+                let end = c == '"' && !escaped;
+                escaped = c == '\\' && !escaped;
+                end
+            })?;
+            end + 2
+        }
+        _ => text
+            .find(|c: char| !(is_word_char(c) || c == '-'))
+            .unwrap_or(text.len()),
+    };
+    Some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{RootDatabase, SourceDatabase};
+
+    fn check(grammar: &str, sample: &str) -> String {
+        let mut db = RootDatabase::default();
+        db.set_file_text(FileId(0), Arc::new(grammar.to_string()));
+        db.set_file_text(FileId(1), Arc::new(sample.to_string()));
+        let files = HashMap::from([(Url::parse("file:///g/expr.ungram").unwrap(), FileId(0))]);
+        let uri = Url::parse("file:///g/a.sample").unwrap();
+        check_sample(&db, &uri, FileId(1), &files).to_string()
+    }
+
+    #[test]
+    fn reads_the_header() {
+        let header = parse_header("// grammar: ../zork_keg.ungram\n// root:  Block \nx\n").unwrap();
+        assert_eq!(header.grammar, "../zork_keg.ungram");
+        assert_eq!(header.grammar_range, TextRange::new(12.into(), 30.into()));
+        assert_eq!(header.root, "Block");
+        assert_eq!(header.root_range, TextRange::new(41.into(), 46.into()));
+        assert_eq!(header.end, 48.into());
+        assert!(parse_header("// grammar: a.ungram\nx\n").is_err());
+    }
+
+    const EXPR: &str = "\
+Expr = BinExpr | Literal
+Literal = 'lex:int_number' | 'lex:ident'
+BinExpr = lhs:Expr op:('+' | '*' | '^') rhs:Expr
+%precedence BinExpr
+  %left '+'
+  %left '*'
+  %right '^'
+";
+
+    #[test]
+    fn follows_precedence() {
+        let tree = check(
+            EXPR,
+            "// grammar: expr.ungram\n// root: Expr\n1 * x + 2 ^ 3 ^ 4\n",
+        );
+        assert_eq!(
+            tree,
+            "\
+Expr@38..55
+  BinExpr@38..55
+    lhs: Expr@38..43
+      BinExpr@38..43
+        lhs: Expr@38..39
+          Literal@38..39
+            'lex:int_number'@38..39
+        op: '*'@40..41
+        rhs: Expr@42..43
+          Literal@42..43
+            'lex:ident'@42..43
+    op: '+'@44..45
+    rhs: Expr@46..55
+      BinExpr@46..55
+        lhs: Expr@46..47
+          Literal@46..47
+            'lex:int_number'@46..47
+        op: '^'@48..49
+        rhs: Expr@50..55
+          BinExpr@50..55
+            lhs: Expr@50..51
+              Literal@50..51
+                'lex:int_number'@50..51
+            op: '^'@52..53
+            rhs: Expr@54..55
+              Literal@54..55
+                'lex:int_number'@54..55
+"
+        );
+    }
+
+    #[test]
+    fn reports_where_the_sample_stops_matching() {
+        let tree = check(EXPR, "// grammar: expr.ungram\n// root: Expr\n1 + * 2\n");
+        assert!(
+            tree.ends_with("error@42..43: expected 'lex:ident' or 'lex:int_number', found `*`\n"),
+            "{tree}"
+        );
+        let tree = check(EXPR, "// grammar: expr.ungram\n// root: Expr\n1 2\n");
+        assert!(
+            tree.ends_with("error@40..41: expected '*', '+' or '^', found `2`\n"),
+            "{tree}"
+        );
+        let tree = check(EXPR, "// grammar: expr.ungram\n// root: Term\n1\n");
+        assert_eq!(
+            tree,
+            "error@33..37: `Term` is not a node of `expr.ungram`\n"
+        );
+        let tree = check(EXPR, "// grammar: other.ungram\n// root: Expr\n1\n");
+        assert_eq!(
+            tree,
+            "error@12..24: cannot find the grammar `other.ungram`\n"
+        );
+    }
+
+    #[test]
+    fn words_do_not_match_keywords() {
+        let grammar = "S = 'let' 'lex:ident' | 'lex:ident' 'lex:ident'";
+        let tree = check(grammar, "// grammar: expr.ungram\n// root: S\nletter x");
+        assert_eq!(
+            tree,
+            "\
+S@35..43
+  'lex:ident'@35..41
+  'lex:ident'@42..43
+"
+        );
+    }
+
+    #[test]
+    fn matches_keywords_before_wide_characters() {
+        let grammar = "S = 'let' 'lex:ident' | 'lex:ident'";
+        let tree = check(grammar, "// grammar: expr.ungram\n// root: S\nééé");
+        assert_eq!(tree, "S@35..41\n  'lex:ident'@35..41\n");
+    }
+}
@@ -0,0 +1,64 @@
+//! Canonical text for rules, independent of how they were laid out.
+use crate::syntax::ast::{self, Expr};
+
+/// Prints a rule with its top-level alternatives on separate lines:
+///
+/// ```text
+/// Name =
+///   A
+/// | B
+/// ```
+pub(crate) fn print_rule(rule: &ast::Rule) -> String {
+    let name = rule
+        .name()
+        .map_or(String::new(), |it| it.text().to_string());
+    match rule.body() {
+        Some(Expr::Alt(it)) => {
+            let arms: Vec<_> = Expr::Alt(it).children().map(|it| print_expr(&it)).collect();
+            format!("{name} =\n  {}", arms.join("\n| "))
+        }
+        Some(body) => format!("{name} = {}", print_expr(&body)),
+        None => format!("{name} ="),
+    }
+}
+
+/// Prints an expression on one line, with single spaces between items.
+pub(crate) fn print_expr(expr: &Expr) -> String {
+    let children = || expr.children().map(|it| print_expr(&it));
+    match expr {
+        Expr::Alt(_) => children().collect::<Vec<_>>().join(" | "),
+        Expr::Seq(_) => children().collect::<Vec<_>>().join(" "),
+        Expr::Labeled(it) => {
+            let label = it.label().map_or(String::new(), |it| it.text().to_string());
+            format!("{label}:{}", children().collect::<String>())
+        }
+        Expr::Node(it) => it.name().map_or(String::new(), |it| it.text().to_string()),
+        Expr::Token(it) => it.token().map_or(String::new(), |it| it.text().to_string()),
+        Expr::Paren(_) => format!("({})", children().collect::<String>()),
+        Expr::Opt(_) => format!("{}?", children().collect::<String>()),
+        Expr::Rep(_) => format!("{}*", children().collect::<String>()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::parse_rule;
+
+    fn check(text: &str, expected: &str) {
+        let rule = ast::Rule::cast(parse_rule(text).syntax_node()).unwrap();
+        assert_eq!(print_rule(&rule), expected);
+    }
+
+    #[test]
+    fn normalizes_layout() {
+        check(
+            "A =  B\n  C* // c\n  x:( D |'e' )?",
+            "A = B C* x:(D | 'e')?",
+        );
+        check(
+            "HrefUrl = BlockUrl\n    | ResourceUrl",
+            "HrefUrl =\n  BlockUrl\n| ResourceUrl",
+        );
+    }
+}
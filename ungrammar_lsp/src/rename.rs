@@ -0,0 +1,194 @@
+//! Renaming nodes, labels and tokens.
+//!
+//! Nodes and tokens are renamed everywhere in the file. Labels only mean
+//! something within their rule, so they are renamed there.
+use std::fmt;
+
+use rowan::{TextRange, TextSize};
+
+use crate::{
+    db::{FileId, SourceDatabase},
+    navigation::{self, Symbol},
+    syntax::{ast, escape_token, is_ident, SyntaxKind::*, SyntaxNode, SyntaxToken},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RenameError(String);
+
+impl fmt::Display for RenameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for RenameError {}
+
+macro_rules! rename_error {
+    ($($tt:tt)*) => { RenameError(format!($($tt)*)) };
+}
+
+/// What can be renamed.
+enum Target {
+    Symbol(Symbol),
+    /// A label and the rule it belongs to.
+    Label(String, SyntaxNode),
+}
+
+fn target_at(
+    db: &dyn SourceDatabase,
+    file: FileId,
+    offset: TextSize,
+) -> Option<(Target, SyntaxToken)> {
+    let root = db.parse(file).syntax_node();
+    let token = navigation::name_token_at(&root, offset)?;
+    if let Some(symbol) = navigation::classify(&token) {
+        return Some((Target::Symbol(symbol), token));
+    }
+    let labeled = token.parent().and_then(ast::LabeledRule::cast)?;
+    let rule = labeled.syntax.ancestors().find(|it| it.kind() == RULE)?;
+    Some((Target::Label(token.text().to_string(), rule), token))
+}
+
+/// Returns the range of the name at `offset` and its current value, or
+/// `None` if there is nothing to rename there.
+pub(crate) fn prepare_rename(
+    db: &dyn SourceDatabase,
+    file: FileId,
+    offset: TextSize,
+) -> Option<(TextRange, String)> {
+    let (target, token) = target_at(db, file, offset)?;
+    let placeholder = match target {
+        Target::Symbol(Symbol::Node(name) | Symbol::Token(name)) | Target::Label(name, _) => name,
+    };
+    Some((token.text_range(), placeholder))
+}
+
+/// Computes the edits that rename the name at `offset` to `new_name`. For
+/// tokens, `new_name` is the value without quotes.
+pub(crate) fn rename(
+    db: &dyn SourceDatabase,
+    file: FileId,
+    offset: TextSize,
+    new_name: &str,
+) -> Result<Vec<(TextRange, String)>, RenameError> {
+    let (target, _) =
+        target_at(db, file, offset).ok_or_else(|| rename_error!("no node, label or token here"))?;
+    let existing = |symbol: &Symbol| !navigation::references(db, file, symbol, true).is_empty();
+    match target {
+        Target::Symbol(Symbol::Node(name)) => {
+            if !is_ident(new_name) {
+                return Err(rename_error!("`{new_name}` is not a valid node name"));
+            }
+            if new_name != name && existing(&Symbol::Node(new_name.to_string())) {
+                return Err(rename_error!("a node named `{new_name}` already exists"));
+            }
+            let ranges = navigation::references(db, file, &Symbol::Node(name), true);
+            Ok(ranges
+                .into_iter()
+                .map(|it| (it, new_name.to_string()))
+                .collect())
+        }
+        Target::Symbol(Symbol::Token(value)) => {
+            if new_name.is_empty() {
+                return Err(rename_error!("tokens cannot be empty"));
+            }
+            if new_name != value && existing(&Symbol::Token(new_name.to_string())) {
+                return Err(rename_error!(
+                    "a token {} already exists",
+                    escape_token(new_name)
+                ));
+            }
+            let ranges = navigation::references(db, file, &Symbol::Token(value), true);
+            let text = escape_token(new_name);
+            Ok(ranges.into_iter().map(|it| (it, text.clone())).collect())
+        }
+        Target::Label(label, rule) => {
+            if !is_ident(new_name) {
+                return Err(rename_error!("`{new_name}` is not a valid label"));
+            }
+            let labels: Vec<_> = rule
+                .descendants()
+                .filter_map(ast::LabeledRule::cast)
+                .filter_map(|it| it.label())
+                .collect();
+            if new_name != label && labels.iter().any(|it| it.text() == new_name) {
+                let rule_name = ast::Rule::cast(rule).and_then(|it| it.name());
+                let rule_name = rule_name.as_ref().map_or("", |it| it.text());
+                return Err(rename_error!(
+                    "rule `{rule_name}` already has a label `{new_name}`"
+                ));
+            }
+            Ok(labels
+                .into_iter()
+                .filter(|it| it.text() == label)
+                .map(|it| (it.text_range(), new_name.to_string()))
+                .collect())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::db::RootDatabase;
+
+    /// Renames the name at `$0` in `text` and applies the edits.
+    fn check(text: &str, new_name: &str) -> Result<String, RenameError> {
+        let offset = TextSize::from(text.find("$0").unwrap() as u32);
+        let mut text = text.replace("$0", "");
+        let mut db = RootDatabase::default();
+        let file = FileId(0);
+        db.set_file_text(file, Arc::new(text.clone()));
+        let mut edits = rename(&db, file, offset, new_name)?;
+        edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start()));
+        for (range, new_text) in edits {
+            text.replace_range(std::ops::Range::<usize>::from(range), &new_text);
+        }
+        Ok(text)
+    }
+
+    #[test]
+    fn renames_nodes_everywhere() {
+        let text = "A = B$0 x:B\nB = 'b'\n%precedence B %left 'b'\n";
+        assert_eq!(
+            check(text, "Bee").unwrap(),
+            "A = Bee x:Bee\nBee = 'b'\n%precedence Bee %left 'b'\n"
+        );
+        assert_eq!(
+            check(text, "A").unwrap_err().to_string(),
+            "a node named `A` already exists"
+        );
+        assert!(check(text, "not valid").is_err());
+    }
+
+    #[test]
+    fn renames_labels_within_their_rule() {
+        let text = "A = x$0:B (x:C)?\nB = x:C y:C\nC = 'c'\n";
+        assert_eq!(
+            check(text, "lhs").unwrap(),
+            "A = lhs:B (lhs:C)?\nB = x:C y:C\nC = 'c'\n"
+        );
+        assert_eq!(
+            check("A = x$0:B y:B\nB = 'b'", "x").unwrap(),
+            "A = x:B y:B\nB = 'b'"
+        );
+        assert_eq!(
+            check("A = x$0:B y:B\nB = 'b'", "y")
+                .unwrap_err()
+                .to_string(),
+            "rule `A` already has a label `y`"
+        );
+    }
+
+    #[test]
+    fn renames_tokens_with_escapes() {
+        let text = "A = '+'$0 B\nB = '+' '-'\n";
+        assert_eq!(check(text, "'").unwrap(), "A = '\\'' B\nB = '\\'' '-'\n");
+        assert_eq!(
+            check(text, "-").unwrap_err().to_string(),
+            "a token '-' already exists"
+        );
+    }
+}
@@ -0,0 +1,95 @@
+//! Growing the selection along the syntax tree: from a token to its atom,
+//! to the sequence and the alternatives around it, up to the whole rule.
+use rowan::{TextRange, TextSize, TokenAtOffset};
+
+use crate::{
+    db::{FileId, SourceDatabase},
+    syntax::SyntaxNode,
+};
+
+/// The ranges the selection at `offset` grows through, innermost first,
+/// without repeats. Ends with the whole file.
+pub(crate) fn selection_ranges(
+    db: &dyn SourceDatabase,
+    file: FileId,
+    offset: TextSize,
+) -> Vec<TextRange> {
+    let root = db.parse(file).syntax_node();
+    let mut res = Vec::new();
+    let mut push = |range: TextRange| {
+        // Nodes that only wrap another one add nothing.
+        if res.last().is_none_or(|&last| last != range) {
+            res.push(range);
+        }
+    };
+    let token = match root.token_at_offset(offset) {
+        TokenAtOffset::None => None,
+        TokenAtOffset::Single(it) => Some(it),
+        // Between two tokens, the one that is not trivia is meant.
+        TokenAtOffset::Between(left, right) => match right.kind().is_trivia() {
+            true => Some(left),
+            false => Some(right),
+        },
+    };
+    let Some(token) = token else {
+        push(root.text_range());
+        return res;
+    };
+    match token.kind().is_trivia() {
+        true => push(TextRange::empty(offset)),
+        false => push(token.text_range()),
+    }
+    for node in token.parent_ancestors() {
+        if node.parent().is_none() {
+            push(node.text_range());
+        } else if let Some(range) = significant_range(&node) {
+            push(range);
+        }
+    }
+    res
+}
+
+/// The range of `node` without the comments and whitespace at its ends.
+fn significant_range(node: &SyntaxNode) -> Option<TextRange> {
+    let mut tokens = node
+        .descendants_with_tokens()
+        .filter_map(|it| it.into_token())
+        .filter(|it| !it.kind().is_trivia());
+    let first = tokens.next()?;
+    let last = tokens.last().unwrap_or_else(|| first.clone());
+    Some(first.text_range().cover(last.text_range()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::db::RootDatabase;
+
+    #[test]
+    fn grows_from_token_to_rule() {
+        let text = "A = 'x' | (b:B C)* D\n\nC = 'c'\n";
+        let offset = text.find("B C").unwrap();
+        let mut db = RootDatabase::default();
+        db.set_file_text(FileId(0), Arc::new(text.to_string()));
+        let found: Vec<_> = selection_ranges(&db, FileId(0), TextSize::from(offset as u32))
+            .into_iter()
+            .map(|it| &text[it])
+            .collect();
+        assert_eq!(
+            found,
+            [
+                "B",
+                "b:B",
+                "b:B C",
+                "(b:B C)",
+                "(b:B C)*",
+                "(b:B C)* D",
+                "'x' | (b:B C)* D",
+                "A = 'x' | (b:B C)* D",
+                text,
+            ]
+        );
+    }
+}
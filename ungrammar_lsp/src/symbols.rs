@@ -0,0 +1,311 @@
+//! The outline of a grammar file, and fuzzy search over node names.
+use std::sync::Arc;
+
+use rowan::TextRange;
+
+use crate::{
+    db::{FileId, SourceDatabase},
+    syntax::{ast, SyntaxKind::*, SyntaxNode, SyntaxToken},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StructureKind {
+    /// A comment that introduces the rules after it.
+    Section,
+    /// A rule that only chooses between other nodes, like `A = B | C`.
+    Enum,
+    Rule,
+    Label,
+    Precedence,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StructureNode {
+    pub(crate) name: String,
+    pub(crate) kind: StructureKind,
+    pub(crate) range: TextRange,
+    /// The part to highlight when the node is picked, like the rule name.
+    pub(crate) selection_range: TextRange,
+    pub(crate) children: Vec<StructureNode>,
+}
+
+/// The outline of a file: rules with their labels, grouped under section
+/// comments.
+///
+/// A section comment is a comment block that starts a line and is followed
+/// by a blank line, so that it does not document the rule below it. Its
+/// section extends up to the next section comment.
+pub(crate) fn file_structure(root: &SyntaxNode) -> Vec<StructureNode> {
+    let mut res = Vec::new();
+    let mut section: Option<StructureNode> = None;
+    for element in root.children_with_tokens() {
+        let item = match element {
+            rowan::NodeOrToken::Token(token) => {
+                if let Some(comment) = section_comment(&token) {
+                    res.extend(section.take());
+                    section = Some(comment);
+                }
+                continue;
+            }
+            rowan::NodeOrToken::Node(node) => match item_structure(node) {
+                Some(it) => it,
+                None => continue,
+            },
+        };
+        match &mut section {
+            Some(section) => {
+                section.range = section.range.cover(item.range);
+                section.children.push(item);
+            }
+            None => res.push(item),
+        }
+    }
+    res.extend(section);
+    res
+}
+
+fn item_structure(node: SyntaxNode) -> Option<StructureNode> {
+    if let Some(precedence) = ast::Precedence::cast(node.clone()) {
+        let name = precedence.node()?.name()?;
+        return Some(StructureNode {
+            name: format!("%precedence {}", name.text()),
+            kind: StructureKind::Precedence,
+            range: node.text_range(),
+            selection_range: name.text_range(),
+            children: Vec::new(),
+        });
+    }
+    let rule = ast::Rule::cast(node)?;
+    let name = rule.name()?;
+    let mut labels: Vec<StructureNode> = Vec::new();
+    for labeled in rule.syntax.descendants().filter_map(ast::LabeledRule::cast) {
+        let Some(label) = labeled.label() else {
+            continue;
+        };
+        if labels.iter().any(|it| it.name == label.text()) {
+            continue;
+        }
+        labels.push(StructureNode {
+            name: label.text().to_string(),
+            kind: StructureKind::Label,
+            range: labeled.syntax.text_range(),
+            selection_range: label.text_range(),
+            children: Vec::new(),
+        });
+    }
+    let is_enum = match rule.body() {
+        Some(body @ ast::Expr::Alt(_)) => {
+            body.children().all(|it| matches!(it, ast::Expr::Node(_)))
+        }
+        _ => false,
+    };
+    Some(StructureNode {
+        name: name.text().to_string(),
+        kind: if is_enum {
+            StructureKind::Enum
+        } else {
+            StructureKind::Rule
+        },
+        range: rule.syntax.text_range(),
+        selection_range: name.text_range(),
+        children: labels,
+    })
+}
+
+/// A node defined in a file, as the index of the workspace holds it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FileSymbol {
+    pub(crate) name: String,
+    pub(crate) kind: StructureKind,
+    /// The range of the name of the rule.
+    pub(crate) range: TextRange,
+    /// The range of the whole rule.
+    pub(crate) rule_range: TextRange,
+    /// The section the rule is in.
+    pub(crate) container: Option<String>,
+}
+
+/// The nodes `file` defines, in order.
+pub(crate) fn file_symbols(db: &dyn SourceDatabase, file: FileId) -> Arc<Vec<FileSymbol>> {
+    let root = db.parse(file).syntax_node();
+    let mut res = Vec::new();
+    let mut todo: Vec<_> = file_structure(&root)
+        .into_iter()
+        .rev()
+        .map(|it| (None, it))
+        .collect();
+    while let Some((container, node)) = todo.pop() {
+        match node.kind {
+            StructureKind::Section => {
+                let name = Some(node.name);
+                let children = node.children.into_iter().rev();
+                todo.extend(children.map(|it| (name.clone(), it)));
+            }
+            StructureKind::Rule | StructureKind::Enum => res.push(FileSymbol {
+                name: node.name,
+                kind: node.kind,
+                range: node.selection_range,
+                rule_range: node.range,
+                container,
+            }),
+            StructureKind::Label | StructureKind::Precedence => (),
+        }
+    }
+    Arc::new(res)
+}
+
+/// Returns a section for the comment block starting at `token`, if it is
+/// one.
+fn section_comment(token: &SyntaxToken) -> Option<StructureNode> {
+    if token.kind() != COMMENT {
+        return None;
+    }
+    // Either the file or a paragraph starts with the comment.
+    let starts_block = match token.prev_token() {
+        Some(prev) => {
+            prev.kind() == WHITESPACE
+                && (prev.prev_token().is_none() || prev.text().matches('\n').count() > 1)
+        }
+        None => true,
+    };
+    if !starts_block {
+        return None;
+    }
+    let mut last = token.clone();
+    loop {
+        let ws = last.next_token()?;
+        if ws.kind() != WHITESPACE {
+            return None;
+        }
+        match ws.text().matches('\n').count() {
+            0 => return None,
+            1 => (),
+            // A blank line: the block stands on its own.
+            _ => break,
+        }
+        match ws.next_token() {
+            Some(next) if next.kind() == COMMENT => last = next,
+            _ => return None,
+        }
+    }
+    let name = token.text().trim_start_matches('/').trim();
+    Some(StructureNode {
+        name: name.to_string(),
+        kind: StructureKind::Section,
+        range: token.text_range().cover(last.text_range()),
+        selection_range: token.text_range(),
+        children: Vec::new(),
+    })
+}
+
+/// Scores how well `name` matches `query`: all characters of the query must
+/// appear in order, ignoring case. Higher is better.
+pub(crate) fn fuzzy_score(query: &str, name: &str) -> Option<u32> {
+    let mut score = 0;
+    let mut chars = name.char_indices();
+    let mut prev_match: Option<usize> = None;
+    for q in query.chars() {
+        let (idx, c) = chars.find(|(_, c)| c.eq_ignore_ascii_case(&q))?;
+        score += 1;
+        if prev_match.is_some_and(|prev| prev + 1 == idx) || idx == 0 {
+            score += 2;
+        }
+        if c.is_uppercase() {
+            // Start of a word in `CamelCase`.
+            score += 1;
+        }
+        prev_match = Some(idx);
+    }
+    if query.eq_ignore_ascii_case(name) {
+        score += 10;
+    }
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{FileId, RootDatabase, SourceDatabase},
+        syntax::SyntaxNode,
+    };
+
+    fn outline(nodes: &[StructureNode], depth: usize, acc: &mut String) {
+        for node in nodes {
+            let name = node.name.chars().take(30).collect::<String>();
+            acc.push_str(&format!("{}{:?} {name}\n", "  ".repeat(depth), node.kind));
+            outline(&node.children, depth + 1, acc);
+        }
+    }
+
+    fn root(text: &str) -> SyntaxNode {
+        let mut db = RootDatabase::default();
+        db.set_file_text(FileId(0), std::sync::Arc::new(text.to_string()));
+        db.parse(FileId(0)).syntax_node()
+    }
+
+    #[test]
+    fn groups_rules_under_section_comments() {
+        let root = root(include_str!("../../markup_ungrams/zork_keg.ungram"));
+        let mut res = String::new();
+        outline(&file_structure(&root), 0, &mut res);
+        let head: Vec<_> = res.lines().take(8).collect();
+        assert_eq!(
+            head,
+            [
+                "Section Zork's flavor of markup. This ",
+                "  Rule HashUrlSafe",
+                "  Rule PathSegment",
+                "  Rule Ident",
+                "  Rule IntNumber",
+                "  Rule HashAlgo",
+                "  Rule ResourceTransferScheme",
+                "  Rule UrlEscapedString",
+            ]
+        );
+        assert!(res.contains("  Rule AbsolutePath\n    Label segment\n"));
+        assert!(res.contains("  Enum HrefUrl\n"));
+    }
+
+    #[test]
+    fn indexes_rules_with_their_section() {
+        use StructureKind::*;
+        let mut db = RootDatabase::default();
+        let text = "A = B | C\n\n// Two\n\nB = x:'b'\nC = 'c'\n%precedence C\n  %left 'c'\n";
+        db.set_file_text(FileId(0), std::sync::Arc::new(text.to_string()));
+        let symbols = db.file_symbols(FileId(0));
+        let found: Vec<_> = symbols
+            .iter()
+            .map(|it| {
+                let rule = &text[it.rule_range];
+                (&text[it.range], rule, it.kind, it.container.as_deref())
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("A", "A = B | C", Enum, None),
+                ("B", "B = x:'b'", Rule, Some("Two")),
+                ("C", "C = 'c'", Rule, Some("Two")),
+            ]
+        );
+    }
+
+    #[test]
+    fn sections_end_at_the_next_section() {
+        let root = root("A = 'a'\n\n// One\n\nB = 'b'\n// doc\nC = 'c'\n\n// Two\n\nD = 'd'\n");
+        let mut res = String::new();
+        outline(&file_structure(&root), 0, &mut res);
+        assert_eq!(
+            res,
+            "Rule A\nSection One\n  Rule B\n  Rule C\nSection Two\n  Rule D\n"
+        );
+    }
+
+    #[test]
+    fn fuzzy_matching() {
+        assert!(fuzzy_score("hurl", "HrefUrl").is_some());
+        assert!(fuzzy_score("xyz", "HrefUrl").is_none());
+        assert!(fuzzy_score("href", "HrefUrl") > fuzzy_score("href", "HashRefer"));
+    }
+}
@@ -0,0 +1,321 @@
+//! Lossless concrete syntax tree for ungrammar files.
+//!
+//! `ungrammar_fork` parses straight into a `Grammar` and gives up at the
+//! first error. Editor features need more than that: every token with its
+//! range, comments, and a tree even when the text is half-typed. This module
+//! provides exactly that, built on `rowan`.
+//!
+//! Rules are parsed one at a time (see [`parse_rule`]) so that the database
+//! can re-parse only the rules that changed and splice them back into the
+//! file's tree.
+pub(crate) mod ast;
+mod lexer;
+mod parser;
+
+use std::sync::Arc;
+
+use rowan::{GreenNode, TextRange, TextSize};
+
+pub(crate) use lexer::{escape_token, is_ident, lex};
+
+/// Every kind of token and node in the tree.
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u16)]
+pub(crate) enum SyntaxKind {
+    WHITESPACE,
+    COMMENT,
+    /// A node name or a label, like `Expr` or `lhs`.
+    IDENT,
+    /// A quoted token, like `'struct'`.
+    TOKEN_LIT,
+    EQ,
+    STAR,
+    QMARK,
+    PIPE,
+    COLON,
+    L_PAREN,
+    R_PAREN,
+    /// A `%` keyword, like `%precedence` or `%left`.
+    DIRECTIVE,
+    /// A character the lexer does not understand.
+    ERROR_TOKEN,
+
+    SOURCE_FILE,
+    /// `Name = body`
+    RULE,
+    /// `A | B`
+    ALT_RULE,
+    /// `A B`
+    SEQ_RULE,
+    /// `label:A`
+    LABELED_RULE,
+    /// `A`
+    NODE_REF,
+    /// `'a'`
+    TOKEN_REF,
+    /// `(A)`
+    PAREN_RULE,
+    /// `A?`
+    OPT_RULE,
+    /// `A*`
+    REP_RULE,
+    /// `%precedence Name` followed by its levels.
+    PRECEDENCE,
+    /// `%left '+' '-'`
+    PRECEDENCE_LEVEL,
+    /// Tokens the parser could not make sense of.
+    ERROR,
+}
+
+use SyntaxKind::*;
+
+const ALL_KINDS: [SyntaxKind; ERROR as usize + 1] = [
+    WHITESPACE,
+    COMMENT,
+    IDENT,
+    TOKEN_LIT,
+    EQ,
+    STAR,
+    QMARK,
+    PIPE,
+    COLON,
+    L_PAREN,
+    R_PAREN,
+    DIRECTIVE,
+    ERROR_TOKEN,
+    SOURCE_FILE,
+    RULE,
+    ALT_RULE,
+    SEQ_RULE,
+    LABELED_RULE,
+    NODE_REF,
+    TOKEN_REF,
+    PAREN_RULE,
+    OPT_RULE,
+    REP_RULE,
+    PRECEDENCE,
+    PRECEDENCE_LEVEL,
+    ERROR,
+];
+
+impl SyntaxKind {
+    pub(crate) fn is_trivia(self) -> bool {
+        matches!(self, WHITESPACE | COMMENT)
+    }
+}
+
+impl From<SyntaxKind> for rowan::SyntaxKind {
+    fn from(kind: SyntaxKind) -> Self {
+        Self(kind as u16)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) enum UngrammarLanguage {}
+
+impl rowan::Language for UngrammarLanguage {
+    type Kind = SyntaxKind;
+
+    fn kind_from_raw(raw: rowan::SyntaxKind) -> SyntaxKind {
+        let kind = ALL_KINDS[raw.0 as usize];
+        debug_assert_eq!(kind as u16, raw.0);
+        kind
+    }
+
+    fn kind_to_raw(kind: SyntaxKind) -> rowan::SyntaxKind {
+        kind.into()
+    }
+}
+
+pub(crate) type SyntaxNode = rowan::SyntaxNode<UngrammarLanguage>;
+pub(crate) type SyntaxToken = rowan::SyntaxToken<UngrammarLanguage>;
+
+/// A syntax error, with a range relative to the start of the parsed text.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct SyntaxError {
+    pub(crate) message: String,
+    pub(crate) range: TextRange,
+}
+
+impl SyntaxError {
+    pub(crate) fn new(message: impl Into<String>, range: TextRange) -> SyntaxError {
+        SyntaxError {
+            message: message.into(),
+            range,
+        }
+    }
+
+    pub(crate) fn shifted(&self, offset: TextSize) -> SyntaxError {
+        SyntaxError::new(self.message.clone(), self.range + offset)
+    }
+}
+
+/// The result of parsing: a green tree plus the errors found on the way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Parse {
+    pub(crate) green: GreenNode,
+    pub(crate) errors: Arc<Vec<SyntaxError>>,
+}
+
+impl Parse {
+    pub(crate) fn syntax_node(&self) -> SyntaxNode {
+        SyntaxNode::new_root(self.green.clone())
+    }
+}
+
+/// Parses the text of a single rule, as carved out of a file by
+/// [`split_rules`].
+///
+/// The root of the result is a [`SyntaxKind::RULE`] or
+/// [`SyntaxKind::PRECEDENCE`] node, or an [`SyntaxKind::ERROR`] node for junk
+/// that starts with neither `Name =` nor `%precedence`.
+pub(crate) fn parse_rule(text: &str) -> Parse {
+    let (tokens, mut errors) = lex(text);
+    let (green, parse_errors) = parser::parse_rule(&tokens);
+    errors.extend(parse_errors);
+    errors.sort_by_key(|it| it.range.start());
+    Parse {
+        green,
+        errors: Arc::new(errors),
+    }
+}
+
+/// A piece of a file: either trivia between rules or the text of one rule.
+///
+/// Precedence declarations count as rules here, named after their directive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Piece<'a> {
+    Trivia(SyntaxKind, &'a str),
+    Rule {
+        name: Option<&'a str>,
+        text: &'a str,
+    },
+}
+
+/// Splits `text` into rules and the trivia in between.
+///
+/// A rule starts at an identifier followed by `=`, or at `%precedence`, and
+/// extends up to the last non-trivia token before the next rule, so comments
+/// above a rule stay outside of it. Anything before the first rule is
+/// returned as a rule without a name.
+pub(crate) fn split_rules(text: &str) -> Vec<Piece<'_>> {
+    let (tokens, _) = lex(text);
+    let significant: Vec<usize> = (0..tokens.len())
+        .filter(|&idx| !tokens[idx].0.is_trivia())
+        .collect();
+    let is_rule_start = |sig: usize| match tokens[significant[sig]] {
+        (IDENT, _) => significant
+            .get(sig + 1)
+            .is_some_and(|&next| tokens[next].0 == EQ),
+        (DIRECTIVE, text) => text == "%precedence",
+        _ => false,
+    };
+
+    let mut res = Vec::new();
+    let mut offset = 0;
+    let mut emitted = 0;
+    let mut sig = 0;
+    while sig < significant.len() {
+        let start = sig;
+        sig += 1;
+        while sig < significant.len() && !is_rule_start(sig) {
+            sig += 1;
+        }
+        let (first, last) = (significant[start], significant[sig - 1]);
+        for &(kind, token_text) in &tokens[emitted..first] {
+            res.push(Piece::Trivia(kind, token_text));
+            offset += token_text.len();
+        }
+        let len: usize = tokens[first..=last].iter().map(|(_, it)| it.len()).sum();
+        let name = is_rule_start(start).then(|| tokens[first].1);
+        res.push(Piece::Rule {
+            name,
+            text: &text[offset..offset + len],
+        });
+        offset += len;
+        emitted = last + 1;
+    }
+    for &(kind, token_text) in &tokens[emitted..] {
+        res.push(Piece::Trivia(kind, token_text));
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kinds_round_trip() {
+        for (idx, kind) in ALL_KINDS.iter().enumerate() {
+            assert_eq!(*kind as usize, idx);
+        }
+    }
+
+    #[test]
+    fn split_keeps_comments_between_rules() {
+        let text = "// file\nA = 'a'\n  | B // tail\n\n// doc\nB = A*\n";
+        let pieces = split_rules(text);
+        let rules: Vec<_> = pieces
+            .iter()
+            .filter_map(|it| match it {
+                Piece::Rule { name, text } => Some((*name, *text)),
+                Piece::Trivia(..) => None,
+            })
+            .collect();
+        assert_eq!(
+            rules,
+            [(Some("A"), "A = 'a'\n  | B"), (Some("B"), "B = A*")]
+        );
+        let round_trip: String = pieces
+            .iter()
+            .map(|it| match it {
+                Piece::Trivia(_, text) | Piece::Rule { text, .. } => *text,
+            })
+            .collect();
+        assert_eq!(round_trip, text);
+    }
+
+    #[test]
+    fn parse_is_lossless_and_recovers() {
+        let text = "A = (B | 'c')* label:D? )";
+        let parse = parse_rule(text);
+        assert_eq!(parse.syntax_node().to_string(), text);
+        assert_eq!(parse.errors.len(), 1);
+        assert_eq!(parse.errors[0].message, "unexpected token");
+
+        let parse = parse_rule("A = | B");
+        assert_eq!(parse.errors.len(), 1);
+    }
+
+    #[test]
+    fn precedence_is_its_own_piece() {
+        let text = "E = E op:('+' | '*') E\n%precedence E\n  %left '+'\n  %left '*'\nF = E\n";
+        let names: Vec<_> = split_rules(text)
+            .into_iter()
+            .filter_map(|it| match it {
+                Piece::Rule { name, .. } => name,
+                Piece::Trivia(..) => None,
+            })
+            .collect();
+        assert_eq!(names, ["E", "%precedence", "F"]);
+
+        let parse = parse_rule("%precedence E\n  %left '+' '-'\n  %right '='");
+        assert_eq!(*parse.errors, []);
+        let node = parse.syntax_node();
+        assert_eq!(node.kind(), PRECEDENCE);
+        assert_eq!(
+            node.children()
+                .filter(|it| it.kind() == PRECEDENCE_LEVEL)
+                .count(),
+            2
+        );
+
+        let parse = parse_rule("%precedence E %left");
+        assert_eq!(
+            parse.errors[0].message,
+            "expected an operator token, like `'+'`"
+        );
+    }
+}
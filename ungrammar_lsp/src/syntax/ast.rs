@@ -0,0 +1,198 @@
+//! Typed views over the untyped syntax tree.
+use ungrammar_fork::Assoc;
+
+use super::{SyntaxKind, SyntaxKind::*, SyntaxNode, SyntaxToken};
+
+macro_rules! ast_node {
+    ($name:ident, $kind:ident) => {
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        pub(crate) struct $name {
+            pub(crate) syntax: SyntaxNode,
+        }
+
+        impl $name {
+            pub(crate) fn cast(syntax: SyntaxNode) -> Option<$name> {
+                (syntax.kind() == $kind).then_some($name { syntax })
+            }
+        }
+    };
+}
+
+ast_node!(SourceFile, SOURCE_FILE);
+ast_node!(Rule, RULE);
+ast_node!(LabeledRule, LABELED_RULE);
+ast_node!(NodeRef, NODE_REF);
+ast_node!(TokenRef, TOKEN_REF);
+ast_node!(Precedence, PRECEDENCE);
+ast_node!(PrecedenceLevel, PRECEDENCE_LEVEL);
+
+fn token(node: &SyntaxNode, kind: SyntaxKind) -> Option<SyntaxToken> {
+    node.children_with_tokens()
+        .filter_map(|it| it.into_token())
+        .find(|it| it.kind() == kind)
+}
+
+impl SourceFile {
+    pub(crate) fn rules(&self) -> impl Iterator<Item = Rule> {
+        self.syntax.children().filter_map(Rule::cast)
+    }
+
+    pub(crate) fn precedences(&self) -> impl Iterator<Item = Precedence> {
+        self.syntax.children().filter_map(Precedence::cast)
+    }
+}
+
+impl Rule {
+    pub(crate) fn name(&self) -> Option<SyntaxToken> {
+        token(&self.syntax, IDENT)
+    }
+
+    pub(crate) fn body(&self) -> Option<Expr> {
+        self.syntax.children().find_map(Expr::cast)
+    }
+
+    /// The comment lines right above the rule, without the `//`. A blank
+    /// line or a comment that trails another rule ends the block.
+    pub(crate) fn doc_comment(&self) -> Option<String> {
+        let lines: Vec<_> = self
+            .doc_comment_tokens()
+            .iter()
+            .map(|token| {
+                let text = token.text().trim_start_matches('/');
+                text.strip_prefix(" ")
+                    .unwrap_or(text)
+                    .trim_end()
+                    .to_string()
+            })
+            .collect();
+        if lines.is_empty() {
+            return None;
+        }
+        Some(lines.join("\n"))
+    }
+
+    /// The comment lines directly above the rule, in order.
+    pub(crate) fn doc_comment_tokens(&self) -> Vec<SyntaxToken> {
+        let mut res = Vec::new();
+        let mut prev = self.syntax.prev_sibling_or_token();
+        while let Some(token) = prev.and_then(|it| it.into_token()) {
+            match token.kind() {
+                WHITESPACE if token.text().matches('\n').count() > 1 => break,
+                WHITESPACE => (),
+                COMMENT => {
+                    let starts_line = match token.prev_token() {
+                        Some(it) => it.kind() == WHITESPACE && it.text().contains('\n'),
+                        None => true,
+                    };
+                    if !starts_line {
+                        break;
+                    }
+                    res.push(token.clone());
+                }
+                _ => break,
+            }
+            prev = token.prev_sibling_or_token();
+        }
+        res.reverse();
+        res
+    }
+}
+
+impl LabeledRule {
+    pub(crate) fn label(&self) -> Option<SyntaxToken> {
+        token(&self.syntax, IDENT)
+    }
+
+    pub(crate) fn rule(&self) -> Option<Expr> {
+        self.syntax.children().find_map(Expr::cast)
+    }
+}
+
+impl NodeRef {
+    pub(crate) fn name(&self) -> Option<SyntaxToken> {
+        token(&self.syntax, IDENT)
+    }
+}
+
+impl TokenRef {
+    pub(crate) fn token(&self) -> Option<SyntaxToken> {
+        token(&self.syntax, TOKEN_LIT)
+    }
+
+    /// The token's value, without quotes and escapes.
+    pub(crate) fn value(&self) -> Option<String> {
+        self.token()
+            .map(|it| super::lexer::unescape_token(it.text()))
+    }
+}
+
+impl Precedence {
+    /// The node whose operators are declared.
+    pub(crate) fn node(&self) -> Option<NodeRef> {
+        self.syntax.children().find_map(NodeRef::cast)
+    }
+
+    pub(crate) fn levels(&self) -> impl Iterator<Item = PrecedenceLevel> {
+        self.syntax.children().filter_map(PrecedenceLevel::cast)
+    }
+}
+
+impl PrecedenceLevel {
+    pub(crate) fn assoc(&self) -> Option<Assoc> {
+        match token(&self.syntax, DIRECTIVE)?.text() {
+            "%left" => Some(Assoc::Left),
+            "%right" => Some(Assoc::Right),
+            "%nonassoc" => Some(Assoc::NonAssoc),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn tokens(&self) -> impl Iterator<Item = TokenRef> {
+        self.syntax.children().filter_map(TokenRef::cast)
+    }
+}
+
+/// Any rule expression, like the body of a rule or an operand of `|`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Expr {
+    Alt(SyntaxNode),
+    Seq(SyntaxNode),
+    Labeled(LabeledRule),
+    Node(NodeRef),
+    Token(TokenRef),
+    Paren(SyntaxNode),
+    Opt(SyntaxNode),
+    Rep(SyntaxNode),
+}
+
+impl Expr {
+    pub(crate) fn cast(syntax: SyntaxNode) -> Option<Expr> {
+        let res = match syntax.kind() {
+            ALT_RULE => Expr::Alt(syntax),
+            SEQ_RULE => Expr::Seq(syntax),
+            LABELED_RULE => Expr::Labeled(LabeledRule::cast(syntax)?),
+            NODE_REF => Expr::Node(NodeRef::cast(syntax)?),
+            TOKEN_REF => Expr::Token(TokenRef::cast(syntax)?),
+            PAREN_RULE => Expr::Paren(syntax),
+            OPT_RULE => Expr::Opt(syntax),
+            REP_RULE => Expr::Rep(syntax),
+            _ => return None,
+        };
+        Some(res)
+    }
+
+    pub(crate) fn syntax(&self) -> &SyntaxNode {
+        match self {
+            Expr::Alt(it) | Expr::Seq(it) | Expr::Paren(it) | Expr::Opt(it) | Expr::Rep(it) => it,
+            Expr::Labeled(it) => &it.syntax,
+            Expr::Node(it) => &it.syntax,
+            Expr::Token(it) => &it.syntax,
+        }
+    }
+
+    /// Direct sub-expressions: the arms of `|`, the items of a sequence, the
+    /// operand of `?`/`*`/label/parentheses.
+    pub(crate) fn children(&self) -> impl Iterator<Item = Expr> {
+        self.syntax().children().filter_map(Expr::cast)
+    }
+}
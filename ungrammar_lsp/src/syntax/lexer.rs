@@ -0,0 +1,160 @@
+//! Lossless lexer. Unlike the one in `ungrammar_fork`, it keeps whitespace
+//! and comments and never stops at an error.
+use rowan::{TextRange, TextSize};
+
+use super::{SyntaxError, SyntaxKind, SyntaxKind::*};
+
+/// Splits `text` into tokens. Concatenating the token texts gives back
+/// `text`.
+pub(crate) fn lex(text: &str) -> (Vec<(SyntaxKind, &str)>, Vec<SyntaxError>) {
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let offset = TextSize::of(&text[..text.len() - rest.len()]);
+        let (kind, len, error) = next_token(rest);
+        if let Some(message) = error {
+            let range = TextRange::at(offset, TextSize::from(len as u32));
+            errors.push(SyntaxError::new(message, range));
+        }
+        tokens.push((kind, &rest[..len]));
+        rest = &rest[len..];
+    }
+    (tokens, errors)
+}
+
+fn next_token(input: &str) -> (SyntaxKind, usize, Option<&'static str>) {
+    let mut chars = input.chars();
+    let c = chars.next().unwrap();
+    let kind = match c {
+        ' ' | '\t' | '\n' => {
+            let len = input
+                .find(|c| !matches!(c, ' ' | '\t' | '\n'))
+                .unwrap_or(input.len());
+            return (WHITESPACE, len, None);
+        }
+        '/' if input.starts_with("//") => {
+            let len = input.find('\n').unwrap_or(input.len());
+            return (COMMENT, len, None);
+        }
+        '\'' => return token_literal(input),
+        c if is_ident_char(c) => {
+            let len = input.find(|c| !is_ident_char(c)).unwrap_or(input.len());
+            return (IDENT, len, None);
+        }
+        '%' => {
+            let len = 1 + input[1..]
+                .find(|c| !is_ident_char(c))
+                .unwrap_or(input.len() - 1);
+            if len == 1 {
+                return (ERROR_TOKEN, 1, Some("expected a directive name after `%`"));
+            }
+            return (DIRECTIVE, len, None);
+        }
+        '=' => EQ,
+        '*' => STAR,
+        '?' => QMARK,
+        '|' => PIPE,
+        ':' => COLON,
+        '(' => L_PAREN,
+        ')' => R_PAREN,
+        '\r' => {
+            return (
+                ERROR_TOKEN,
+                1,
+                Some("unexpected `\\r`, only Unix-style line endings allowed"),
+            )
+        }
+        c => return (ERROR_TOKEN, c.len_utf8(), Some("unexpected character")),
+    };
+    (kind, 1, None)
+}
+
+fn token_literal(input: &str) -> (SyntaxKind, usize, Option<&'static str>) {
+    let mut chars = input.char_indices().skip(1);
+    let mut error = None;
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '\'' => return (TOKEN_LIT, idx + 1, error),
+            '\\' => match chars.next() {
+                Some((_, '\\' | '\'')) => (),
+                Some((_, '\n')) | None => break,
+                Some(_) => error = Some("invalid escape in token literal"),
+            },
+            // Stop at the end of the line so that one missing quote does
+            // not swallow the rest of the file.
+            '\n' => return (TOKEN_LIT, idx, Some("unclosed token literal")),
+            _ => (),
+        }
+    }
+    let len = input.find('\n').unwrap_or(input.len());
+    (TOKEN_LIT, len, Some("unclosed token literal"))
+}
+
+fn is_ident_char(c: char) -> bool {
+    matches!(c, 'a'..='z' | 'A'..='Z' | '_')
+}
+
+/// Returns `true` if `text` can be used as a node name or a label.
+pub(crate) fn is_ident(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(is_ident_char)
+}
+
+/// The inverse of [`unescape_token`]: quotes `value` as a token literal.
+pub(crate) fn escape_token(value: &str) -> String {
+    let mut buf = String::with_capacity(value.len() + 2);
+    buf.push('\'');
+    for c in value.chars() {
+        if matches!(c, '\\' | '\'') {
+            buf.push('\\');
+        }
+        buf.push(c);
+    }
+    buf.push('\'');
+    buf
+}
+
+/// Returns the value of a token literal, without quotes and escapes.
+pub(crate) fn unescape_token(text: &str) -> String {
+    let inner = text.strip_prefix('\'').unwrap_or(text);
+    let inner = inner.strip_suffix('\'').unwrap_or(inner);
+    let mut buf = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => buf.extend(chars.next()),
+            c => buf.push(c),
+        }
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lexes_every_kind() {
+        let (tokens, errors) = lex("A = x:'\\'' B* | (C?) // c\n%left");
+        let kinds: Vec<_> = tokens.iter().map(|(kind, _)| *kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                IDENT, WHITESPACE, EQ, WHITESPACE, IDENT, COLON, TOKEN_LIT, WHITESPACE, IDENT,
+                STAR, WHITESPACE, PIPE, WHITESPACE, L_PAREN, IDENT, QMARK, R_PAREN, WHITESPACE,
+                COMMENT, WHITESPACE, DIRECTIVE,
+            ]
+        );
+        assert!(errors.is_empty());
+        assert_eq!(unescape_token(tokens[6].1), "'");
+        assert_eq!(escape_token("'"), tokens[6].1);
+    }
+
+    #[test]
+    fn unclosed_literal_stops_at_newline() {
+        let (tokens, errors) = lex("A = 'oops\nB = 'b'");
+        assert_eq!(tokens[4], (TOKEN_LIT, "'oops"));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "unclosed token literal");
+    }
+}
@@ -0,0 +1,264 @@
+//! Error-tolerant parser for a single rule.
+//!
+//! Mirrors what `ungrammar_fork` accepts: `?` and `*` apply once to an atom,
+//! a label applies to an atom together with its postfix operator, and the
+//! first alternative must not start with `|`. Precedence declarations are
+//! parsed as items of their own.
+use rowan::{Checkpoint, GreenNode, GreenNodeBuilder, TextRange, TextSize};
+
+use super::{SyntaxError, SyntaxKind, SyntaxKind::*};
+
+pub(super) fn parse_rule(tokens: &[(SyntaxKind, &str)]) -> (GreenNode, Vec<SyntaxError>) {
+    let mut p = Parser {
+        tokens,
+        pos: 0,
+        offset: TextSize::default(),
+        builder: GreenNodeBuilder::new(),
+        errors: Vec::new(),
+    };
+    if p.at(IDENT) && p.nth(1) == Some(EQ) {
+        p.builder.start_node(RULE.into());
+        p.bump();
+        p.bump();
+        if p.at_eof() {
+            p.error("expected a rule");
+        } else {
+            alt_rule(&mut p);
+        }
+        leftover(&mut p);
+    } else if p.at(DIRECTIVE) {
+        p.builder.start_node(PRECEDENCE.into());
+        precedence(&mut p);
+        leftover(&mut p);
+    } else {
+        p.builder.start_node(ERROR.into());
+        p.error("expected a rule, like `Name = ...`");
+        while !p.at_eof() {
+            p.bump();
+        }
+    }
+    p.eat_trivia();
+    p.builder.finish_node();
+    (p.builder.finish(), p.errors)
+}
+
+struct Parser<'t> {
+    tokens: &'t [(SyntaxKind, &'t str)],
+    pos: usize,
+    offset: TextSize,
+    builder: GreenNodeBuilder<'static>,
+    errors: Vec<SyntaxError>,
+}
+
+impl Parser<'_> {
+    /// Kind of the `n`th significant token ahead.
+    fn nth(&self, n: usize) -> Option<SyntaxKind> {
+        self.tokens[self.pos..]
+            .iter()
+            .map(|(kind, _)| *kind)
+            .filter(|kind| !kind.is_trivia())
+            .nth(n)
+    }
+    fn at(&self, kind: SyntaxKind) -> bool {
+        self.nth(0) == Some(kind)
+    }
+    /// Text of the next significant token.
+    fn current_text(&self) -> Option<&str> {
+        self.tokens[self.pos..]
+            .iter()
+            .find(|(kind, _)| !kind.is_trivia())
+            .map(|(_, text)| *text)
+    }
+    fn at_eof(&self) -> bool {
+        self.nth(0).is_none()
+    }
+    /// Attaches pending trivia to the innermost open node.
+    fn eat_trivia(&mut self) {
+        while let Some(&(kind, text)) = self.tokens.get(self.pos) {
+            if !kind.is_trivia() {
+                break;
+            }
+            self.builder.token(kind.into(), text);
+            self.offset += TextSize::of(text);
+            self.pos += 1;
+        }
+    }
+    fn bump(&mut self) {
+        self.eat_trivia();
+        if let Some(&(kind, text)) = self.tokens.get(self.pos) {
+            self.builder.token(kind.into(), text);
+            self.offset += TextSize::of(text);
+            self.pos += 1;
+        }
+    }
+    fn start_node(&mut self, kind: SyntaxKind) {
+        self.eat_trivia();
+        self.builder.start_node(kind.into());
+    }
+    fn checkpoint(&mut self) -> Checkpoint {
+        self.eat_trivia();
+        self.builder.checkpoint()
+    }
+    /// Reports an error at the next significant token, or at the end.
+    fn error(&mut self, message: &str) {
+        let mut offset = self.offset;
+        let mut len = TextSize::default();
+        for &(kind, text) in &self.tokens[self.pos..] {
+            if !kind.is_trivia() {
+                len = TextSize::of(text);
+                break;
+            }
+            offset += TextSize::of(text);
+        }
+        let range = TextRange::at(offset, len);
+        self.errors.push(SyntaxError::new(message, range));
+    }
+}
+
+/// Wraps everything that is left into an error node.
+fn leftover(p: &mut Parser<'_>) {
+    if p.at_eof() {
+        return;
+    }
+    p.error("unexpected token");
+    p.start_node(ERROR);
+    while !p.at_eof() {
+        p.bump();
+    }
+    p.builder.finish_node();
+}
+
+fn at_level_start(p: &Parser<'_>) -> bool {
+    p.at(DIRECTIVE) && matches!(p.current_text(), Some("%left" | "%right" | "%nonassoc"))
+}
+
+/// `%precedence Name` followed by one or more levels. The caller has opened
+/// the `PRECEDENCE` node.
+fn precedence(p: &mut Parser<'_>) {
+    if p.current_text() != Some("%precedence") {
+        p.error("unknown directive, expected `%precedence`");
+    }
+    p.bump();
+    if p.at(IDENT) {
+        p.start_node(NODE_REF);
+        p.bump();
+        p.builder.finish_node();
+    } else {
+        p.error("expected the name of a node after `%precedence`");
+    }
+    if !at_level_start(p) {
+        p.error("expected `%left`, `%right` or `%nonassoc`");
+    }
+    while at_level_start(p) {
+        p.start_node(PRECEDENCE_LEVEL);
+        p.bump();
+        if !p.at(TOKEN_LIT) {
+            p.error("expected an operator token, like `'+'`");
+        }
+        while p.at(TOKEN_LIT) {
+            p.start_node(TOKEN_REF);
+            p.bump();
+            p.builder.finish_node();
+        }
+        p.builder.finish_node();
+    }
+}
+
+fn at_atom_start(p: &Parser<'_>) -> bool {
+    matches!(p.nth(0), Some(IDENT | TOKEN_LIT | L_PAREN))
+}
+
+fn alt_rule(p: &mut Parser<'_>) {
+    let checkpoint = p.checkpoint();
+    let leading_pipe = p.at(PIPE);
+    if leading_pipe {
+        p.error(
+            "The first element in a sequence of productions or alternatives \
+            must not have a leading pipe (`|`)",
+        );
+        p.bump();
+    }
+    let mut arms = 1;
+    seq_rule(p);
+    while p.at(PIPE) {
+        p.bump();
+        seq_rule(p);
+        arms += 1;
+    }
+    if arms > 1 || leading_pipe {
+        p.builder.start_node_at(checkpoint, ALT_RULE.into());
+        p.builder.finish_node();
+    }
+}
+
+fn seq_rule(p: &mut Parser<'_>) {
+    if !at_atom_start(p) {
+        p.error("expected a rule");
+        return;
+    }
+    let checkpoint = p.checkpoint();
+    let mut items = 0;
+    while at_atom_start(p) {
+        postfix_rule(p);
+        items += 1;
+    }
+    if items > 1 {
+        p.builder.start_node_at(checkpoint, SEQ_RULE.into());
+        p.builder.finish_node();
+    }
+}
+
+fn postfix_rule(p: &mut Parser<'_>) {
+    let checkpoint = p.checkpoint();
+    let labeled = atom_rule(p);
+    if labeled {
+        return;
+    }
+    let kind = match p.nth(0) {
+        Some(QMARK) => OPT_RULE,
+        Some(STAR) => REP_RULE,
+        _ => return,
+    };
+    p.builder.start_node_at(checkpoint, kind.into());
+    p.bump();
+    p.builder.finish_node();
+}
+
+/// Parses an atom, returning `true` if it was a labeled rule.
+fn atom_rule(p: &mut Parser<'_>) -> bool {
+    match p.nth(0) {
+        Some(IDENT) if p.nth(1) == Some(COLON) => {
+            p.start_node(LABELED_RULE);
+            p.bump();
+            p.bump();
+            if at_atom_start(p) {
+                postfix_rule(p);
+            } else {
+                p.error("expected a rule after the label");
+            }
+            p.builder.finish_node();
+            return true;
+        }
+        Some(IDENT) => {
+            p.start_node(NODE_REF);
+            p.bump();
+        }
+        Some(TOKEN_LIT) => {
+            p.start_node(TOKEN_REF);
+            p.bump();
+        }
+        Some(L_PAREN) => {
+            p.start_node(PAREN_RULE);
+            p.bump();
+            alt_rule(p);
+            if p.at(R_PAREN) {
+                p.bump();
+            } else {
+                p.error("unexpected token, expected `)`");
+            }
+        }
+        _ => unreachable!("callers check `at_atom_start`"),
+    }
+    p.builder.finish_node();
+    false
+}
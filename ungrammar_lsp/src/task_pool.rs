@@ -0,0 +1,56 @@
+//! A fixed set of worker threads for requests that only read the database,
+//! so that a slow request does not hold up edits.
+use std::thread::{self, JoinHandle};
+
+use crossbeam_channel::{Receiver, Sender};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+pub(crate) struct TaskPool<T> {
+    jobs: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    results: Sender<T>,
+    /// Where the results of the tasks arrive, in the order they finish.
+    pub(crate) receiver: Receiver<T>,
+}
+
+impl<T: Send + 'static> TaskPool<T> {
+    pub(crate) fn new(threads: usize) -> TaskPool<T> {
+        let (jobs, queue) = crossbeam_channel::unbounded::<Job>();
+        let workers = (0..threads)
+            .map(|idx| {
+                let queue = queue.clone();
+                thread::Builder::new()
+                    .name(format!("worker-{idx}"))
+                    .spawn(move || queue.iter().for_each(|job| job()))
+                    .expect("failed to spawn a worker thread")
+            })
+            .collect();
+        let (results, receiver) = crossbeam_channel::unbounded();
+        TaskPool {
+            jobs: Some(jobs),
+            workers,
+            results,
+            receiver,
+        }
+    }
+
+    pub(crate) fn spawn(&self, task: impl FnOnce() -> T + Send + 'static) {
+        let results = self.results.clone();
+        let job = Box::new(move || {
+            // Nobody is waiting for the result if the server is going down.
+            let _ = results.send(task());
+        });
+        self.jobs.as_ref().unwrap().send(job).unwrap();
+    }
+}
+
+impl<T> Drop for TaskPool<T> {
+    /// Lets the workers finish what they are doing, then stops them.
+    fn drop(&mut self) {
+        drop(self.jobs.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,1609 @@
+//! End-to-end tests: the server runs on a thread of the test process and
+//! talks to a fake client over an in-memory connection.
+use std::{
+    process::ExitCode,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use lsp_server::{
+    Connection, ErrorCode, Message, Notification as NotificationData, Request as RequestData,
+    RequestId, Response,
+};
+use lsp_types::{
+    notification::{
+        Cancel, DidChangeConfiguration, DidChangeTextDocument, DidChangeWatchedFiles,
+        DidCloseTextDocument, DidOpenTextDocument, Exit, Initialized, Notification,
+        PublishDiagnostics,
+    },
+    request::{
+        CallHierarchyIncomingCalls, CallHierarchyOutgoingCalls, CallHierarchyPrepare,
+        CodeActionRequest, CodeLensRequest, Completion, DocumentHighlightRequest,
+        DocumentSymbolRequest, ExecuteCommand, FoldingRangeRequest, Formatting, GotoDefinition,
+        HoverRequest, Initialize, InlayHintRequest, PrepareRenameRequest, RangeFormatting,
+        References, RegisterCapability, Rename, Request, ResolveCompletionItem,
+        SelectionRangeRequest, SemanticTokensFullRequest, SemanticTokensRangeRequest, Shutdown,
+        WorkspaceConfiguration, WorkspaceSymbolRequest,
+    },
+    CallHierarchyIncomingCallsParams, CallHierarchyOutgoingCallsParams, CallHierarchyPrepareParams,
+    CancelParams, ClientCapabilities, CodeActionContext, CodeActionKind, CodeActionOrCommand,
+    CodeActionParams, CodeLensParams, CompletionParams, CompletionResponse, CompletionTextEdit,
+    DiagnosticSeverity, DidChangeConfigurationParams, DidChangeTextDocumentParams,
+    DidChangeWatchedFilesParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    DocumentFormattingParams, DocumentHighlightKind, DocumentHighlightParams,
+    DocumentRangeFormattingParams, DocumentSymbolParams, DocumentSymbolResponse, Documentation,
+    ExecuteCommandParams, FileChangeType, FileEvent, FoldingRangeKind, FoldingRangeParams,
+    FormattingOptions, GotoDefinitionParams, GotoDefinitionResponse, HoverContents, HoverParams,
+    InitializeParams, InitializedParams, InlayHintLabel, InlayHintParams, InsertTextFormat,
+    Location, MarkupKind, NumberOrString, OneOf, Position, PositionEncodingKind,
+    PrepareRenameResponse, PublishDiagnosticsParams, Range, ReferenceContext, ReferenceParams,
+    RegistrationParams, RenameParams, SelectionRangeParams, SemanticTokensParams,
+    SemanticTokensRangeParams, SemanticTokensRangeResult, SemanticTokensResult,
+    SemanticTokensServerCapabilities, ServerCapabilities, SymbolKind,
+    TextDocumentContentChangeEvent, TextDocumentIdentifier, TextDocumentItem,
+    TextDocumentPositionParams, TextEdit, Url, VersionedTextDocumentIdentifier, WorkspaceFolder,
+    WorkspaceSymbolParams,
+};
+
+use crate::{
+    db::check_canceled,
+    handlers,
+    lsp_ext::{
+        AstPreview, DocumentDiagnosticParams, DocumentDiagnosticReport, DocumentDiagnosticRequest,
+        PreviousResultId, SampleTree, SampleTreeParams, TextDocumentContentParams,
+        TextDocumentContentRefresh, TextDocumentContentRequest, WorkspaceDiagnosticParams,
+        WorkspaceDiagnosticRefresh, WorkspaceDiagnosticRequest,
+    },
+    transport::{self, Address},
+    StateSnapshot,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A request that takes until the database changes, to have something to
+/// cancel.
+pub(crate) enum Sleep {}
+
+impl Request for Sleep {
+    type Params = ();
+    type Result = ();
+    const METHOD: &'static str = "test/sleep";
+}
+
+pub(crate) fn handle_sleep(snap: &StateSnapshot, _: ()) -> handlers::Result<()> {
+    let start = Instant::now();
+    while start.elapsed() < TIMEOUT {
+        check_canceled(&*snap.db);
+        thread::sleep(Duration::from_millis(1));
+    }
+    Ok(())
+}
+
+enum Unknown {}
+
+impl Request for Unknown {
+    type Params = ();
+    type Result = ();
+    const METHOD: &'static str = "test/unknown";
+}
+
+/// `initialize` with capabilities `lsp-types` does not know about.
+enum RawInitialize {}
+
+impl Request for RawInitialize {
+    type Params = serde_json::Value;
+    type Result = serde_json::Value;
+    const METHOD: &'static str = Initialize::METHOD;
+}
+
+struct TestServer {
+    capabilities: ServerCapabilities,
+    /// `capabilities` as sent, with the ones `lsp-types` does not know.
+    raw_capabilities: serde_json::Value,
+    client: Option<Connection>,
+    thread: Option<JoinHandle<ExitCode>>,
+    next_id: i32,
+}
+
+impl TestServer {
+    fn new() -> TestServer {
+        TestServer::with_params(InitializeParams {
+            capabilities: vscode(),
+            ..Default::default()
+        })
+    }
+
+    fn with_params(params: InitializeParams) -> TestServer {
+        TestServer::with_raw_params(serde_json::to_value(params).unwrap())
+    }
+
+    fn with_raw_params(params: serde_json::Value) -> TestServer {
+        let (server, client) = Connection::memory();
+        let thread = thread::spawn(move || crate::run_server(&server).unwrap());
+        TestServer::initialize(client, Some(thread), params)
+    }
+
+    /// Initializes a server the test talks to over `client`. Without
+    /// `thread`, the server runs elsewhere and `exit` cannot wait for it.
+    fn initialize(
+        client: Connection,
+        thread: Option<JoinHandle<ExitCode>>,
+        params: serde_json::Value,
+    ) -> TestServer {
+        let mut res = TestServer {
+            capabilities: ServerCapabilities::default(),
+            raw_capabilities: serde_json::Value::Null,
+            client: Some(client),
+            thread,
+            next_id: 0,
+        };
+        let result = res.request::<RawInitialize>(params);
+        res.raw_capabilities = result["capabilities"].clone();
+        res.capabilities = serde_json::from_value(res.raw_capabilities.clone()).unwrap();
+        res.notify::<Initialized>(InitializedParams {});
+        res
+    }
+
+    fn client(&self) -> &Connection {
+        self.client.as_ref().unwrap()
+    }
+
+    fn notify<N: Notification>(&self, params: N::Params) {
+        let notif = NotificationData::new(N::METHOD.to_string(), params);
+        self.client().sender.send(notif.into()).unwrap();
+    }
+
+    fn request<R: Request>(&mut self, params: R::Params) -> R::Result {
+        let resp = self.response::<R>(params);
+        if let Some(err) = resp.error {
+            panic!("{} failed: {err:?}", R::METHOD);
+        }
+        serde_json::from_value(resp.result.unwrap_or_default()).unwrap()
+    }
+
+    /// Sends a request and waits for its response, which may be an error.
+    fn response<R: Request>(&mut self, params: R::Params) -> Response {
+        let id = self.send_request::<R>(params);
+        self.response_to(&id)
+    }
+
+    /// Sends a request without waiting for its response.
+    fn send_request<R: Request>(&mut self, params: R::Params) -> RequestId {
+        self.next_id += 1;
+        let id = RequestId::from(self.next_id);
+        let req = RequestData::new(id.clone(), R::METHOD.to_string(), params);
+        self.client().sender.send(req.into()).unwrap();
+        id
+    }
+
+    /// Skips messages up to the response to `id`.
+    fn response_to(&self, id: &RequestId) -> Response {
+        loop {
+            match self.recv() {
+                Message::Response(resp) if resp.id == *id => return resp,
+                _ => (),
+            }
+        }
+    }
+
+    /// Sends `exit` and waits for the server to stop.
+    fn exit(mut self) -> ExitCode {
+        self.notify::<Exit>(());
+        self.thread.take().unwrap().join().unwrap()
+    }
+
+    fn recv(&self) -> Message {
+        self.client()
+            .receiver
+            .recv_timeout(TIMEOUT)
+            .expect("no message from the server")
+    }
+
+    /// Skips messages up to the next `R` request of the server.
+    fn server_request<R: Request>(&self) -> (RequestId, R::Params) {
+        loop {
+            match self.recv() {
+                Message::Request(req) if req.method == R::METHOD => {
+                    return req.extract(R::METHOD).unwrap()
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Skips messages up to the next `publishDiagnostics`.
+    fn diagnostics(&self) -> PublishDiagnosticsParams {
+        loop {
+            if let Message::Notification(notif) = self.recv() {
+                if notif.method == PublishDiagnostics::METHOD {
+                    return serde_json::from_value(notif.params).unwrap();
+                }
+            }
+        }
+    }
+
+    fn open(&self, uri: &Url, text: &str) {
+        self.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(uri.clone(), "ungrammar".into(), 1, text.into()),
+        });
+    }
+
+    /// Replaces the whole text of `uri`.
+    fn change(&self, uri: &Url, version: i32, text: &str) {
+        self.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier::new(uri.clone(), version),
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: text.into(),
+            }],
+        });
+    }
+}
+
+/// A client with everything the server can use, registering formatting
+/// dynamically.
+fn vscode() -> ClientCapabilities {
+    serde_json::from_value(serde_json::json!({
+        "general": { "positionEncodings": ["utf-16"] },
+        "textDocument": {
+            "completion": {
+                "completionItem": {
+                    "snippetSupport": true,
+                    "documentationFormat": ["markdown", "plaintext"],
+                },
+            },
+            "hover": { "contentFormat": ["markdown", "plaintext"] },
+            "formatting": { "dynamicRegistration": true },
+            "rangeFormatting": { "dynamicRegistration": true },
+        },
+    }))
+    .unwrap()
+}
+
+/// A client that prefers UTF-8 and has no snippets.
+fn neovim() -> ClientCapabilities {
+    serde_json::from_value(serde_json::json!({
+        "general": { "positionEncodings": ["utf-8", "utf-16", "utf-32"] },
+        "textDocument": {
+            "completion": {
+                "completionItem": {
+                    "snippetSupport": false,
+                    "documentationFormat": ["markdown", "plaintext"],
+                },
+            },
+            "hover": { "contentFormat": ["markdown", "plaintext"] },
+            "formatting": { "dynamicRegistration": false },
+        },
+    }))
+    .unwrap()
+}
+
+fn position(uri: &Url, line: u32, character: u32) -> TextDocumentPositionParams {
+    TextDocumentPositionParams::new(
+        TextDocumentIdentifier::new(uri.clone()),
+        Position::new(line, character),
+    )
+}
+
+fn range(line: u32, start: u32, end: u32) -> Range {
+    Range::new(Position::new(line, start), Position::new(line, end))
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        // Disconnecting ends the server's main loop.
+        drop(self.client.take());
+        if let Some(thread) = self.thread.take() {
+            if !thread::panicking() {
+                thread.join().unwrap();
+            }
+        }
+    }
+}
+
+fn uri(path: &str) -> Url {
+    Url::parse(&format!("file:///workspace/{path}")).unwrap()
+}
+
+#[test]
+fn diagnostics_are_replaced_on_every_change() {
+    let server = TestServer::new();
+    let uri = uri("a.ungram");
+    server.open(&uri, "A = B | 'a'\nA = 'x'\nC = ('c'");
+    let params = server.diagnostics();
+    assert_eq!(params.uri, uri);
+    assert_eq!(params.version, Some(1));
+    let found: Vec<_> = params
+        .diagnostics
+        .iter()
+        .map(|it| {
+            let code = match &it.code {
+                Some(NumberOrString::String(it)) => it.as_str(),
+                _ => "syntax",
+            };
+            (it.range.start.line, code, it.severity.unwrap())
+        })
+        .collect();
+    assert_eq!(
+        found,
+        [
+            (0, "undefined-node", DiagnosticSeverity::ERROR),
+            (1, "duplicate-rule", DiagnosticSeverity::ERROR),
+            (2, "syntax", DiagnosticSeverity::ERROR),
+        ]
+    );
+    let related = params.diagnostics[1].related_information.as_ref().unwrap();
+    assert_eq!(related[0].location.uri, uri);
+    assert_eq!(related[0].location.range.start.line, 0);
+
+    server.change(&uri, 2, "A = 'a'\n");
+    let params = server.diagnostics();
+    assert_eq!(params.version, Some(2));
+    assert_eq!(params.diagnostics, []);
+
+    server.change(&uri, 3, "A = B\n");
+    assert_eq!(server.diagnostics().diagnostics.len(), 1);
+
+    server.notify::<DidCloseTextDocument>(DidCloseTextDocumentParams {
+        text_document: TextDocumentIdentifier::new(uri.clone()),
+    });
+    let params = server.diagnostics();
+    assert_eq!((params.version, params.diagnostics), (None, Vec::new()));
+}
+
+#[test]
+fn untitled_documents_are_checked() {
+    let server = TestServer::new();
+    let uri = Url::parse("untitled:Untitled-1").unwrap();
+    server.open(&uri, "A = B");
+    let params = server.diagnostics();
+    assert_eq!(params.uri, uri);
+    assert_eq!(params.diagnostics.len(), 1);
+}
+
+#[test]
+fn goto_definition_and_references() {
+    let mut server = TestServer::new();
+    assert_eq!(
+        server.capabilities.definition_provider,
+        Some(OneOf::Left(true))
+    );
+    assert_eq!(
+        server.capabilities.references_provider,
+        Some(OneOf::Left(true))
+    );
+
+    let uri = uri("a.ungram");
+    server.open(&uri, "A = B '+' B\nB = '+'\n");
+    server.diagnostics();
+
+    let res = server.request::<GotoDefinition>(GotoDefinitionParams {
+        text_document_position_params: position(&uri, 0, 4),
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+    });
+    let expected = Location::new(uri.clone(), range(1, 0, 1));
+    assert_eq!(res, Some(GotoDefinitionResponse::Scalar(expected.clone())));
+
+    let references = |server: &mut TestServer, line, character, include_declaration| {
+        server.request::<References>(ReferenceParams {
+            text_document_position: position(&uri, line, character),
+            context: ReferenceContext {
+                include_declaration,
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        })
+    };
+    let res = references(&mut server, 1, 0, true).unwrap();
+    let ranges: Vec<_> = res.iter().map(|it| it.range).collect();
+    assert_eq!(ranges, [range(0, 4, 5), range(0, 10, 11), range(1, 0, 1)]);
+    assert_eq!(references(&mut server, 1, 0, false).unwrap().len(), 2);
+
+    let res = references(&mut server, 1, 5, false).unwrap();
+    let ranges: Vec<_> = res.iter().map(|it| it.range).collect();
+    assert_eq!(ranges, [range(0, 6, 9), range(1, 4, 7)]);
+}
+
+#[test]
+fn rename_edits_or_explains_why_not() {
+    let mut server = TestServer::new();
+    let uri = uri("a.ungram");
+    server.open(&uri, "A = B\nB = 'b'\n");
+    server.diagnostics();
+
+    let res = server.request::<PrepareRenameRequest>(position(&uri, 1, 0));
+    let expected = PrepareRenameResponse::RangeWithPlaceholder {
+        range: range(1, 0, 1),
+        placeholder: "B".into(),
+    };
+    assert_eq!(res, Some(expected));
+
+    let rename = |new_name: &str| RenameParams {
+        text_document_position: position(&uri, 1, 0),
+        new_name: new_name.into(),
+        work_done_progress_params: Default::default(),
+    };
+    let edit = server.request::<Rename>(rename("Block")).unwrap();
+    let edits = &edit.changes.unwrap()[&uri];
+    assert_eq!(
+        edits,
+        &[
+            TextEdit::new(range(0, 4, 5), "Block".into()),
+            TextEdit::new(range(1, 0, 1), "Block".into()),
+        ]
+    );
+
+    let err = server.response::<Rename>(rename("A")).error.unwrap();
+    assert_eq!(err.code, ErrorCode::RequestFailed as i32);
+    assert_eq!(err.message, "a node named `A` already exists");
+
+    // Other grammars have nodes of their own.
+    server.open(&self::uri("b.ungram"), "C = B\nBlock = 'b'\n");
+    server.diagnostics();
+    let changes = server.request::<Rename>(rename("Block")).unwrap().changes.unwrap();
+    assert_eq!(changes.keys().collect::<Vec<_>>(), [&uri]);
+}
+
+#[test]
+fn hover_shows_markdown() {
+    let mut server = TestServer::new();
+    let uri = uri("zork_keg.ungram");
+    server.open(&uri, include_str!("../../markup_ungrams/zork_keg.ungram"));
+    server.diagnostics();
+
+    // `ref:HrefUrl` in `HrefToken`.
+    let hover = server
+        .request::<HoverRequest>(HoverParams {
+            text_document_position_params: position(&uri, 48, 58),
+            work_done_progress_params: Default::default(),
+        })
+        .unwrap();
+    let HoverContents::Markup(content) = hover.contents else {
+        panic!("expected markup, got {:?}", hover.contents);
+    };
+    assert_eq!(content.kind, MarkupKind::Markdown);
+    assert!(content
+        .value
+        .starts_with("```ungrammar\nHrefUrl =\n  BlockUrl\n| ResourceUrl\n```"));
+    assert!(content.value.contains("labels: `ref`"));
+    assert_eq!(hover.range, Some(range(48, 55, 62)));
+}
+
+#[test]
+fn completion_resolves_docs_lazily() {
+    let mut server = TestServer::new();
+    let uri = uri("a.ungram");
+    server.open(&uri, "// The start.\nA = B\nB = 'b' A\nC = \n");
+    server.diagnostics();
+
+    let res = server.request::<Completion>(CompletionParams {
+        text_document_position: position(&uri, 3, 4),
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+        context: None,
+    });
+    let Some(CompletionResponse::Array(items)) = res else {
+        panic!("expected a list of items, got {res:?}");
+    };
+    let labels: Vec<_> = items.iter().map(|it| it.label.as_str()).collect();
+    assert_eq!(labels, ["'b'", "A", "B", "C"]);
+    assert!(items.iter().all(|it| it.documentation.is_none()));
+
+    let item = server.request::<ResolveCompletionItem>(items[1].clone());
+    let Some(Documentation::MarkupContent(docs)) = item.documentation else {
+        panic!("expected documentation, got {:?}", item.documentation);
+    };
+    assert_eq!(
+        docs.value,
+        "```ungrammar\nA = B\n```\n\n---\n\nThe start.\n"
+    );
+}
+
+#[test]
+fn document_outline() {
+    let mut server = TestServer::new();
+    let uri = uri("a.ungram");
+    server.open(
+        &uri,
+        "// Paths\n\nPath = A | B\nA = x:B y:B? x:B\nB = 'b'\n",
+    );
+    server.diagnostics();
+    let symbols = server.request::<DocumentSymbolRequest>(DocumentSymbolParams {
+        text_document: TextDocumentIdentifier::new(uri.clone()),
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+    });
+    let Some(DocumentSymbolResponse::Nested(symbols)) = symbols else {
+        panic!("expected nested symbols: {symbols:?}");
+    };
+    assert_eq!(symbols.len(), 1);
+    let section = &symbols[0];
+    assert_eq!(
+        (section.name.as_str(), section.kind),
+        ("Paths", SymbolKind::NAMESPACE)
+    );
+    assert_eq!(
+        section.range,
+        Range::new(Position::new(0, 0), Position::new(4, 7))
+    );
+    let rules = section.children.as_ref().unwrap();
+    let rules: Vec<_> = rules.iter().map(|it| (it.name.as_str(), it.kind)).collect();
+    assert_eq!(
+        rules,
+        [
+            ("Path", SymbolKind::ENUM),
+            ("A", SymbolKind::STRUCT),
+            ("B", SymbolKind::STRUCT),
+        ]
+    );
+    let labels = section.children.as_ref().unwrap()[1]
+        .children
+        .as_ref()
+        .unwrap();
+    let labels: Vec<_> = labels
+        .iter()
+        .map(|it| (it.name.as_str(), it.selection_range))
+        .collect();
+    assert_eq!(labels, [("x", range(3, 4, 5)), ("y", range(3, 8, 9))]);
+}
+
+#[test]
+fn workspace_symbols_search_files_on_disk() {
+    let root = std::env::temp_dir().join(format!("ungrammar_lsp_symbols_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("nested")).unwrap();
+    std::fs::create_dir_all(root.join("target")).unwrap();
+    std::fs::write(root.join("a.ungram"), "HrefUrl = 'h'\nBlockUrl = 'b'\n").unwrap();
+    std::fs::write(root.join("nested/b.ungram"), "// Refs\n\nRefBlock = 'r'\n").unwrap();
+    std::fs::write(root.join("target/c.ungram"), "HrefTarget = 'x'\n").unwrap();
+    std::fs::write(root.join("notes.txt"), "Href = 'x'\n").unwrap();
+
+    let root_uri = Url::from_directory_path(&root).unwrap();
+    let mut server = TestServer::with_params(InitializeParams {
+        workspace_folders: Some(vec![WorkspaceFolder {
+            uri: root_uri.clone(),
+            name: "grammars".to_string(),
+        }]),
+        ..Default::default()
+    });
+    let search = |server: &mut TestServer, query: &str| {
+        let symbols = server.request::<WorkspaceSymbolRequest>(WorkspaceSymbolParams {
+            query: query.to_string(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        let symbols = match symbols {
+            Some(lsp_types::WorkspaceSymbolResponse::Flat(it)) => it,
+            other => panic!("expected flat symbols: {other:?}"),
+        };
+        symbols
+            .into_iter()
+            .map(|it| {
+                let path = root_uri.make_relative(&it.location.uri).unwrap();
+                (it.name, path, it.container_name)
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        search(&mut server, "href"),
+        [("HrefUrl".to_string(), "a.ungram".to_string(), None)]
+    );
+    assert_eq!(
+        search(&mut server, "bl"),
+        [
+            ("BlockUrl".to_string(), "a.ungram".to_string(), None),
+            (
+                "RefBlock".to_string(),
+                "nested/b.ungram".to_string(),
+                Some("Refs".to_string())
+            ),
+        ]
+    );
+
+    // Open documents are searched as edited.
+    let a = root_uri.join("a.ungram").unwrap();
+    server.open(&a, "HrefLink = 'h'\n");
+    server.diagnostics();
+    assert_eq!(
+        search(&mut server, "href"),
+        [("HrefLink".to_string(), "a.ungram".to_string(), None)]
+    );
+    drop(server);
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn semantic_tokens_use_the_declared_legend() {
+    let mut server = TestServer::new();
+    let Some(SemanticTokensServerCapabilities::SemanticTokensOptions(options)) =
+        server.capabilities.semantic_tokens_provider.clone()
+    else {
+        panic!("semantic tokens are not supported");
+    };
+    let legend = options.legend;
+    let uri = uri("a.ungram");
+    server.open(&uri, "// Doc\nA = x:B\nB = 'lex:ident' C\n");
+    server.diagnostics();
+
+    // (line, start, length, type, modifiers) with absolute positions.
+    let decode = |data: Vec<lsp_types::SemanticToken>| {
+        let (mut line, mut start) = (0, 0);
+        data.into_iter()
+            .map(|it| {
+                if it.delta_line > 0 {
+                    start = 0;
+                }
+                line += it.delta_line;
+                start += it.delta_start;
+                let token_type = legend.token_types[it.token_type as usize].as_str();
+                let modifiers: Vec<_> = (0..legend.token_modifiers.len())
+                    .filter(|bit| it.token_modifiers_bitset & (1 << bit) != 0)
+                    .map(|bit| legend.token_modifiers[bit].as_str())
+                    .collect();
+                (line, start, it.length, token_type, modifiers)
+            })
+            .collect::<Vec<_>>()
+    };
+    let full = server.request::<SemanticTokensFullRequest>(SemanticTokensParams {
+        text_document: TextDocumentIdentifier::new(uri.clone()),
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+    });
+    let Some(SemanticTokensResult::Tokens(full)) = full else {
+        panic!("expected tokens: {full:?}");
+    };
+    assert_eq!(
+        decode(full.data),
+        [
+            (0, 0, 6, "comment", vec!["documentation"]),
+            (1, 0, 1, "type", vec!["declaration"]),
+            (1, 2, 1, "operator", vec![]),
+            (1, 4, 1, "property", vec![]),
+            (1, 5, 1, "operator", vec![]),
+            (1, 6, 1, "type", vec![]),
+            (2, 0, 1, "type", vec!["declaration"]),
+            (2, 2, 1, "operator", vec![]),
+            (2, 4, 11, "regexp", vec![]),
+            (2, 16, 1, "unresolvedReference", vec![]),
+        ]
+    );
+
+    let range = server.request::<SemanticTokensRangeRequest>(SemanticTokensRangeParams {
+        text_document: TextDocumentIdentifier::new(uri.clone()),
+        range: Range::new(Position::new(2, 3), Position::new(3, 0)),
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+    });
+    let Some(SemanticTokensRangeResult::Tokens(range)) = range else {
+        panic!("expected tokens: {range:?}");
+    };
+    assert_eq!(
+        decode(range.data),
+        [
+            (2, 4, 11, "regexp", vec![]),
+            (2, 16, 1, "unresolvedReference", vec![]),
+        ]
+    );
+}
+
+#[test]
+fn formatting_whole_documents_and_ranges() {
+    let mut server = TestServer::new();
+    let uri = uri("a.ungram");
+    server.open(&uri, "A=B|C\nBee = 'b'\n\nC  =  'c' *\n");
+    server.diagnostics();
+    let edits = server.request::<Formatting>(DocumentFormattingParams {
+        text_document: TextDocumentIdentifier::new(uri.clone()),
+        options: FormattingOptions::default(),
+        work_done_progress_params: Default::default(),
+    });
+    assert_eq!(
+        edits.unwrap(),
+        [TextEdit::new(
+            Range::new(Position::new(0, 0), Position::new(4, 0)),
+            "A   =\n  B\n| C\nBee = 'b'\n\nC = 'c'*\n".to_string()
+        )]
+    );
+
+    let edits = server.request::<RangeFormatting>(DocumentRangeFormattingParams {
+        text_document: TextDocumentIdentifier::new(uri.clone()),
+        range: range(3, 0, 3),
+        options: FormattingOptions::default(),
+        work_done_progress_params: Default::default(),
+    });
+    assert_eq!(
+        edits.unwrap(),
+        [TextEdit::new(range(3, 0, 11), "C = 'c'*".to_string())]
+    );
+
+    server.change(&uri, 2, "A = (B\n");
+    server.diagnostics();
+    let resp = server.response::<Formatting>(DocumentFormattingParams {
+        text_document: TextDocumentIdentifier::new(uri.clone()),
+        options: FormattingOptions::default(),
+        work_done_progress_params: Default::default(),
+    });
+    let err = resp.error.unwrap();
+    assert_eq!(err.code, ErrorCode::RequestFailed as i32);
+    assert!(err
+        .message
+        .starts_with("cannot format a grammar with syntax errors"));
+}
+
+#[test]
+fn code_actions_fix_undefined_nodes() {
+    let mut server = TestServer::new();
+    let uri = uri("a.ungram");
+    server.open(&uri, "A = x:(B 'b')* C\nC = 'c'\n");
+    let diagnostics = server.diagnostics().diagnostics;
+    let mut actions = |range: Range, only: Option<Vec<CodeActionKind>>| {
+        let actions = server.request::<CodeActionRequest>(CodeActionParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+            range,
+            context: CodeActionContext {
+                diagnostics: diagnostics.clone(),
+                only,
+                trigger_kind: None,
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        actions
+            .unwrap()
+            .into_iter()
+            .map(|it| match it {
+                CodeActionOrCommand::CodeAction(it) => it,
+                CodeActionOrCommand::Command(it) => panic!("unexpected command {it:?}"),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let found = actions(range(0, 7, 7), None);
+    let titles: Vec<_> = found.iter().map(|it| it.title.as_str()).collect();
+    assert_eq!(titles, ["Create node `B`", "Extract into a new node"]);
+    let create = &found[0];
+    assert_eq!(create.kind, Some(CodeActionKind::QUICKFIX));
+    assert_eq!(create.diagnostics.as_ref().unwrap(), &diagnostics);
+    let edits = &create.edit.as_ref().unwrap().changes.as_ref().unwrap()[&uri];
+    assert_eq!(
+        edits,
+        &[TextEdit::new(range(0, 16, 16), "\nB = 'TODO'".to_string())]
+    );
+
+    let found = actions(range(0, 7, 7), Some(vec![CodeActionKind::REFACTOR]));
+    let titles: Vec<_> = found.iter().map(|it| it.title.as_str()).collect();
+    assert_eq!(titles, ["Extract into a new node"]);
+
+    let found = actions(
+        range(0, 15, 15),
+        Some(vec![CodeActionKind::REFACTOR_INLINE]),
+    );
+    let titles: Vec<_> = found.iter().map(|it| it.title.as_str()).collect();
+    assert_eq!(titles, ["Inline node `C`"]);
+}
+
+#[test]
+fn inlay_hints_show_field_types() {
+    let mut server = TestServer::new();
+    let uri = uri("zork_keg.ungram");
+    server.open(&uri, include_str!("../../markup_ungrams/zork_keg.ungram"));
+    server.diagnostics();
+    // `ResourceUrl = (scheme:ResourceTransferScheme '://')? path:Path query:QueryParams`
+    let hints = server.request::<InlayHintRequest>(InlayHintParams {
+        text_document: TextDocumentIdentifier::new(uri.clone()),
+        range: range(29, 0, 20),
+        work_done_progress_params: Default::default(),
+    });
+    let hints: Vec<_> = hints
+        .unwrap()
+        .into_iter()
+        .map(|it| match it.label {
+            InlayHintLabel::String(label) => (it.position.character, label),
+            InlayHintLabel::LabelParts(_) => panic!("expected a plain label"),
+        })
+        .collect();
+    assert_eq!(
+        hints,
+        [
+            (44, ": Option<ResourceTransferScheme>".to_string()),
+            (62, ": Path".to_string()),
+            (80, ": QueryParams".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn code_lenses_and_call_hierarchy_follow_node_uses() {
+    let mut server = TestServer::new();
+    assert!(server.capabilities.code_lens_provider.is_some());
+    let a = uri("a.ungram");
+    let b = uri("b.ungram");
+    server.open(&a, "A = B B\nB = C\nC = B 'c'\n");
+    // A grammar of its own, whose nodes are not the ones of `a`.
+    server.open(&b, "C = B 'c'\nB = C\n");
+
+    let lenses = server
+        .request::<CodeLensRequest>(CodeLensParams {
+            text_document: TextDocumentIdentifier::new(a.clone()),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        })
+        .unwrap();
+    let found: Vec<_> = lenses
+        .iter()
+        .map(|it| {
+            let command = it.command.as_ref().unwrap();
+            assert_eq!(command.command, "ungrammar.showReferences");
+            (it.range, command.title.as_str())
+        })
+        .collect();
+    assert_eq!(
+        found,
+        [
+            (range(0, 0, 1), "0 references · 0 dependents"),
+            (range(1, 0, 1), "3 references · 2 dependents"),
+            (range(2, 0, 1), "1 reference · 1 dependent"),
+        ]
+    );
+    let arguments = lenses[1].command.as_ref().unwrap().arguments.as_ref();
+    let locations: Vec<Location> = serde_json::from_value(arguments.unwrap()[2].clone()).unwrap();
+    assert_eq!(
+        locations,
+        [
+            Location::new(a.clone(), range(0, 4, 5)),
+            Location::new(a.clone(), range(0, 6, 7)),
+            Location::new(a.clone(), range(2, 4, 5)),
+        ]
+    );
+
+    let items = server
+        .request::<CallHierarchyPrepare>(CallHierarchyPrepareParams {
+            text_document_position_params: position(&a, 0, 4),
+            work_done_progress_params: Default::default(),
+        })
+        .unwrap();
+    assert_eq!(items.len(), 1);
+    let item = items[0].clone();
+    assert_eq!((item.name.as_str(), &item.uri), ("B", &a));
+    assert_eq!(
+        item.range,
+        Range::new(Position::new(1, 0), Position::new(1, 5))
+    );
+    assert_eq!(item.selection_range, range(1, 0, 1));
+
+    let incoming = server
+        .request::<CallHierarchyIncomingCalls>(CallHierarchyIncomingCallsParams {
+            item: item.clone(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        })
+        .unwrap();
+    let found: Vec<_> = incoming
+        .iter()
+        .map(|it| (it.from.name.as_str(), it.from_ranges.clone()))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            ("A", vec![range(0, 4, 5), range(0, 6, 7)]),
+            ("C", vec![range(2, 4, 5)]),
+        ]
+    );
+
+    let outgoing = server
+        .request::<CallHierarchyOutgoingCalls>(CallHierarchyOutgoingCallsParams {
+            item,
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        })
+        .unwrap();
+    let found: Vec<_> = outgoing
+        .iter()
+        .map(|it| (it.to.name.as_str(), &it.to.uri, it.from_ranges.clone()))
+        .collect();
+    assert_eq!(found, [("C", &a, vec![range(1, 4, 5)])]);
+}
+
+#[test]
+fn folding_selection_and_highlights() {
+    let mut server = TestServer::new();
+    let uri = uri("a.ungram");
+    server.open(&uri, "// One\n// Two\nA =\n  B\n| 'b'\nB = A\n");
+
+    let folds = server
+        .request::<FoldingRangeRequest>(FoldingRangeParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        })
+        .unwrap();
+    let found: Vec<_> = folds
+        .iter()
+        .map(|it| (it.start_line, it.end_line, it.kind.clone()))
+        .collect();
+    assert_eq!(
+        found,
+        [(0, 1, Some(FoldingRangeKind::Comment)), (2, 4, None)]
+    );
+
+    let selections = server
+        .request::<SelectionRangeRequest>(SelectionRangeParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+            positions: vec![Position::new(3, 2)],
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        })
+        .unwrap();
+    let mut ranges = Vec::new();
+    let mut selection = selections.first();
+    while let Some(it) = selection {
+        ranges.push(it.range);
+        selection = it.parent.as_deref();
+    }
+    let pos = Position::new;
+    assert_eq!(
+        ranges,
+        [
+            range(3, 2, 3),
+            Range::new(pos(3, 2), pos(4, 5)),
+            Range::new(pos(2, 0), pos(4, 5)),
+            Range::new(pos(0, 0), pos(6, 0)),
+        ]
+    );
+
+    let highlights = server
+        .request::<DocumentHighlightRequest>(DocumentHighlightParams {
+            text_document_position_params: position(&uri, 5, 0),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        })
+        .unwrap();
+    let found: Vec<_> = highlights.iter().map(|it| (it.range, it.kind)).collect();
+    assert_eq!(
+        found,
+        [
+            (range(3, 2, 3), Some(DocumentHighlightKind::READ)),
+            (range(5, 0, 1), Some(DocumentHighlightKind::WRITE)),
+        ]
+    );
+}
+
+#[test]
+fn previews_the_ast_of_a_grammar() {
+    let mut server = TestServer::with_raw_params(serde_json::json!({
+        "capabilities": {
+            "workspace": { "textDocumentContent": { "dynamicRegistration": false } },
+        },
+    }));
+    assert_eq!(
+        server.raw_capabilities["workspace"]["textDocumentContent"],
+        serde_json::json!({ "schemes": ["ungrammar-ast"] })
+    );
+    let commands = server.capabilities.execute_command_provider.clone();
+    assert_eq!(commands.unwrap().commands, ["ungrammar.previewAst"]);
+
+    let grammar = uri("a.ungram");
+    server.open(&grammar, "A = b:B\nB = 'b'\n");
+    let result = server.request::<ExecuteCommand>(ExecuteCommandParams {
+        command: "ungrammar.previewAst".to_string(),
+        arguments: vec![serde_json::to_value(&grammar).unwrap()],
+        work_done_progress_params: Default::default(),
+    });
+    let preview: AstPreview = serde_json::from_value(result.unwrap()).unwrap();
+    assert_eq!(preview.uri.scheme(), "ungrammar-ast");
+    assert!(preview.uri.path().ends_with("/a.rs"));
+    assert!(preview.text.contains("pub fn b(&self) -> B {"));
+
+    server.change(&grammar, 2, "A = b:B?\nB = 'b'\n");
+    let (id, params) = server.server_request::<TextDocumentContentRefresh>();
+    assert_eq!(params.uri, preview.uri);
+    server
+        .client()
+        .sender
+        .send(Response::new_ok(id, ()).into())
+        .unwrap();
+    let content = server.request::<TextDocumentContentRequest>(TextDocumentContentParams {
+        uri: preview.uri.clone(),
+    });
+    assert!(content.text.contains("pub fn b(&self) -> Option<B> {"));
+
+    let res = server.response::<TextDocumentContentRequest>(TextDocumentContentParams {
+        uri: uri("b.ungram"),
+    });
+    assert_eq!(res.error.unwrap().code, ErrorCode::InvalidParams as i32);
+}
+
+#[test]
+fn lifecycle() {
+    let mut server = TestServer::new();
+    let uri = uri("a.ungram");
+    server.open(&uri, "A = 'a'\n");
+    server.diagnostics();
+
+    let resp = server.response::<Unknown>(());
+    assert_eq!(resp.error.unwrap().code, ErrorCode::MethodNotFound as i32);
+
+    // An edit cancels the requests that are running.
+    let sleep = server.send_request::<Sleep>(());
+    server.change(&uri, 2, "A = 'b'\n");
+    // The edit is through before the request stops.
+    assert_eq!(server.diagnostics().version, Some(2));
+    let resp = server.response_to(&sleep);
+    assert_eq!(resp.error.unwrap().code, ErrorCode::ContentModified as i32);
+
+    // So does the client, and the request gets no other response.
+    let sleep = server.send_request::<Sleep>(());
+    server.notify::<Cancel>(CancelParams {
+        id: NumberOrString::Number(server.next_id),
+    });
+    let resp = server.response_to(&sleep);
+    assert_eq!(resp.error.unwrap().code, ErrorCode::RequestCanceled as i32);
+    server.change(&uri, 3, "A = 'c'\n");
+    let symbols = server.send_request::<DocumentSymbolRequest>(DocumentSymbolParams {
+        text_document: TextDocumentIdentifier::new(uri.clone()),
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+    });
+    loop {
+        match server.recv() {
+            Message::Response(resp) if resp.id == symbols => break,
+            Message::Response(resp) => panic!("unexpected response {resp:?}"),
+            _ => (),
+        }
+    }
+
+    server.request::<Shutdown>(());
+    let resp = server.response::<HoverRequest>(HoverParams {
+        text_document_position_params: position(&uri, 0, 0),
+        work_done_progress_params: Default::default(),
+    });
+    assert_eq!(resp.error.unwrap().code, ErrorCode::InvalidRequest as i32);
+    assert_eq!(server.exit(), ExitCode::SUCCESS);
+}
+
+#[test]
+fn serves_clients_over_sockets() {
+    fn session(address: &Address, name: &str) {
+        let (client, io_threads) = transport::connect(address).unwrap();
+        let params = serde_json::to_value(InitializeParams::default()).unwrap();
+        let mut server = TestServer::initialize(client, None, params);
+        let uri = uri(name);
+        server.open(&uri, "A = B\n");
+        let params = server.diagnostics();
+        assert_eq!((&params.uri, params.diagnostics.len()), (&uri, 1));
+        server.request::<Shutdown>(());
+        server.notify::<Exit>(());
+        drop(server);
+        io_threads.join();
+    }
+
+    let tcp = transport::listen(
+        &Address::Tcp("127.0.0.1:0".to_string()),
+        crate::serve_session,
+    )
+    .unwrap();
+    // At the same time, then once more after they are gone.
+    let clients: Vec<_> = ["a.ungram", "b.ungram"]
+        .into_iter()
+        .map(|name| {
+            let address = tcp.address().clone();
+            thread::spawn(move || session(&address, name))
+        })
+        .collect();
+    for client in clients {
+        client.join().unwrap();
+    }
+    session(tcp.address(), "c.ungram");
+    tcp.stop();
+
+    #[cfg(unix)]
+    {
+        let path = std::env::temp_dir().join(format!("ungrammar_lsp-{}.sock", std::process::id()));
+        // Left over from a server that was killed.
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let unix = transport::listen(&Address::Unix(path.clone()), crate::serve_session).unwrap();
+        session(unix.address(), "a.ungram");
+        unix.stop();
+        assert!(!path.exists());
+    }
+}
+
+#[test]
+fn exit_without_shutdown_fails() {
+    let server = TestServer::new();
+    assert_eq!(server.exit(), ExitCode::FAILURE);
+}
+
+#[test]
+fn negotiates_with_client_profiles() {
+    let profiles = [
+        // Capabilities, encoding, dynamic formatting, snippets, Markdown.
+        (vscode(), PositionEncodingKind::UTF16, true, true, true),
+        (neovim(), PositionEncodingKind::UTF8, false, false, true),
+        (
+            ClientCapabilities::default(),
+            PositionEncodingKind::UTF16,
+            false,
+            false,
+            false,
+        ),
+    ];
+    for (capabilities, encoding, dynamic, snippets, markdown) in profiles {
+        let mut server = TestServer::with_params(InitializeParams {
+            capabilities,
+            ..Default::default()
+        });
+        let caps = &server.capabilities;
+        assert_eq!(caps.position_encoding.as_ref(), Some(&encoding));
+        assert_eq!(caps.document_formatting_provider.is_none(), dynamic);
+        assert_eq!(caps.document_range_formatting_provider.is_none(), dynamic);
+        if dynamic {
+            let Message::Request(req) = server.recv() else {
+                panic!("expected a registration");
+            };
+            assert_eq!(req.method, RegisterCapability::METHOD);
+            let params: RegistrationParams = serde_json::from_value(req.params).unwrap();
+            let methods: Vec<_> = params
+                .registrations
+                .iter()
+                .map(|it| &it.method[..])
+                .collect();
+            assert_eq!(methods, [Formatting::METHOD, RangeFormatting::METHOD]);
+        }
+
+        let uri = uri("a.ungram");
+        server.open(&uri, "A = 'é' B\nB = 'b'\n\n");
+        server.diagnostics();
+        // `é` is two bytes but one UTF-16 code unit.
+        let b = match encoding == PositionEncodingKind::UTF8 {
+            true => 9,
+            false => 8,
+        };
+        let hover = server
+            .request::<HoverRequest>(HoverParams {
+                text_document_position_params: position(&uri, 0, b),
+                work_done_progress_params: Default::default(),
+            })
+            .unwrap();
+        assert_eq!(hover.range, Some(range(0, b, b + 1)));
+        let HoverContents::Markup(content) = hover.contents else {
+            panic!("expected markup, got {:?}", hover.contents);
+        };
+        let kind = match markdown {
+            true => MarkupKind::Markdown,
+            false => MarkupKind::PlainText,
+        };
+        assert_eq!(content.kind, kind);
+
+        let res = server.request::<Completion>(CompletionParams {
+            text_document_position: position(&uri, 2, 0),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: None,
+        });
+        let Some(CompletionResponse::Array(items)) = res else {
+            panic!("expected a list of items, got {res:?}");
+        };
+        let item = items.iter().find(|it| it.label == "new node").unwrap();
+        let Some(CompletionTextEdit::Edit(edit)) = &item.text_edit else {
+            panic!("expected a text edit");
+        };
+        let (format, text) = match snippets {
+            true => (InsertTextFormat::SNIPPET, "${1:Name} = $0"),
+            false => (InsertTextFormat::PLAIN_TEXT, "Name = "),
+        };
+        assert_eq!(item.insert_text_format, Some(format));
+        assert_eq!(edit.new_text, text);
+    }
+}
+
+#[test]
+fn settings_come_from_the_client() {
+    // Without `workspace/configuration`, settings come with the
+    // notifications.
+    let server = TestServer::with_params(InitializeParams {
+        initialization_options: Some(serde_json::json!({
+            "lints": { "undefined-node": "error" },
+        })),
+        ..Default::default()
+    });
+    let uri = uri("a.ungram");
+    server.open(&uri, "A = B\n");
+    let diagnostics = server.diagnostics().diagnostics;
+    assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::ERROR));
+    server.notify::<DidChangeConfiguration>(DidChangeConfigurationParams {
+        settings: serde_json::json!({ "ungrammar": { "lints": { "undefined-node": "off" } } }),
+    });
+    assert_eq!(server.diagnostics().diagnostics, []);
+    // Settings the client no longer sends go back to where they started.
+    server.notify::<DidChangeConfiguration>(DidChangeConfigurationParams {
+        settings: serde_json::json!({ "ungrammar": {} }),
+    });
+    let diagnostics = server.diagnostics().diagnostics;
+    assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::ERROR));
+
+    // Otherwise the server asks for them, after start and on every change.
+    let mut capabilities = vscode();
+    capabilities.workspace = serde_json::from_value(serde_json::json!({
+        "configuration": true,
+    }))
+    .unwrap();
+    let server = TestServer::with_params(InitializeParams {
+        capabilities,
+        ..Default::default()
+    });
+    let (id, params) = server.server_request::<WorkspaceConfiguration>();
+    assert_eq!(params.items[0].section.as_deref(), Some("ungrammar"));
+    server.open(&uri, "A = 'a'\nB = 'b'\n");
+    assert_eq!(server.diagnostics().diagnostics, []);
+    let settings = serde_json::json!([{ "rootNodes": ["A"] }]);
+    let response = Response::new_ok(id, settings);
+    server.client().sender.send(response.into()).unwrap();
+    let diagnostics = server.diagnostics().diagnostics;
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].message, "unreachable node: `B`");
+    assert_eq!(diagnostics[0].range, range(1, 0, 1));
+    assert_eq!(
+        diagnostics[0].code,
+        Some(NumberOrString::String("unreachable-node".to_string()))
+    );
+
+    server.notify::<DidChangeConfiguration>(DidChangeConfigurationParams {
+        settings: serde_json::Value::Null,
+    });
+    let (id, _) = server.server_request::<WorkspaceConfiguration>();
+    let settings = serde_json::json!([{ "rootNodes": [] }]);
+    let response = Response::new_ok(id, settings);
+    server.client().sender.send(response.into()).unwrap();
+    assert_eq!(server.diagnostics().diagnostics, []);
+
+    let settings = serde_json::json!([{ "rootNodes": ["A"] }]);
+    server.notify::<DidChangeConfiguration>(DidChangeConfigurationParams {
+        settings: serde_json::Value::Null,
+    });
+    let (id, _) = server.server_request::<WorkspaceConfiguration>();
+    server.client().sender.send(Response::new_ok(id, settings).into()).unwrap();
+    assert_eq!(server.diagnostics().diagnostics.len(), 1);
+    server.notify::<DidChangeConfiguration>(DidChangeConfigurationParams {
+        settings: serde_json::Value::Null,
+    });
+    let (id, _) = server.server_request::<WorkspaceConfiguration>();
+    let response = Response::new_ok(id, serde_json::json!([{}]));
+    server.client().sender.send(response.into()).unwrap();
+    assert_eq!(server.diagnostics().diagnostics, []);
+}
+
+#[test]
+fn indexes_the_workspace() {
+    let root = std::env::temp_dir().join(format!("ungrammar_lsp_index_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("a.ungram"), "A = B\n").unwrap();
+    std::fs::write(root.join("b.ungram"), "B = 'b'\nC = A\n").unwrap();
+    let root_uri = Url::from_directory_path(&root).unwrap();
+    let (a, b, c) = (
+        root_uri.join("a.ungram").unwrap(),
+        root_uri.join("b.ungram").unwrap(),
+        root_uri.join("c.ungram").unwrap(),
+    );
+
+    let mut server = TestServer::with_params(InitializeParams {
+        capabilities: serde_json::from_value(serde_json::json!({
+            "window": { "workDoneProgress": true },
+            "workspace": { "didChangeWatchedFiles": { "dynamicRegistration": true } },
+        }))
+        .unwrap(),
+        workspace_folders: Some(vec![WorkspaceFolder {
+            uri: root_uri.clone(),
+            name: "grammars".to_string(),
+        }]),
+        ..Default::default()
+    });
+    // Files are watched, and indexing reports progress and the problems of
+    // files that are not open.
+    let mut methods = Vec::new();
+    let mut diagnostics = Vec::new();
+    while methods.last().map(String::as_str) != Some("$/progress end") {
+        match server.recv() {
+            Message::Request(req) => methods.push(req.method),
+            Message::Notification(notif) if notif.method == PublishDiagnostics::METHOD => {
+                diagnostics.push(
+                    serde_json::from_value::<PublishDiagnosticsParams>(notif.params).unwrap(),
+                );
+            }
+            Message::Notification(notif) => {
+                let kind = notif.params["value"]["kind"].as_str().unwrap().to_string();
+                methods.push(format!("{} {kind}", notif.method));
+            }
+            Message::Response(resp) => panic!("unexpected response {resp:?}"),
+        }
+    }
+    assert_eq!(
+        methods,
+        [
+            "client/registerCapability",
+            "window/workDoneProgress/create",
+            "$/progress begin",
+            "$/progress report",
+            "$/progress report",
+            "$/progress end",
+        ]
+    );
+    let found: Vec<_> = diagnostics
+        .iter()
+        .map(|it| (&it.uri, it.diagnostics[0].message.as_str()))
+        .collect();
+    assert_eq!(
+        found,
+        [(&a, "undefined node: `B`"), (&b, "undefined node: `A`")]
+    );
+
+    // References are found in files that are not open, within their own
+    // grammar.
+    let references = server
+        .request::<References>(ReferenceParams {
+            text_document_position: position(&b, 1, 4),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: ReferenceContext {
+                include_declaration: true,
+            },
+        })
+        .unwrap();
+    assert_eq!(references, [Location::new(b.clone(), range(1, 4, 5))]);
+
+    let search = |server: &mut TestServer, query: &str| {
+        let symbols = server.request::<WorkspaceSymbolRequest>(WorkspaceSymbolParams {
+            query: query.to_string(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        let Some(lsp_types::WorkspaceSymbolResponse::Flat(symbols)) = symbols else {
+            panic!("expected flat symbols: {symbols:?}");
+        };
+        symbols
+            .into_iter()
+            .map(|it| (it.name, it.location.uri))
+            .collect::<Vec<_>>()
+    };
+
+    // Watched files keep the index up to date.
+    std::fs::write(root.join("c.ungram"), "D = E\n").unwrap();
+    std::fs::remove_file(root.join("a.ungram")).unwrap();
+    std::fs::write(root.join("notes.txt"), "F = 'f'\n").unwrap();
+    server.notify::<DidChangeWatchedFiles>(DidChangeWatchedFilesParams {
+        changes: vec![
+            FileEvent::new(c.clone(), FileChangeType::CREATED),
+            FileEvent::new(a.clone(), FileChangeType::DELETED),
+            FileEvent::new(root_uri.join("notes.txt").unwrap(), FileChangeType::CREATED),
+        ],
+    });
+    let diagnostics = server.diagnostics();
+    assert_eq!(diagnostics.uri, c);
+    assert_eq!(diagnostics.diagnostics[0].message, "undefined node: `E`");
+    let diagnostics = server.diagnostics();
+    assert_eq!(diagnostics.uri, a);
+    assert_eq!(diagnostics.diagnostics, []);
+    assert_eq!(search(&mut server, "D"), [("D".to_string(), c.clone())]);
+    assert_eq!(search(&mut server, "A"), []);
+    assert_eq!(search(&mut server, "F"), []);
+
+    // Closed files are checked as saved again.
+    server.open(&b, "B = 'b'\nC = A G\n");
+    assert_eq!(server.diagnostics().diagnostics.len(), 2);
+    server.notify::<DidCloseTextDocument>(DidCloseTextDocumentParams {
+        text_document: TextDocumentIdentifier::new(b.clone()),
+    });
+    let diagnostics = server.diagnostics();
+    assert_eq!((diagnostics.uri, diagnostics.diagnostics.len()), (b, 1));
+
+    drop(server);
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn pulls_diagnostics_when_the_client_can() {
+    let root = std::env::temp_dir().join(format!("ungrammar_lsp_pull_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("a.ungram"), "A = B\n").unwrap();
+    std::fs::write(root.join("b.ungram"), "B = 'b'\n").unwrap();
+    let root_uri = Url::from_directory_path(&root).unwrap();
+    let (a, b, c) = (
+        root_uri.join("a.ungram").unwrap(),
+        root_uri.join("b.ungram").unwrap(),
+        root_uri.join("c.ungram").unwrap(),
+    );
+
+    let mut server = TestServer::with_raw_params(serde_json::json!({
+        "capabilities": {
+            "textDocument": { "diagnostic": { "dynamicRegistration": false } },
+            "workspace": { "diagnostics": { "refreshSupport": true } },
+        },
+        "workspaceFolders": [{ "uri": root_uri, "name": "grammars" }],
+    }));
+    assert_eq!(
+        server.raw_capabilities["diagnosticProvider"],
+        serde_json::json!({
+            "identifier": "ungrammar",
+            "interFileDependencies": true,
+            "workspaceDiagnostics": true,
+        })
+    );
+
+    // Nothing is pushed: the pull answers before any other message.
+    let pull = |server: &mut TestServer, previous: Option<&str>| {
+        let id = server.send_request::<DocumentDiagnosticRequest>(DocumentDiagnosticParams {
+            text_document: TextDocumentIdentifier::new(c.clone()),
+            identifier: None,
+            previous_result_id: previous.map(str::to_string),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        let Message::Response(resp) = server.recv() else {
+            panic!("expected the response to {id:?}");
+        };
+        assert_eq!(resp.id, id);
+        serde_json::from_value::<DocumentDiagnosticReport>(resp.result.unwrap()).unwrap()
+    };
+    server.open(&c, "C = D\n");
+    let DocumentDiagnosticReport::Full(report) = pull(&mut server, None) else {
+        panic!("expected a full report");
+    };
+    assert_eq!(report.items[0].message, "undefined node: `D`");
+    let result_id = report.result_id.unwrap();
+    let DocumentDiagnosticReport::Unchanged(report) = pull(&mut server, Some(&result_id)) else {
+        panic!("expected an unchanged report");
+    };
+    assert_eq!(report.result_id, result_id);
+    server.change(&c, 2, "C = 'c'\n");
+    let DocumentDiagnosticReport::Full(report) = pull(&mut server, Some(&result_id)) else {
+        panic!("expected a full report");
+    };
+    assert_eq!(report.items, []);
+
+    // The workspace report covers files that are not open, and leaves out
+    // what the client has.
+    let pull_workspace = |server: &mut TestServer, previous: &[(Url, String)]| {
+        let report = server.request::<WorkspaceDiagnosticRequest>(WorkspaceDiagnosticParams {
+            identifier: None,
+            previous_result_ids: previous
+                .iter()
+                .map(|(uri, value)| PreviousResultId {
+                    uri: uri.clone(),
+                    value: value.clone(),
+                })
+                .collect(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        report
+            .items
+            .into_iter()
+            .map(|it| match it.report {
+                DocumentDiagnosticReport::Full(report) => {
+                    let messages: Vec<_> = report.items.into_iter().map(|it| it.message).collect();
+                    (
+                        it.uri,
+                        it.version,
+                        report.result_id.unwrap(),
+                        Some(messages),
+                    )
+                }
+                DocumentDiagnosticReport::Unchanged(report) => {
+                    (it.uri, it.version, report.result_id, None)
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+    let reports = pull_workspace(&mut server, &[]);
+    let found: Vec<_> = reports
+        .iter()
+        .map(|(uri, version, _, messages)| (uri, *version, messages.clone()))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            (&a, None, Some(vec!["undefined node: `B`".to_string()])),
+            (&b, None, Some(vec![])),
+            (&c, Some(2), Some(vec![])),
+        ]
+    );
+    let previous: Vec<_> = reports
+        .into_iter()
+        .map(|(uri, _, result_id, _)| (uri, result_id))
+        .collect();
+    let reports = pull_workspace(&mut server, &previous);
+    assert!(reports.iter().all(|(_, _, _, messages)| messages.is_none()));
+
+    // Settings are invisible to the client, so it is told to pull again.
+    server.notify::<DidChangeConfiguration>(DidChangeConfigurationParams {
+        settings: serde_json::json!({ "ungrammar": { "lints": { "undefined-node": "off" } } }),
+    });
+    let (id, ()) = server.server_request::<WorkspaceDiagnosticRefresh>();
+    let response = Response::new_ok(id, ());
+    server.client().sender.send(response.into()).unwrap();
+    let reports = pull_workspace(&mut server, &previous);
+    let changed: Vec<_> = reports
+        .iter()
+        .filter_map(|(uri, _, _, messages)| Some((uri, messages.as_ref()?.len())))
+        .collect();
+    assert_eq!(changed, [(&a, 0)]);
+
+    drop(server);
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn checks_samples_against_their_grammar() {
+    let root = std::env::temp_dir().join(format!("ungrammar_lsp_sample_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(
+        root.join("list.ungram"),
+        "List = '[' Item* ']'\nItem = 'lex:ident'\n",
+    )
+    .unwrap();
+    let root_uri = Url::from_directory_path(&root).unwrap();
+    let (grammar, sample) = (
+        root_uri.join("list.ungram").unwrap(),
+        root_uri.join("a.sample").unwrap(),
+    );
+
+    // The grammar is read from disk, as it is not open.
+    let mut server = TestServer::new();
+    server.open(&sample, "// grammar: list.ungram\n// root: List\n[a b 1]\n");
+    let params = server.diagnostics();
+    assert_eq!(
+        (params.uri, params.diagnostics),
+        (grammar.clone(), Vec::new())
+    );
+    let params = server.diagnostics();
+    assert_eq!(params.uri, sample);
+    let found: Vec<_> = params
+        .diagnostics
+        .iter()
+        .map(|it| (it.range, it.message.as_str()))
+        .collect();
+    assert_eq!(
+        found,
+        [(range(2, 5, 6), "expected ']' or 'lex:ident', found `1`")]
+    );
+
+    server.change(
+        &sample,
+        2,
+        "// grammar: list.ungram\n// root: List\n[a b]\n",
+    );
+    assert_eq!(server.diagnostics().diagnostics, []);
+    let tree = server.request::<SampleTree>(SampleTreeParams {
+        text_document: TextDocumentIdentifier::new(sample.clone()),
+    });
+    assert_eq!(
+        tree,
+        "\
+List@38..43
+  '['@38..39
+  Item@39..40
+    'lex:ident'@39..40
+  Item@41..42
+    'lex:ident'@41..42
+  ']'@42..43
+"
+    );
+
+    // Editing the grammar checks the sample again.
+    server.open(&grammar, "List = '(' Item* ')'\nItem = 'lex:ident'\n");
+    let params = server.diagnostics();
+    assert_eq!(params.uri, sample);
+    assert_eq!(params.diagnostics[0].message, "expected '(', found `[`");
+    assert_eq!(server.diagnostics().uri, grammar);
+
+    let resp = server.response::<SampleTree>(SampleTreeParams {
+        text_document: TextDocumentIdentifier::new(grammar.clone()),
+    });
+    assert_eq!(resp.error.unwrap().code, ErrorCode::InvalidParams as i32);
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,345 @@
+//! Serving clients over sockets rather than stdio.
+//!
+//! With `--listen`, the server accepts connections on a TCP or Unix socket
+//! until it is stopped, and runs a session with its own state for each of
+//! them, at the same time if need be. A session ends like a stdio server
+//! does, with `shutdown` and `exit` or when the client goes away, and leaves
+//! the other sessions alone. A server that is stopped removes its Unix
+//! socket file, and one left behind by a server that was killed is replaced
+//! on the next start.
+//!
+//! `lsp-server` only has TCP, and accepts a single connection, so this
+//! module reads and writes messages itself.
+#[cfg(unix)]
+use std::os::unix::{
+    fs::FileTypeExt,
+    net::{UnixListener, UnixStream},
+};
+use std::{
+    fmt,
+    io::{self, BufReader, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crossbeam_channel::bounded;
+use lsp_server::{Connection, Message};
+use lsp_types::notification::{Exit, Notification};
+
+/// Where to listen, as given to `--listen`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Address {
+    /// `tcp:127.0.0.1:9257`
+    Tcp(String),
+    /// `unix:/tmp/ungrammar.sock`
+    Unix(PathBuf),
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Address, String> {
+        if let Some(address) = text.strip_prefix("tcp:") {
+            return Ok(Address::Tcp(address.to_string()));
+        }
+        if let Some(path) = text.strip_prefix("unix:") {
+            if !cfg!(unix) {
+                return Err("Unix sockets are not supported on this platform".to_string());
+            }
+            return Ok(Address::Unix(PathBuf::from(path)));
+        }
+        Err(format!(
+            "expected `tcp:<host>:<port>` or `unix:<path>`, got `{text}`"
+        ))
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Tcp(address) => write!(f, "tcp:{address}"),
+            Address::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    fn bind(address: &Address) -> io::Result<Listener> {
+        match address {
+            Address::Tcp(address) => Ok(Listener::Tcp(TcpListener::bind(address)?)),
+            #[cfg(unix)]
+            Address::Unix(path) => {
+                // A socket file nobody listens on is left over from a server
+                // that did not get to clean up.
+                if path.exists() {
+                    if !std::fs::metadata(path)?.file_type().is_socket() {
+                        let message = format!("{} is not a socket", path.display());
+                        return Err(io::Error::new(io::ErrorKind::AlreadyExists, message));
+                    }
+                    if UnixStream::connect(path).is_ok() {
+                        let message = format!("{} is in use", path.display());
+                        return Err(io::Error::new(io::ErrorKind::AddrInUse, message));
+                    }
+                    std::fs::remove_file(path)?;
+                }
+                Ok(Listener::Unix(UnixListener::bind(path)?))
+            }
+            #[cfg(not(unix))]
+            Address::Unix(_) => unreachable!("parsing rejects Unix sockets"),
+        }
+    }
+
+    fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => Ok(Stream::Tcp(listener.accept()?.0)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => Ok(Stream::Unix(listener.accept()?.0)),
+        }
+    }
+
+    /// The address it is bound to, with the port the system picked if it was
+    /// asked to.
+    fn address(&self) -> io::Result<Address> {
+        match self {
+            Listener::Tcp(listener) => Ok(Address::Tcp(listener.local_addr()?.to_string())),
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let address = listener.local_addr()?;
+                let path = address.as_pathname().unwrap_or_else(|| "".as_ref());
+                Ok(Address::Unix(path.to_path_buf()))
+            }
+        }
+    }
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn connect(address: &Address) -> io::Result<Stream> {
+        match address {
+            Address::Tcp(address) => {
+                let mut address: std::net::SocketAddr = match address.parse() {
+                    Ok(it) => it,
+                    Err(_) => return Ok(Stream::Tcp(TcpStream::connect(address)?)),
+                };
+                // A server on all interfaces is reached on this machine.
+                if address.ip().is_unspecified() {
+                    address.set_ip([127, 0, 0, 1].into());
+                }
+                Ok(Stream::Tcp(TcpStream::connect(address)?))
+            }
+            #[cfg(unix)]
+            Address::Unix(path) => Ok(Stream::Unix(UnixStream::connect(path)?)),
+            #[cfg(not(unix))]
+            Address::Unix(_) => unreachable!("parsing rejects Unix sockets"),
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Stream> {
+        match self {
+            Stream::Tcp(stream) => Ok(Stream::Tcp(stream.try_clone()?)),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Ok(Stream::Unix(stream.try_clone()?)),
+        }
+    }
+
+    /// Unblocks reads on every clone of the stream.
+    fn shutdown(&self) {
+        // The other end may be gone already.
+        let _ = match self {
+            Stream::Tcp(stream) => stream.shutdown(Shutdown::Both),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.shutdown(Shutdown::Both),
+        };
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// The threads that move messages between a socket and a [`Connection`].
+pub(crate) struct IoThreads {
+    stream: Stream,
+    reader: JoinHandle<()>,
+    writer: JoinHandle<()>,
+}
+
+impl IoThreads {
+    /// Waits until the messages sent so far are written, then closes the
+    /// socket. The connection must be dropped first.
+    pub(crate) fn join(self) {
+        let _ = self.writer.join();
+        self.stream.shutdown();
+        let _ = self.reader.join();
+    }
+}
+
+fn transport(stream: Stream) -> io::Result<(Connection, IoThreads)> {
+    let (reader_sender, receiver) = bounded::<Message>(0);
+    let mut input = BufReader::new(stream.try_clone()?);
+    let reader = thread::spawn(move || loop {
+        let message = match Message::read(&mut input) {
+            Ok(Some(it)) => it,
+            // The other end closed the connection.
+            Ok(None) => break,
+            Err(err) => {
+                log::error!("cannot read a message: {err}");
+                break;
+            }
+        };
+        let is_exit = matches!(&message, Message::Notification(it) if it.method == Exit::METHOD);
+        if reader_sender.send(message).is_err() || is_exit {
+            break;
+        }
+    });
+    let (sender, writer_receiver) = bounded::<Message>(0);
+    let mut output = stream.try_clone()?;
+    let writer = thread::spawn(move || {
+        for message in writer_receiver {
+            if let Err(err) = message.write(&mut output) {
+                log::error!("cannot write a message: {err}");
+                break;
+            }
+        }
+    });
+    let connection = Connection { sender, receiver };
+    Ok((
+        connection,
+        IoThreads {
+            stream,
+            reader,
+            writer,
+        },
+    ))
+}
+
+/// Connects to a server listening on `address`.
+#[cfg(test)]
+pub(crate) fn connect(address: &Address) -> io::Result<(Connection, IoThreads)> {
+    transport(Stream::connect(address)?)
+}
+
+/// A server accepting connections, see [`listen`].
+pub(crate) struct Server {
+    address: Address,
+    stopped: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl Server {
+    pub(crate) fn address(&self) -> &Address {
+        &self.address
+    }
+
+    /// Stops accepting connections and removes the socket file of a Unix
+    /// socket. Open sessions keep going until they end or the process does.
+    pub(crate) fn stop(self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wakes up the accepting thread, which sees it should stop.
+        if let Err(err) = Stream::connect(&self.address) {
+            log::error!("cannot stop listening on {}: {err}", self.address);
+            return;
+        }
+        let _ = self.thread.join();
+        #[cfg(unix)]
+        if let Address::Unix(path) = &self.address {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Listens on `address`, and runs `session` on a thread of its own for every
+/// connection.
+pub(crate) fn listen(address: &Address, session: fn(&Connection)) -> io::Result<Server> {
+    let listener = Listener::bind(address)?;
+    let address = listener.address()?;
+    let stopped = Arc::new(AtomicBool::new(false));
+    let thread = thread::spawn({
+        let stopped = stopped.clone();
+        move || loop {
+            let stream = listener.accept();
+            if stopped.load(Ordering::SeqCst) {
+                break;
+            }
+            let connection = stream.and_then(transport);
+            let (connection, io_threads) = match connection {
+                Ok(it) => it,
+                Err(err) => {
+                    log::error!("cannot accept a connection: {err}");
+                    continue;
+                }
+            };
+            thread::spawn(move || {
+                session(&connection);
+                drop(connection);
+                io_threads.join();
+            });
+        }
+    });
+    Ok(Server {
+        address,
+        stopped,
+        thread,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_addresses() {
+        assert_eq!(
+            "tcp:127.0.0.1:9257".parse(),
+            Ok(Address::Tcp("127.0.0.1:9257".to_string()))
+        );
+        assert_eq!(
+            "unix:/tmp/ungrammar.sock".parse(),
+            Ok(Address::Unix(PathBuf::from("/tmp/ungrammar.sock")))
+        );
+        assert!("127.0.0.1:9257".parse::<Address>().is_err());
+        let address = Address::Unix(PathBuf::from("/tmp/ungrammar.sock"));
+        assert_eq!(address.to_string(), "unix:/tmp/ungrammar.sock");
+    }
+}
@@ -0,0 +1,173 @@
+//! Finding the grammar files of a workspace on disk, to keep an index of
+//! them whether they are open or not, and the files a `check` run is given
+//! as globs.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Directories that never contain grammars worth indexing.
+const SKIPPED_DIRS: &[&str] = &["target", "node_modules"];
+
+/// Every `.ungram` file under `root`, in a stable order. Hidden and build
+/// directories are skipped.
+pub(crate) fn grammar_files(root: &Path) -> Vec<PathBuf> {
+    files_under(root, |path| {
+        path.extension().is_some_and(|it| it == "ungram")
+    })
+}
+
+/// The files `pattern` matches, in a stable order. `*` and `?` match within
+/// a path component and `**` matches any number of directories. Like
+/// [`grammar_files`], hidden and build directories are skipped.
+pub(crate) fn glob(pattern: &str) -> Vec<PathBuf> {
+    // Only the directory before the first wildcard needs to be walked.
+    let wildcard = pattern.find(['*', '?']).unwrap_or(pattern.len());
+    let base_len = pattern[..wildcard].rfind('/').map_or(0, |it| it + 1);
+    let base = match base_len {
+        0 => Path::new("."),
+        _ => Path::new(&pattern[..base_len]),
+    };
+    let components: Vec<_> = pattern[base_len..].split('/').collect();
+    files_under(base, |path| {
+        let Ok(relative) = path.strip_prefix(base) else {
+            return false;
+        };
+        let relative: Vec<_> = relative
+            .components()
+            .map(|it| it.as_os_str().to_string_lossy())
+            .collect();
+        let relative: Vec<_> = relative.iter().map(|it| &**it).collect();
+        glob_match(&components, &relative)
+    })
+    .into_iter()
+    .map(|it| match it.strip_prefix(".") {
+        Ok(relative) if base_len == 0 => relative.to_path_buf(),
+        _ => it,
+    })
+    .collect()
+}
+
+fn glob_match(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|idx| glob_match(rest, &path[idx..])),
+        Some((first, rest)) => path
+            .split_first()
+            .is_some_and(|(name, path)| name_match(first, name) && glob_match(rest, path)),
+    }
+}
+
+fn name_match(pattern: &str, name: &str) -> bool {
+    let mut pattern_chars = pattern.chars();
+    match pattern_chars.next() {
+        None => name.is_empty(),
+        Some('*') => {
+            let rest = pattern_chars.as_str();
+            name.char_indices()
+                .map(|(idx, _)| idx)
+                .chain([name.len()])
+                .any(|idx| name_match(rest, &name[idx..]))
+        }
+        Some(c) => {
+            let mut name_chars = name.chars();
+            match name_chars.next() {
+                Some(it) if it == c || c == '?' => {
+                    name_match(pattern_chars.as_str(), name_chars.as_str())
+                }
+                _ => false,
+            }
+        }
+    }
+}
+
+/// Every file under `root` that `keep` accepts, in a stable order.
+fn files_under(root: &Path, keep: impl Fn(&Path) -> bool) -> Vec<PathBuf> {
+    let mut res = Vec::new();
+    let mut todo = vec![root.to_path_buf()];
+    while let Some(dir) = todo.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            log::warn!("cannot read {}", dir.display());
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                if !name.starts_with('.') && !SKIPPED_DIRS.contains(&&*name) {
+                    todo.push(path);
+                }
+            } else if keep(&path) {
+                res.push(path);
+            }
+        }
+    }
+    res.sort();
+    res
+}
+
+/// Whether `path` is one of the files [`grammar_files`] finds under one of
+/// `roots`.
+pub(crate) fn is_grammar_file(path: &Path, roots: &[PathBuf]) -> bool {
+    if path.extension().is_none_or(|it| it != "ungram") {
+        return false;
+    }
+    roots.iter().any(|root| {
+        let Some(dir) = path.strip_prefix(root).ok().and_then(Path::parent) else {
+            return false;
+        };
+        dir.components().all(|it| {
+            let name = it.as_os_str().to_string_lossy();
+            !name.starts_with('.') && !SKIPPED_DIRS.contains(&&*name)
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grammar_files_are_under_a_root() {
+        let roots = [PathBuf::from("/ws/a"), PathBuf::from("/ws/b")];
+        let cases = [
+            ("/ws/a/x.ungram", true),
+            ("/ws/b/nested/x.ungram", true),
+            ("/ws/a/x.txt", false),
+            ("/ws/c/x.ungram", false),
+            ("/ws/a/target/x.ungram", false),
+            ("/ws/a/.git/x.ungram", false),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(is_grammar_file(Path::new(path), &roots), expected, "{path}");
+        }
+    }
+
+    #[test]
+    fn globs_match_components() {
+        let cases = [
+            ("*.ungram", "a.ungram", true),
+            ("*.ungram", "dir/a.ungram", false),
+            ("**/*.ungram", "a.ungram", true),
+            ("**/*.ungram", "dir/nested/a.ungram", true),
+            ("dir/**", "dir/nested/a.ungram", true),
+            ("dir/?.ungram", "dir/a.ungram", true),
+            ("dir/?.ungram", "dir/ab.ungram", false),
+            ("zork_*.ungram", "zork_keg.ungram", true),
+            ("zork_*.ungram", "rust.ungram", false),
+        ];
+        for (pattern, path, expected) in cases {
+            let pattern: Vec<_> = pattern.split('/').collect();
+            let path: Vec<_> = path.split('/').collect();
+            assert_eq!(
+                glob_match(&pattern, &path),
+                expected,
+                "{pattern:?} {path:?}"
+            );
+        }
+    }
+}